@@ -108,7 +108,7 @@ public class LoginActivity extends AppCompatActivity {
                     int output = login(mail, pass);
                     // Rust's backend function will return one of many defined status codes.
                     switch (output) {
-                        case 0:
+                        case SugarError.OK:
                             // Note about signup success.
                             Toast.makeText(
                                     LoginActivity.this,
@@ -121,23 +121,23 @@ public class LoginActivity extends AppCompatActivity {
                             i.putExtra("mail", mail);
                             startActivity(i);
                             break;
-                        case 10:
+                        case SugarError.INVALID_EMAIL:
                             mailErrorText.setText(R.string.error_mail_regex);
                             mailErrorText.setVisibility(View.VISIBLE);
                             break;
-                        case 11:
+                        case SugarError.INVALID_PASS:
                             passwordErrorText.setText(R.string.error_pass_wrong);
                             passwordErrorText.setVisibility(View.VISIBLE);
                             break;
-                        case 30:
+                        case SugarError.INVALID_LOGIN_CREDENTIALS:
                             passwordErrorText.setText(R.string.error_invalid_credentials);
                             passwordErrorText.setVisibility(View.VISIBLE);
                             break;
-                        case 37:
+                        case SugarError.EMAIL_NOT_FOUND:
                             mailErrorText.setText(R.string.error_not_found);
                             mailErrorText.setVisibility(View.VISIBLE);
                             break;
-                        case 31:
+                        case SugarError.USER_DISABLED:
                             Toast.makeText(
                                     LoginActivity.this,
                                     "Your 'Sugar' account was disabled due to strange activity from this device. Please contact administration for further support.",
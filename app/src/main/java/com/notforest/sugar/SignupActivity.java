@@ -69,7 +69,7 @@ public class SignupActivity extends AppCompatActivity {
                         int output = signUp(mail, pass, conf);
                         // Rust's backend function will return one of many defined status codes.
                         switch (output) {
-                            case 0:
+                            case SugarError.OK:
                                 // Note about signup success.
                                 Toast.makeText(
                                         SignupActivity.this,
@@ -80,19 +80,19 @@ public class SignupActivity extends AppCompatActivity {
                                 // Jumping to login activity
                                 startActivity(new Intent(SignupActivity.this, LoginActivity.class));
                                 break;
-                            case 10:
+                            case SugarError.INVALID_EMAIL:
                                 mailErrorText.setText(R.string.error_mail_regex);
                                 mailErrorText.setVisibility(View.VISIBLE);
                                 break;
-                            case 11:
+                            case SugarError.WEAK_PASS:
                                 passwordErrorText.setText(R.string.error_pass_weak);
                                 passwordErrorText.setVisibility(View.VISIBLE);
                                 break;
-                            case 20:
+                            case SugarError.EMAIL_EXISTS:
                                 mailErrorText.setText(R.string.error_mail_used);
                                 mailErrorText.setVisibility(View.VISIBLE);
                                 break;
-                            case 21:
+                            case SugarError.TOO_MANY_ATTEMPTS:
                                 Toast.makeText(
                                         SignupActivity.this,
                                         "Your 'Sugar' account was disabled due to strange activity from this device. Please contact administration for further support.",
@@ -0,0 +1,11 @@
+/*
+ *  Exception thrown by native calls related to user's authentication.
+ * */
+
+package com.notforest.sugar;
+
+public class SugarAuthException extends SugarException {
+    public SugarAuthException(final String message) {
+        super(message);
+    }
+}
@@ -0,0 +1,11 @@
+/*
+ *  Exception thrown by native calls related to the connection with the target.
+ * */
+
+package com.notforest.sugar;
+
+public class SugarBridgeException extends SugarException {
+    public SugarBridgeException(final String message) {
+        super(message);
+    }
+}
@@ -0,0 +1,30 @@
+/*
+ *  Management of the cache directories handled by the backend.
+ * */
+
+package com.notforest.sugar;
+
+public class SugarCache {
+    // Locations of cached items.
+    public static final int INTERNAL = 0;
+    public static final int EXTERNAL = 1;
+
+    // Indices within the array returned by usage().
+    public static final int USAGE_INTERNAL = 0;
+    public static final int USAGE_EXTERNAL = 1;
+    public static final int USAGE_PINNED = 2;
+    public static final int USAGE_ITEMS = 3;
+    public static final int USAGE_BUDGET = 4;
+
+    // Native JNI interface for Rust backend.
+    /* Returns the space taken by the cache in bytes, indexed by USAGE_* constants. */
+    public static native long[] usage();
+    /* Removes cached items. Returns the status code. */
+    public static native int clear(final boolean keepPinned);
+    /* Changes the size budget and evicts items, which do not fit anymore. Returns the status code. */
+    public static native int setBudget(final long bytes);
+    /* Protects the item from the eviction, e.g. when it is bookmarked. Returns the status code. */
+    public static native int pin(final int location, final String name);
+    /* Allows the item to be evicted again. Returns the status code. */
+    public static native int unpin(final int location, final String name);
+}
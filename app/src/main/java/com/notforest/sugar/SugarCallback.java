@@ -0,0 +1,48 @@
+/*
+ *  Callback interface for events pushed from Rust's backend.
+ *
+ *  All methods are called from a single background thread owned by the backend, so implementations must
+ *  move any UI related work to the main thread themselves.
+ * */
+
+package com.notforest.sugar;
+
+public interface SugarCallback {
+    /* States of the bridge passed to onStateChanged. */
+    int STATE_IDLE = 0;
+    int STATE_OPENING = 1;
+    int STATE_HANDSHAKING = 2;
+    int STATE_CONNECTED = 3;
+    int STATE_DRAINING = 4;
+    int STATE_CLOSED = 5;
+    int STATE_FAILED = 6;
+
+    /* Connection has been established or closed. A closed connection carries the error code and message, or
+     * SugarError.OK and null if it was closed properly. */
+    void onConnectionChanged(boolean connected, int error, String message);
+
+    /* Bridge has moved to another state, one of STATE_* constants. */
+    void onStateChanged(int state);
+
+    /* Connection has failed with the error and is established once more after the delay. Attempts are counted
+     * from 1. */
+    void onReconnecting(int attempt, long delayMs, int error, String message);
+
+    /* Disks of the connected target. */
+    void onDisks(String[] disks);
+
+    /* Partitions of the disk. */
+    void onPartitions(String disk, String[] partitions);
+
+    /* Files and directories in the root of the partition. Directories are marked in the second array. */
+    void onFiles(String disk, String partition, String[] entries, boolean[] directories);
+
+    /* Amount of bytes of the file transferred so far. */
+    void onProgress(String disk, String partition, String path, long done, boolean finished);
+
+    /* Error, which occurred in the background. The code is one of SugarError codes. */
+    void onError(int code, String message);
+
+    /* Log line of the backend. Levels go from 1 (error) to 5 (trace). */
+    void onLog(int level, String message);
+}
@@ -0,0 +1,91 @@
+/*
+ *  Status codes returned by Rust's backend.
+ *
+ *  Each code is unique across all failure domains and never changes. Codes are grouped by hundreds,
+ *  one hundred per domain, so the domain of any code can be obtained with domain(). Zero means success.
+ * */
+
+package com.notforest.sugar;
+
+public final class SugarError {
+    public static final int OK = 0;
+
+    // Failure domains.
+    public static final int DOMAIN_INTERNAL = 1;
+    public static final int DOMAIN_STORAGE = 2;
+    public static final int DOMAIN_AUTH = 3;
+    public static final int DOMAIN_BRIDGE = 4;
+    public static final int DOMAIN_CLIENT = 5;
+
+    // Internal application errors.
+    public static final int TOKIO_THREAD_ERROR = 101;
+    public static final int NETWORK_ERROR = 102;
+    public static final int FFI_ERROR = 103;
+
+    // Storage errors.
+    public static final int FILE_NOT_EXIST = 201;
+    public static final int TIME_OUT = 202;
+    public static final int BAD_DATA = 203;
+    public static final int NO_DATA = 204;
+    public static final int SERIALIZATION_ERROR = 205;
+    public static final int OUT_OF_MEMORY = 206;
+    public static final int INTERRUPTED = 207;
+    public static final int IO_ERROR = 208;
+    public static final int PERMISSION_DENIED = 209;
+    public static final int ALREADY_EXISTS = 210;
+    public static final int STORAGE_FULL = 211;
+    public static final int NOT_INITIALIZED = 212;
+    public static final int INVALID_KEY = 213;
+    public static final int UNSUPPORTED_SCHEMA = 214;
+    public static final int DECRYPTION_FAILED = 215;
+    public static final int KEY_UNAVAILABLE = 216;
+    public static final int DATABASE_ERROR = 217;
+
+    // Authentication errors.
+    public static final int INVALID_EMAIL = 301;
+    public static final int INVALID_PASS = 302;
+    public static final int WEAK_PASS = 303;
+    public static final int EMAIL_EXISTS = 304;
+    public static final int TOO_MANY_ATTEMPTS = 305;
+    public static final int INVALID_LOGIN_CREDENTIALS = 306;
+    public static final int USER_DISABLED = 307;
+    public static final int TOKEN_EXPIRED = 308;
+    public static final int TOKEN_NOT_VALID = 309;
+    public static final int MALFORMED_TOKEN_HEADER = 310;
+    public static final int KEY_PARSING_ERROR = 311;
+    public static final int NO_MATCH_DECODING_KEY = 312;
+    public static final int EMAIL_NOT_FOUND = 313;
+    public static final int INVALID_USER_ID = 314;
+    public static final int AUTH_UNKNOWN = 399;
+
+    // Bridge errors.
+    public static final int BRIDGE_NOT_READY = 401;
+    public static final int CONNECTION_REFUSED = 402;
+    public static final int CONNECTION_TIMEOUT = 403;
+    public static final int BRIDGE_CLOSED = 404;
+    public static final int CONTEXT_ERROR = 405;
+    public static final int FILE_DESCRIPTOR_ERROR = 406;
+    public static final int RETRY_LIMIT_EXCEEDED = 407;
+    public static final int TRANSFER_ERROR = 408;
+    public static final int INCOMPATIBLE_TARGET = 409;
+    public static final int ALREADY_CONNECTED = 410;
+    public static final int NOT_CONNECTED = 411;
+    public static final int NO_SUITABLE_INTERFACE = 412;
+    public static final int INTERFACE_CLAIM_ERROR = 413;
+    public static final int DEVICE_LOST = 414;
+    public static final int DATA_TOO_LARGE = 415;
+
+    // Client request errors.
+    public static final int REQUEST_REFUSED = 501;
+    public static final int REQUEST_BRIDGE_CLOSED = 502;
+    public static final int REQUEST_TIMEOUT = 503;
+    public static final int REQUEST_BAD_DATA = 504;
+    public static final int REQUEST_TOO_LARGE = 505;
+
+    private SugarError() {}
+
+    /* Returns the failure domain of the code, or zero for success. */
+    public static int domain(final int code) {
+        return code / 100;
+    }
+}
@@ -0,0 +1,14 @@
+/*
+ *  Exception thrown by Rust's backend, when a native call has failed.
+ *
+ *  The message carries the error reported by the backend. It is unchecked, since any native call may
+ *  throw it, e.g. when the backend has panicked.
+ * */
+
+package com.notforest.sugar;
+
+public class SugarException extends RuntimeException {
+    public SugarException(final String message) {
+        super(message);
+    }
+}
@@ -13,4 +13,20 @@ public class SugarInit {
     // Native JNI interface for Rust backend.
     /* Initialized all important tasks on Rust's backend side. */
     public static native void rustInit(final String filesDir, final String cacheDir, final String extFilesDir, final String extCacheDir);
+    /* Registers the object, which will obtain all events from the backend. */
+    public static native void registerCallback(final SugarCallback callback);
+    /* Removes the registered callback object. */
+    public static native void unregisterCallback();
+    /* Changes the log level of the backend: 0 - off, 1 - error, 2 - warn, 3 - info, 4 - debug, 5 - trace. */
+    public static native void setLogLevel(final int level);
+    /* Changes the reconnection after a failed connection, applied from the next connection. 0 attempts disable it. */
+    public static native void setReconnectPolicy(final int maxAttempts, final long initialDelayMs, final long maxDelayMs);
+    /* Writes a zip for a bug report to the external files directory. Returns its path or null. */
+    public static native String exportDiagnostics(final String appVersion);
+    /* Returns pending crash reports as JSON objects, the oldest first. */
+    public static native String[] crashReports();
+    /* Deletes the crash report by its "id" field. Returns 0 on success or an error code. */
+    public static native int deleteCrashReport(final String id);
+    /* Deletes all pending crash reports. Returns 0 on success or an error code. */
+    public static native int deleteCrashReports();
 }
@@ -49,6 +49,9 @@ import androidx.fragment.app.Fragment;
 
 import com.notforest.sugar.MainActivity;
 import com.notforest.sugar.R;
+import com.notforest.sugar.SugarCallback;
+import com.notforest.sugar.SugarError;
+import com.notforest.sugar.SugarInit;
 
 import java.util.ArrayList;
 import java.util.Arrays;
@@ -66,6 +69,10 @@ public class TargetFragment extends Fragment {
     private static native int connect(int fd);
     private static native int disconnect();
     private static native String conn_info();
+    private static native int listDisks();
+    private static native int listPartitions(String disk);
+    private static native int listFiles(String disk, String partition);
+    private static native int download(String disk, String partition, String path, String dest);
 
     private static final String ACTION_USB_PERMISSION = "com.notforest.sugar.USB_PERMISSION";
 
@@ -100,10 +107,11 @@ public class TargetFragment extends Fragment {
                             UsbDeviceConnection usbDeviceConnection = usbManager.openDevice(device);
                             int fileDescriptor = usbDeviceConnection.getFileDescriptor();
                             switch (connect(fileDescriptor)) {
-                                case 0:
+                                case SugarError.OK:
                                     POWER = true;
                                     sharedPreferences.edit().putBoolean("power_" + machineNameTextView.getText(), false).apply();
-                                    displayMessage("info:" + getString(R.string.connected_to_device) + conn_info());
+                                    // The handshake is still ahead, onConnectionChanged reports its result.
+                                    displayMessage("info: " + getString(R.string.connecting_to_device) + device.getDeviceName());
                                     break;
                                 default:
                                     displayMessage("error: " + getString(R.string.error_unknown_error));
@@ -221,17 +229,99 @@ public class TargetFragment extends Fragment {
         return root;
     }
 
+    /* Events from the backend come from a background thread, so all of them are moved to the UI thread. */
+    private final SugarCallback sugarCallback = new SugarCallback() {
+        @Override
+        public void onConnectionChanged(boolean connected, int error, String message) {
+            runOnUi(() -> {
+                if (connected) {
+                    displayMessage("info: " + getString(R.string.connected_to_device) + conn_info());
+                } else if (error != SugarError.OK) {
+                    displayMessage("error: " + message);
+                }
+            });
+        }
+
+        @Override
+        public void onDisks(String[] disks) {
+            postMessage("info: " + String.join(", ", disks));
+        }
+
+        @Override
+        public void onPartitions(String disk, String[] partitions) {
+            postMessage("info: " + disk + ": " + String.join(", ", partitions));
+        }
+
+        @Override
+        public void onFiles(String disk, String partition, String[] entries, boolean[] directories) {
+            StringBuilder builder = new StringBuilder(disk + "/" + partition + ":");
+            for (int i = 0; i < entries.length; i++) {
+                builder.append(' ').append(entries[i]).append(directories[i] ? "/" : "");
+            }
+            postMessage("info: " + builder);
+        }
+
+        @Override
+        public void onProgress(String disk, String partition, String path, long done, boolean finished) {
+            if (finished) {
+                postMessage("info: " + disk + "/" + partition + "/" + path + " (" + done + " B)");
+            }
+        }
+
+        @Override
+        public void onStateChanged(int state) {
+            // Connection changes are already shown by onConnectionChanged, only the handshake is worth a note.
+            if (state == SugarCallback.STATE_HANDSHAKING) {
+                postMessage("info: negotiating the session...");
+            }
+        }
+
+        @Override
+        public void onReconnecting(int attempt, long delayMs, int error, String message) {
+            postMessage("info: reconnecting in " + delayMs + " ms, attempt " + attempt + ": " + message);
+        }
+
+        @Override
+        public void onError(int code, String message) {
+            postMessage("error: " + message);
+        }
+
+        @Override
+        public void onLog(int level, String message) {
+            // Only errors and warnings are shown in the terminal, everything else is in the logcat.
+            if (level <= 2) {
+                postMessage((level == 1 ? "error: " : "info: ") + message);
+            }
+        }
+    };
+
     @Override
     public void onResume() {
         super.onResume();
         IntentFilter filter = new IntentFilter(ACTION_USB_PERMISSION);
         requireActivity().registerReceiver(usbPermissionReceiver, filter);
+        SugarInit.registerCallback(sugarCallback);
     }
 
     @Override
     public void onPause() {
         super.onPause();
         requireActivity().unregisterReceiver(usbPermissionReceiver);
+        SugarInit.unregisterCallback();
+    }
+
+    private void runOnUi(final Runnable action) {
+        if (getActivity() != null) {
+            getActivity().runOnUiThread(() -> {
+                if (isAdded()) {
+                    action.run();
+                }
+            });
+        }
+    }
+
+    private void postMessage(final String message) {
+        runOnUi(() -> displayMessage(message));
     }
 
     private void loadMessageBuffer() {
@@ -286,17 +376,17 @@ public class TargetFragment extends Fragment {
                         displayMessage(getString(R.string.connecting_to_device) + chosenDevice.getDeviceName());
                         displayMessage("info: " + getString(R.string.flashing_the_daemon));
                         switch (connect(fileDescriptor)) {
-                            case 0:
+                            case SugarError.OK:
                                 POWER = !POWER;
                                 sharedPreferences.edit().putBoolean("power_" + machineNameTextView.getText(), POWER).apply();
-                                displayMessage("info: " + getString(R.string.connected_to_device) + conn_info());
+                                // Connecting is already shown, onConnectionChanged reports the result of the handshake.
                                 break;
                             default:
                                 displayMessage("error: " + getString(R.string.error_unknown_error));
                         }
                     } else {
                         switch (disconnect()) {
-                            case 0:
+                            case SugarError.OK:
                                 POWER = !POWER;
                                 sharedPreferences.edit().putBoolean("power_" + machineNameTextView.getText(), POWER).apply();
                                 break;
@@ -28,6 +28,7 @@ import androidx.lifecycle.ViewModelProvider;
 
 import com.notforest.sugar.LoginActivity;
 import com.notforest.sugar.R;
+import com.notforest.sugar.SugarError;
 import com.notforest.sugar.MainActivity;
 import com.notforest.sugar.databinding.FragmentHomeBinding;
 import com.notforest.sugar.databinding.FragmentProfileBinding;
@@ -133,32 +134,32 @@ public class ProfileFragment extends Fragment {
                     TextView new_mail_err = root.findViewById(R.id.new_email_error);
 
                     switch (changeEmail(new_mail.getText().toString())) {
-                        case 0:
+                        case SugarError.OK:
                             userMail.setText(new_mail.getText());
                             mainActivity.change_user_data(new_mail.getText().toString());
 
                             break;
-                        case 10:
+                        case SugarError.INVALID_EMAIL:
                             new_mail_err.setText(R.string.error_mail_regex);
                             new_mail_err.setVisibility(View.VISIBLE);
                             break;
-                        case 31:
+                        case SugarError.USER_DISABLED:
                             Toast.makeText(
                                     mainActivity,
                                     "Your 'Sugar' account was disabled due to strange activity from this device. Please contact administration for further support.",
                                     Toast.LENGTH_LONG
                             ).show();
                             break;
-                        case 20:
+                        case SugarError.EMAIL_EXISTS:
                             new_mail_err.setText(R.string.error_mail_used_new);
                             new_mail_err.setVisibility(View.VISIBLE);
                             break;
-                        case 32: // Expired token, previous login required
-                        case 33: // Token not valid, previous login required
-                        case 34: // Same.
-                        case 35: // Same.
-                        case 36: // Same.
-                        case 40:
+                        case SugarError.TOKEN_EXPIRED: // Expired token, previous login required
+                        case SugarError.TOKEN_NOT_VALID: // Token not valid, previous login required
+                        case SugarError.MALFORMED_TOKEN_HEADER: // Same.
+                        case SugarError.KEY_PARSING_ERROR: // Same.
+                        case SugarError.NO_MATCH_DECODING_KEY: // Same.
+                        case SugarError.FILE_NOT_EXIST:
                             // No file means the user is not logged in for some reason.
                             Toast.makeText(
                                     mainActivity,
@@ -188,24 +189,28 @@ public class ProfileFragment extends Fragment {
                             old_pass.getText().toString(),
                             new_pass.getText().toString()
                     )) {
-                        case 0: break;
-                        case 11:
+                        case SugarError.OK: break;
+                        case SugarError.INVALID_PASS:
+                            pass_err.setText(R.string.error_pass_wrong);
+                            pass_err.setVisibility(View.VISIBLE);
+                            break;
+                        case SugarError.WEAK_PASS:
                             pass_err.setText(R.string.error_pass_weak);
                             pass_err.setVisibility(View.VISIBLE);
                             break;
-                        case 31:
+                        case SugarError.USER_DISABLED:
                             Toast.makeText(
                                     mainActivity,
                                     "Your 'Sugar' account was disabled due to strange activity from this device. Please contact administration for further support.",
                                     Toast.LENGTH_LONG
                             ).show();
                             break;
-                        case 32: // Expired token, previous login required
-                        case 33: // Token not valid, previous login required
-                        case 34: // Same.
-                        case 35: // Same.
-                        case 36: // Same.
-                        case 40: // No file means the user is not logged in for some reason.
+                        case SugarError.TOKEN_EXPIRED: // Expired token, previous login required
+                        case SugarError.TOKEN_NOT_VALID: // Token not valid, previous login required
+                        case SugarError.MALFORMED_TOKEN_HEADER: // Same.
+                        case SugarError.KEY_PARSING_ERROR: // Same.
+                        case SugarError.NO_MATCH_DECODING_KEY: // Same.
+                        case SugarError.FILE_NOT_EXIST: // No file means the user is not logged in for some reason.
                             Toast.makeText(
                                     mainActivity,
                                     "Please login again.",
@@ -227,13 +232,13 @@ public class ProfileFragment extends Fragment {
                 @Override
                 public void onClick(View v) {
                     switch (logout()) {
-                        case 0:
+                        case SugarError.OK:
                             // We are now logged out, so jumping to login screen.
-                        case 40:
+                        case SugarError.FILE_NOT_EXIST:
                             // No file means the user is not logged in for some reason.
                             mainActivity.finish();
                             break;
-                        case 46:
+                        case SugarError.INTERRUPTED:
                             // IO interrupted, trying one more time.
                             logout();
                             break;
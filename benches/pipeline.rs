@@ -0,0 +1,119 @@
+//! Throughput of pipelined reads.
+//!
+//! Reads a file from the simulated daemon through a transport, which completes each read transfer
+//! only after a fixed latency since it was submitted, just like a bulk transfer on a real bus. The
+//! same file is read with a single transfer in flight and with the whole pipeline of the reader.
+//!
+//! Usage: `cargo bench --bench pipeline`
+
+use std::{
+    collections::VecDeque,
+    fs,
+    thread,
+    time::{Duration, Instant},
+};
+
+use rusb::Error as RusbError;
+use sugar_jni::sugar::{
+    conn::{
+        client::EntryKind,
+        lifecycle::LifecycleState,
+        sim::SimDaemon,
+        transport::Sink,
+        Bridge, MemoryTransport, Transport,
+    },
+    runtime,
+};
+
+/// Time between the submission and the completion of one read transfer.
+const LATENCY: Duration = Duration::from_micros(125);
+/// Size of the file read in each run.
+const FILE_SIZE: usize = 2 << 20;
+
+/// Memory transport, whose read transfers complete after [`LATENCY`].
+struct LatencyTransport {
+    inner: MemoryTransport,
+    /// Most transfers kept in flight, regardless of what the reader asks for.
+    limit: usize,
+}
+
+impl Transport for LatencyTransport {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, RusbError> {
+        thread::sleep(LATENCY);
+        self.inner.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, RusbError> {
+        self.inner.write(buf)
+    }
+
+    /// Each transfer is resubmitted right after its completion is passed to the sink.
+    fn stream(&self, depth: usize, size: usize, sink: &mut Sink<'_>) -> Result<(), RusbError> {
+        let now = Instant::now();
+        let mut submitted: VecDeque<_> = (0..depth.min(self.limit).max(1)).map(|_| now).collect();
+        let mut buf = vec![0; size];
+
+        while let Some(at) = submitted.pop_front() {
+            if let Some(wait) = (at + LATENCY).checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+            if !sink(self.inner.read(&mut buf).map(|len| &buf[..len])) {
+                break
+            }
+            submitted.push_back(Instant::now());
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> String {
+        format!("In-memory transport with {} transfers in flight", self.limit)
+    }
+
+    fn machine(&self) -> String {
+        "latency".to_string()
+    }
+}
+
+/// Reads the file once with at most `limit` transfers in flight and returns the time it took.
+fn read(root: &std::path::Path, limit: usize) -> Duration {
+    let (host, target) = MemoryTransport::pair();
+    let daemon = SimDaemon::new(root).spawn(target);
+
+    let mut bridge = Bridge::with_transport(1, LatencyTransport { inner: host, limit });
+    let mut state = bridge.subscribe();
+    let client = bridge.client();
+    let bridge = runtime::spawn(async move { bridge.connect().await });
+    runtime::block_on(state.wait_for(|state| *state == LifecycleState::Connected)).unwrap();
+
+    let elapsed = runtime::block_on(async {
+        let disk = client.list_disks().await.unwrap().remove(0);
+        let partition = client.list_partitions(&disk).await.unwrap().remove(0);
+        let file = client.list_files(&partition).await.unwrap().into_iter()
+            .find(|entry| entry.kind == EntryKind::File)
+            .unwrap();
+
+        let start = Instant::now();
+        let data = client.read_file(&file).await.unwrap();
+        assert_eq!(data.len(), FILE_SIZE);
+        start.elapsed()
+    });
+
+    runtime::block_on(client.disconnect()).unwrap();
+    runtime::block_on(bridge).unwrap().unwrap();
+    daemon.join().unwrap().unwrap();
+    elapsed
+}
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("sugar-bench-{}", std::process::id()));
+    fs::create_dir_all(root.join("sda/sda1")).unwrap();
+    fs::write(root.join("sda/sda1/data.bin"), vec![0x5a; FILE_SIZE]).unwrap();
+
+    for (name, limit) in [("single transfer", 1), ("pipelined", usize::MAX)] {
+        let elapsed = read(&root, limit);
+        let rate = FILE_SIZE as f64 / elapsed.as_secs_f64() / (1 << 20) as f64;
+        println!("{:<16} {:>8.1?} {:>8.2} MiB/s", name, elapsed, rate);
+    }
+
+    fs::remove_dir_all(&root).ok();
+}
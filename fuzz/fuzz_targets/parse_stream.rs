@@ -0,0 +1,46 @@
+//! Feeds arbitrary byte streams through the whole receiving path.
+//!
+//! The stream is split into transfers of arbitrary sizes, reassembled by the frame decoder and
+//! then handled by both the bridge's parser and the simulated daemon. None of them may panic.
+//!
+//! Run with: `cargo +nightly fuzz run parse_stream`
+#![no_main]
+
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use tokio::runtime::{Builder, Runtime};
+use sugar_jni::sugar::{
+    conn::{codec::FrameDecoder, sim::SimDaemon, Bridge, MemoryTransport},
+    parse::SugarParser,
+};
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Builder::new_current_thread().build().unwrap())
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk, stream)) = data.split_first() else { return };
+    let chunk = chunk.max(1) as usize;
+
+    // The other end must stay alive, so that all answers are written successfully.
+    let (host, _target) = MemoryTransport::pair();
+    let mut bridge = Bridge::with_transport(0, host);
+    let mut daemon = SimDaemon::new("/nonexistent");
+    let mut decoder = FrameDecoder::default();
+
+    runtime().block_on(async {
+        for transfer in stream.chunks(chunk) {
+            decoder.push(transfer);
+
+            while let Some(frame) = decoder.next_frame() {
+                if let Ok(cmd) = frame {
+                    daemon.handle(cmd.byte_code());
+                    SugarParser::parse_byte_code(&mut bridge, cmd).await;
+                }
+            }
+        }
+        decoder.flush().ok();
+    });
+});
@@ -0,0 +1,72 @@
+//! Simulated Sugar daemon.
+//!
+//! Serves a local directory tree over the Sugar protocol through an in-memory transport and walks
+//! it from the other side with a [`DaemonClient`], printing every disk, partition and file it
+//! finds. This allows to check the protocol flow without any target attached.
+//!
+//! Usage: `sugar-sim [ROOT]`, where ROOT stands in for `/mnt/disks` on the target.
+
+use std::process::ExitCode;
+
+use sugar_jni::sugar::conn::{
+    client::{ClientResult, EntryKind},
+    sim::SimDaemon,
+    Bridge, DaemonClient, MemoryTransport,
+};
+
+async fn walk(client: &DaemonClient) -> ClientResult<()> {
+    for disk in client.list_disks().await? {
+        println!("{}", disk.name);
+
+        for part in client.list_partitions(&disk).await? {
+            println!("  {}", part.name);
+
+            for entry in client.list_files(&part).await? {
+                // Directory entries are marked with a trailing slash.
+                match entry.kind {
+                    EntryKind::File => println!("    {}", entry.path),
+                    EntryKind::Directory => println!("    {}/", entry.path),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let root = std::env::args().nth(1).unwrap_or_else(|| "/mnt/disks".to_string());
+    let (host, target) = MemoryTransport::pair();
+    let daemon = SimDaemon::new(root).spawn(target);
+
+    let mut bridge = Bridge::with_transport(rand::random(), host);
+    let client = bridge.client();
+    let bridge = tokio::spawn(async move { bridge.connect().await });
+
+    let walked = walk(&client).await;
+    client.disconnect().await.ok();
+
+    let connected = bridge.await;
+    let daemon = tokio::task::spawn_blocking(move || daemon.join()).await;
+
+    match (walked, connected, daemon) {
+        (Ok(_), Ok(Ok(_)), Ok(Ok(Ok(_)))) => ExitCode::SUCCESS,
+        (Err(err), _, _) => {
+            eprintln!("Simulation failed: {}", err);
+            ExitCode::FAILURE
+        },
+        (_, Ok(Err(err)), _) => {
+            eprintln!("Simulation failed: {}", err.report());
+            ExitCode::FAILURE
+        },
+        (_, _, Ok(Ok(Err(err)))) => {
+            eprintln!("Simulation failed: {}", err);
+            ExitCode::FAILURE
+        },
+        _ => {
+            eprintln!("Simulation has panicked.");
+            ExitCode::FAILURE
+        },
+    }
+}
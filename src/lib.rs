@@ -29,13 +29,40 @@ pub mod sugar {
     /// Main application logic lays here. Connects an android mobile device to a target
     /// architecture via a USB connection by mainly using ported hidapi library.
     pub mod conn {
+        /// Actor which owns the bridge of the current connection.
+        pub mod actor;
         /// Main communication bridge.
         pub mod bridge;
         pub mod cmd;
+        /// Typed requests to the daemon.
+        pub mod client;
+        /// Splitting of the byte stream into commands.
+        pub mod codec;
+        /// Protocol version and capability negotiation.
+        pub mod session;
+        /// Counters of the bridge traffic.
+        pub mod stats;
+        /// Byte transports the bridge communicates through.
+        pub mod transport;
+        /// Pipelined bulk transfers of libusb.
+        mod transfer;
+        /// Dedicated reader and writer of the bridge.
+        mod io;
+        /// Lifecycle of the bridge.
+        pub mod lifecycle;
+        /// Removal of the USB device noticed via hotplug events.
+        mod hotplug;
+        /// Recovery from failed transfers and reconnection of failed bridges.
+        pub mod recovery;
+        /// Simulated daemon for running the bridge without a target.
+        pub mod sim;
         mod buf;
 
         pub(crate) use buf::Buffer;
         pub use bridge::Bridge;
+        pub use client::DaemonClient;
+        pub use session::SessionInfo;
+        pub use transport::{MemoryTransport, Transport, UsbTransport};
         pub use bridge::service;
     }
 
@@ -47,6 +74,16 @@ pub mod sugar {
     pub mod parse;
     /// All storage related functions.
     pub mod storage;
+    /// Asynchronous runtime shared by all calls from Java.
+    pub mod runtime;
+    /// Events pushed to the front-end.
+    pub mod events;
+    /// Logging with redaction of secrets.
+    pub mod logging;
+    /// Diagnostic bundles attached to bug reports.
+    pub mod diag;
+    /// Crash reports captured by the panic hook.
+    pub mod crash;
 
     pub use api::FIREBASE_URI;
 }
@@ -57,22 +94,84 @@ pub mod sugar {
 #[cfg(target_os = "android")]
 #[allow(non_snake_case)]
 pub mod android {
-    use std::path::Path;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+    use std::time::Duration;
 
     use super::*;
 
     
-    use jni::JNIEnv;
-    use jni::objects::{JClass, JString};
-    use jni::sys::jstring;
+    use jni::{JNIEnv, JavaVM};
+    use jni::objects::{GlobalRef, JClass, JObject, JObjectArray, JString, JValue};
+    use jni::sys::{jboolean, jint, jlong, jlongArray, jobjectArray, jstring};
 
     use log::LevelFilter;
-    use android_logger::{Config, FilterBuilder};
+    use android_logger::{AndroidLogger, Config};
     use sugar::auth::profile::{change_mail, change_pass};
     use sugar::auth::service::{fast_login, login, logout, signup};
-    use sugar::conn::service::{connect, disconnect, get_conn_info};
-    use sugar::auth::usrsrv::UserServiceStatus;
-    use sugar::storage::{FILES_DIR, CACHE_DIR, EXT_FILES_DIR, EXT_CACHE_DIR};
+    use sugar::auth::usrsrv::seal_credentials;
+    use sugar::conn::client::{Disk, EntryKind, Partition, RemoteEntry};
+    use sugar::conn::recovery::ReconnectPolicy;
+    use sugar::conn::service::{connect, disconnect, download, get_conn_info, list_disks, list_files, list_partitions, set_reconnect_policy};
+    use sugar::events::{self, Event, EventLogger, EventSink};
+    use sugar::logging::{self, Secret, SugarLogger};
+    use sugar::{crash, diag};
+    use sugar::errors::{InternalError, StorageError, SugarResult};
+    use sugar::runtime::{self, block_on};
+    use sugar::storage::{Cache, CacheLocation, FILES_DIR, CACHE_DIR, EXT_FILES_DIR, EXT_CACHE_DIR};
+
+    /// Exception thrown by calls, which are not related to any specific service.
+    const SUGAR_EXCEPTION: &str = "com/notforest/sugar/SugarException";
+    /// Exception thrown by calls related to the bridge.
+    const BRIDGE_EXCEPTION: &str = "com/notforest/sugar/SugarBridgeException";
+    /// Exception thrown by calls related to user's authentication.
+    const AUTH_EXCEPTION: &str = "com/notforest/sugar/SugarAuthException";
+
+    /// Runs the body of an exported function, so that no panic can ever unwind into the JVM.
+    ///
+    /// Both a panic and a failed JNI call are thrown as the provided Java exception with the Rust
+    /// error message attached. The fallback value is returned in such case, which Java only sees if
+    /// the exception could not be thrown.
+    fn guard<'local, T>(
+        env: &mut JNIEnv<'local>,
+        exception: &str,
+        fallback: T,
+        body: impl FnOnce(&mut JNIEnv<'local>) -> jni::errors::Result<T>,
+    ) -> T {
+        let msg = match panic::catch_unwind(AssertUnwindSafe(|| body(&mut *env))) {
+            Ok(Ok(value)) => return value,
+            Ok(Err(err)) => format!("JNI call has failed: {}", err),
+            Err(payload) => format!("Backend has panicked: {}", crash::message(payload.as_ref())),
+        };
+        log::error!("{}", msg);
+
+        // An exception left by the failed JNI call is replaced by our own one.
+        if env.exception_check().unwrap_or(false) {
+            env.exception_clear().ok();
+        }
+        if let Err(err) = env.throw_new(exception, &msg) {
+            log::error!("Unable to throw {}: {}", exception, err);
+        }
+
+        fallback
+    }
+
+    /// Converts Java's string into Rust's one.
+    fn get_string(env: &mut JNIEnv, string: &JString) -> jni::errors::Result<String> {
+        Ok(env.get_string(string)?.into())
+    }
+
+    /// Status returned by calls, which have failed on the boundary.
+    const FFI_FAILURE: jint = InternalError::FFI_ERROR as jint;
+
+    /// Converts the result into a status code for Java, where zero means success.
+    fn status<T>(result: SugarResult<T>) -> jint {
+        match result {
+            Ok(_) => 0,
+            Err(err) => err.code() as jint,
+        }
+    }
 
     // EXTERNS
     /// Initialization code from rust's side.
@@ -85,38 +184,187 @@ pub mod android {
         ext_files_dir: JString,
         ext_cache_dir: JString,
     ) {
-        // Initializing logger.
-        android_logger::init_once(
-            Config::default()
-                .with_max_level(LevelFilter::Trace)                     // limit log level
-                .with_tag("RUST_BACKEND")                               
-                .with_filter(                                           // configure messages for specific crate
-                    FilterBuilder::new()
-                        .parse("debug,hello::crate=error,rusb=debug,tokio=info")
-                        .build()
-                )
-        );
-        log::info!("Logger initialized");
-
-        // Converting
-        let files_dir: String = env.get_string(&files_dir).expect("Could not parse Java string.").into();
-        let cache_dir: String = env.get_string(&cache_dir).expect("Could not parse Java string.").into();
-        let ext_files_dir: String = env.get_string(&ext_files_dir).expect("Could not parse Java string.").into();
-        let ext_cache_dir: String = env.get_string(&ext_cache_dir).expect("Could not parse Java string.").into();
+        guard(&mut env, SUGAR_EXCEPTION, (), |env| {
+            // Initializing logger.
+            let logger = AndroidLogger::new(
+                Config::default()
+                    .with_max_level(LevelFilter::Trace)                     // filtered by the backend itself
+                    .with_tag("RUST_BACKEND")                               
+            );
+            // Log lines are also pushed to the front-end, but only the important ones.
+            let logger = SugarLogger::new(EventLogger::new(logger, LevelFilter::Info));
+            if log::set_boxed_logger(Box::new(logger)).is_ok() {
+                log::set_max_level(logging::level());
+            }
+            log::info!("Logger initialized");
+
+            // All calls from Java share the same runtime.
+            if let Err(err) = runtime::init() {
+                log::error!("Unable to create the async runtime: {}", err);
+            }
+
+            // Converting
+            let files_dir = get_string(env, &files_dir)?;
+            let cache_dir = get_string(env, &cache_dir)?;
+            let ext_files_dir = get_string(env, &ext_files_dir)?;
+            let ext_cache_dir = get_string(env, &ext_cache_dir)?;
  
-        log::info!("Files directory at: {}", files_dir);
-        log::info!("Cache directory at: {}", cache_dir);
-        log::info!("External files directory at: {}", ext_files_dir);
-        log::info!("External cache directory at: {}", ext_cache_dir);
+            log::info!("Files directory at: {}", files_dir);
+            log::info!("Cache directory at: {}", cache_dir);
+            log::info!("External files directory at: {}", ext_files_dir);
+            log::info!("External cache directory at: {}", ext_cache_dir);
+
+            // Getting info about directories from the environment.
+            FILES_DIR.write().unwrap_or_else(|e| e.into_inner()).push(Path::new(files_dir.as_str()));
+            CACHE_DIR.write().unwrap_or_else(|e| e.into_inner()).push(Path::new(cache_dir.as_str()));
+            EXT_FILES_DIR.write().unwrap_or_else(|e| e.into_inner()).push(Path::new(ext_files_dir.as_str()));
+            EXT_CACHE_DIR.write().unwrap_or_else(|e| e.into_inner()).push(Path::new(ext_cache_dir.as_str()));
+
+            // Logs are kept on the disk from now on.
+            if let Err(err) = logging::open_file(&Path::new(files_dir.as_str()).join("logs")) {
+                log::error!("Unable to open the log file: {}", err);
+            }
+
+            // Panics are recorded from now on, so that they can be reported on the next start.
+            crash::install();
+
+            // Older versions stored the credentials in plaintext.
+            if let Err(err) = seal_credentials() {
+                log::error!("Unable to encrypt stored credentials: {}", err.report());
+            }
+
+            log::debug!("Debug mode enabled");
+            log::info!("OK");
+            Ok(())
+        })
+    }
 
-        // Getting info about directories from the environment.
-        FILES_DIR.write().unwrap().push(Path::new(files_dir.as_str()));
-        CACHE_DIR.write().unwrap().push(Path::new(cache_dir.as_str()));
-        EXT_FILES_DIR.write().unwrap().push(Path::new(ext_files_dir.as_str()));
-        EXT_CACHE_DIR.write().unwrap().push(Path::new(ext_cache_dir.as_str()));
+    /// Changes the most verbose level logged by the backend.
+    ///
+    /// Levels are numbered from 0 (off) to 5 (trace), larger values are treated as trace.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarInit_setLogLevel(
+        mut env: JNIEnv,
+        _: JClass,
+        level: jint,
+    ) {
+        guard(&mut env, SUGAR_EXCEPTION, (), |_| {
+            let level = match level {
+                i32::MIN..=0 => LevelFilter::Off,
+                1 => LevelFilter::Error,
+                2 => LevelFilter::Warn,
+                3 => LevelFilter::Info,
+                4 => LevelFilter::Debug,
+                _ => LevelFilter::Trace,
+            };
 
-        log::debug!("Debug mode enabled");
-        log::info!("OK");
+            logging::set_level(level);
+            Ok(())
+        })
+    }
+
+    /// Sets the policy of reconnection after the connection fails. Zero attempts disable it.
+    ///
+    /// Applied from the next connection on. Negative values are treated as zero.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarInit_setReconnectPolicy(
+        mut env: JNIEnv,
+        _: JClass,
+        max_attempts: jint,
+        initial_delay_ms: jlong,
+        max_delay_ms: jlong,
+    ) {
+        guard(&mut env, SUGAR_EXCEPTION, (), |_| {
+            // Converting
+            let initial_delay = Duration::from_millis(initial_delay_ms.max(0) as u64);
+            let policy = ReconnectPolicy {
+                max_attempts: max_attempts.max(0) as u32,
+                initial_delay,
+                max_delay: Duration::from_millis(max_delay_ms.max(0) as u64).max(initial_delay),
+                ..ReconnectPolicy::default()
+            };
+
+            set_reconnect_policy(policy);
+            Ok(())
+        })
+    }
+
+    /// Writes a diagnostic bundle for a bug report.
+    ///
+    /// Returns the path of the zip within the external files directory, or null on failure.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarInit_exportDiagnostics(
+        mut env: JNIEnv,
+        _: JClass,
+        java_app_version: JString,
+    ) -> jstring {
+        guard(&mut env, SUGAR_EXCEPTION, ptr::null_mut(), |env| {
+            log::info!("Begin: export diagnostics.");
+            // Converting
+            let app_version = get_string(env, &java_app_version)?;
+
+            match block_on(diag::export_bundle(app_version)) {
+                Ok(path) => Ok(env.new_string(path.to_string_lossy())?.into_raw()),
+                Err(err) => {
+                    log::error!("Unable to export diagnostics: {}", err.report());
+                    Ok(ptr::null_mut())
+                },
+            }
+        })
+    }
+
+    /// Returns all pending crash reports as JSON objects, the oldest first.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarInit_crashReports(
+        mut env: JNIEnv,
+        _: JClass,
+    ) -> jobjectArray {
+        guard(&mut env, SUGAR_EXCEPTION, ptr::null_mut(), |env| {
+            log::info!("Begin: crash reports.");
+
+            let reports = match crash::reports() {
+                Ok(reports) => reports,
+                Err(err) => {
+                    log::error!("Unable to read crash reports: {}", err.report());
+                    return Ok(ptr::null_mut())
+                },
+            };
+
+            // Reports are plain structures, so they always serialize.
+            let reports: Vec<String> = reports.iter()
+                .filter_map(|report| serde_json::to_string(report).ok())
+                .collect();
+            Ok(string_array(env, reports.iter().map(String::as_str))?.into_raw())
+        })
+    }
+
+    /// Deletes the crash report with the provided ID.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarInit_deleteCrashReport(
+        mut env: JNIEnv,
+        _: JClass,
+        java_id: JString,
+    ) -> jint {
+        guard(&mut env, SUGAR_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: delete crash report.");
+            // Converting
+            let id = get_string(env, &java_id)?;
+
+            Ok(status(crash::delete(&id)))
+        })
+    }
+
+    /// Deletes all pending crash reports.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarInit_deleteCrashReports(
+        mut env: JNIEnv,
+        _: JClass,
+    ) -> jint {
+        guard(&mut env, SUGAR_EXCEPTION, FFI_FAILURE, |_| {
+            log::info!("Begin: delete crash reports.");
+
+            Ok(status(crash::delete_all()))
+        })
     }
 
     /// Wrapper function to provide java's strings to rust signup interface.
@@ -129,14 +377,16 @@ pub mod android {
         java_mail: JString,
         java_pass: JString,
         java_conf: JString
-    ) -> u8 {
-        log::info!("Begin: signup");
-        // Converting
-        let mail = env.get_string(&java_mail).expect("Could not parse Java string.").into();
-        let pass = env.get_string(&java_pass).expect("Could not parse Java string.").into();
-        let conf = env.get_string(&java_conf).expect("Could not parse Java string.").into();
+    ) -> jint {
+        guard(&mut env, AUTH_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: signup");
+            // Converting
+            let mail = get_string(env, &java_mail)?;
+            let pass = Secret::new(get_string(env, &java_pass)?);
+            let conf = Secret::new(get_string(env, &java_conf)?);
 
-        signup(mail, pass, conf).into() 
+            Ok(status(block_on(signup(mail, pass, conf))))
+        })
     }
 
     /// Fast login method by current token credentials.
@@ -147,11 +397,12 @@ pub mod android {
         mut env: JNIEnv,
         _: JClass
     ) -> jstring {
-        log::info!("Begin: login");
+        guard(&mut env, AUTH_EXCEPTION, ptr::null_mut(), |env| {
+            log::info!("Begin: login");
 
-        let st = if let Some(s) = fast_login() { s } else { "".to_string() };
-        env.new_string(st).expect("Unable to create new Java string from environment.")
-            .into_raw()
+            let st = if let Some(s) = block_on(fast_login()) { s } else { "".to_string() };
+            Ok(env.new_string(st)?.into_raw())
+        })
     }
 
     /// Wrapper function to provide java's strings to rust login interface.
@@ -163,22 +414,29 @@ pub mod android {
         _: JClass,
         java_mail: JString,
         java_pass: JString,
-    ) -> u8 {
-        log::info!("Begin: login");
-        // Converting
-        let mail = env.get_string(&java_mail).expect("Could not parse Java string.").into();
-        let pass = env.get_string(&java_pass).expect("Could not parse Java string.").into();
+    ) -> jint {
+        guard(&mut env, AUTH_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: login");
+            // Converting
+            let mail = get_string(env, &java_mail)?;
+            let pass = Secret::new(get_string(env, &java_pass)?);
         
-        login(mail, pass).into()
+            Ok(status(block_on(login(mail, pass))))
+        })
     }
 
     /// Wrapper function for logging out.
     ///
     /// Will be called by Java's front-end, when user creates new 'Sugar' account.
     #[no_mangle]
-    pub extern fn Java_com_notforest_sugar_ui_profile_ProfileFragment_logout() -> u8 {
-        log::info!("Begin: logout");
-        logout().into()
+    pub extern fn Java_com_notforest_sugar_ui_profile_ProfileFragment_logout(
+        mut env: JNIEnv,
+        _: JClass,
+    ) -> jint {
+        guard(&mut env, AUTH_EXCEPTION, FFI_FAILURE, |_| {
+            log::info!("Begin: logout");
+            Ok(status(block_on(logout())))
+        })
     }
 
     #[no_mangle]
@@ -186,12 +444,14 @@ pub mod android {
         mut env: JNIEnv,
         _: JClass,
         java_mail: JString,
-    ) -> u8 { 
-        log::info!("Begin: change email address");
-        // Converting
-        let mail = env.get_string(&java_mail).expect("Could not parse Java string.").into();
+    ) -> jint { 
+        guard(&mut env, AUTH_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: change email address");
+            // Converting
+            let mail = get_string(env, &java_mail)?;
 
-        change_mail(mail).into()
+            Ok(status(block_on(change_mail(mail))))
+        })
     }
 
     #[no_mangle]
@@ -200,32 +460,40 @@ pub mod android {
         _: JClass,
         pass_old: JString,
         pass_new: JString,
-    ) -> u8 {
-        log::info!("Begin: change password");
-        // Converting
-        let old = env.get_string(&pass_old).expect("Could not parse Java string.").into();
-        let new = env.get_string(&pass_new).expect("Could not parse Java string.").into();
+    ) -> jint {
+        guard(&mut env, AUTH_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: change password");
+            // Converting
+            let old = Secret::new(get_string(env, &pass_old)?);
+            let new = Secret::new(get_string(env, &pass_new)?);
 
-        change_pass(old, new).into()
+            Ok(status(block_on(change_pass(old, new))))
+        })
     }
 
     #[no_mangle]
     pub extern fn Java_com_notforest_sugar_ui_home_TargetFragment_connect(
-        env: JNIEnv,
+        mut env: JNIEnv,
         _: JClass,
         file_desc: i32,
-    ) -> u8 {
-        log::info!("Begin: connect");
+    ) -> jint {
+        guard(&mut env, BRIDGE_EXCEPTION, FFI_FAILURE, |_| {
+            log::info!("Begin: connect");
 
-        connect(file_desc) as u8
+            Ok(status(block_on(connect(file_desc))))
+        })
     }
 
     #[no_mangle]
     pub extern fn Java_com_notforest_sugar_ui_home_TargetFragment_disconnect(
-    ) -> u8 {
-        log::info!("Begin: disconnect");
+        mut env: JNIEnv,
+        _: JClass,
+    ) -> jint {
+        guard(&mut env, BRIDGE_EXCEPTION, FFI_FAILURE, |_| {
+            log::info!("Begin: disconnect");
 
-        disconnect() as u8
+            Ok(status(block_on(disconnect())))
+        })
     }
     
     #[no_mangle]
@@ -233,10 +501,313 @@ pub mod android {
         mut env: JNIEnv,
         _: JClass
     ) -> jstring {
-        log::info!("Begin: connection info.");
+        guard(&mut env, BRIDGE_EXCEPTION, ptr::null_mut(), |env| {
+            log::info!("Begin: connection info.");
+
+            let st = block_on(get_conn_info());
+            Ok(env.new_string(st)?.into_raw())
+        })
+    }
+
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_ui_home_TargetFragment_listDisks(
+        mut env: JNIEnv,
+        _: JClass,
+    ) -> jint {
+        guard(&mut env, BRIDGE_EXCEPTION, FFI_FAILURE, |_| {
+            log::info!("Begin: list disks.");
+
+            Ok(status(block_on(list_disks())))
+        })
+    }
+
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_ui_home_TargetFragment_listPartitions(
+        mut env: JNIEnv,
+        _: JClass,
+        java_disk: JString,
+    ) -> jint {
+        guard(&mut env, BRIDGE_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: list partitions.");
+            // Converting
+            let name = get_string(env, &java_disk)?;
+
+            Ok(status(block_on(list_partitions(Disk { name }))))
+        })
+    }
+
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_ui_home_TargetFragment_listFiles(
+        mut env: JNIEnv,
+        _: JClass,
+        java_disk: JString,
+        java_part: JString,
+    ) -> jint {
+        guard(&mut env, BRIDGE_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: list files.");
+            // Converting
+            let disk = get_string(env, &java_disk)?;
+            let name = get_string(env, &java_part)?;
+
+            Ok(status(block_on(list_files(Partition { disk, name }))))
+        })
+    }
+
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_ui_home_TargetFragment_download(
+        mut env: JNIEnv,
+        _: JClass,
+        java_disk: JString,
+        java_part: JString,
+        java_path: JString,
+        java_dest: JString,
+    ) -> jint {
+        guard(&mut env, BRIDGE_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: download.");
+            // Converting
+            let disk = get_string(env, &java_disk)?;
+            let name = get_string(env, &java_part)?;
+            let path = get_string(env, &java_path)?;
+            let dest = get_string(env, &java_dest)?;
+
+            let file = RemoteEntry { partition: Partition { disk, name }, path, kind: EntryKind::File };
+            Ok(status(block_on(download(file, PathBuf::from(dest)))))
+        })
+    }
+
+    /// Returns the space taken by the cache, or null if it cannot be measured.
+    ///
+    /// Layout of the array is described by `SugarCache.USAGE_*` constants.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarCache_usage(
+        mut env: JNIEnv,
+        _: JClass,
+    ) -> jlongArray {
+        guard(&mut env, SUGAR_EXCEPTION, ptr::null_mut(), |env| {
+            log::info!("Begin: cache usage.");
+
+            let usage = match Cache::usage() {
+                Ok(usage) => usage,
+                Err(err) => {
+                    log::error!("Unable to measure the cache: {}", err.report());
+                    return Ok(ptr::null_mut())
+                },
+            };
+
+            let values = [usage.internal, usage.external, usage.pinned, usage.items as u64, usage.budget].map(|v| v as jlong);
+            let array = env.new_long_array(values.len() as i32)?;
+            env.set_long_array_region(&array, 0, &values)?;
+            Ok(array.into_raw())
+        })
+    }
+
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarCache_clear(
+        mut env: JNIEnv,
+        _: JClass,
+        keep_pinned: jboolean,
+    ) -> jint {
+        guard(&mut env, SUGAR_EXCEPTION, FFI_FAILURE, |_| {
+            log::info!("Begin: clear cache.");
+
+            Ok(status(Cache::clear(keep_pinned != 0)))
+        })
+    }
+
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarCache_setBudget(
+        mut env: JNIEnv,
+        _: JClass,
+        bytes: jlong,
+    ) -> jint {
+        guard(&mut env, SUGAR_EXCEPTION, FFI_FAILURE, |_| {
+            log::info!("Begin: set cache budget.");
+
+            Ok(status(Cache::set_budget(bytes.max(0) as u64)))
+        })
+    }
+
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarCache_pin(
+        mut env: JNIEnv,
+        _: JClass,
+        location: jint,
+        java_name: JString,
+    ) -> jint {
+        guard(&mut env, SUGAR_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: pin cache item.");
+            // Converting
+            let name = get_string(env, &java_name)?;
+
+            Ok(status(cache_location(location).and_then(|location| Cache::pin(location, &name))))
+        })
+    }
+
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarCache_unpin(
+        mut env: JNIEnv,
+        _: JClass,
+        location: jint,
+        java_name: JString,
+    ) -> jint {
+        guard(&mut env, SUGAR_EXCEPTION, FFI_FAILURE, |env| {
+            log::info!("Begin: unpin cache item.");
+            // Converting
+            let name = get_string(env, &java_name)?;
+
+            Ok(status(cache_location(location).and_then(|location| Cache::unpin(location, &name))))
+        })
+    }
+
+    /// Converts `SugarCache.INTERNAL` or `SugarCache.EXTERNAL` to the location.
+    fn cache_location(location: jint) -> SugarResult<CacheLocation> {
+        match location {
+            0 => Ok(CacheLocation::Internal),
+            1 => Ok(CacheLocation::External),
+            _ => Err(StorageError::INVALID_KEY.into()),
+        }
+    }
+
+    /// Registers the Java object, which will obtain all events from the backend.
+    ///
+    /// The object must implement `com.notforest.sugar.SugarCallback`.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarInit_registerCallback(
+        mut env: JNIEnv,
+        _: JClass,
+        callback: JObject,
+    ) {
+        guard(&mut env, SUGAR_EXCEPTION, (), |env| {
+            log::info!("Begin: register callback.");
+
+            let vm = env.get_java_vm()?;
+            let callback = env.new_global_ref(callback)?;
+            events::set_sink(JavaEventSink { vm, callback });
+            Ok(())
+        })
+    }
+
+    /// Removes the registered callback object.
+    #[no_mangle]
+    pub extern fn Java_com_notforest_sugar_SugarInit_unregisterCallback(
+        mut env: JNIEnv,
+        _: JClass,
+    ) {
+        guard(&mut env, SUGAR_EXCEPTION, (), |_| {
+            log::info!("Begin: unregister callback.");
+
+            events::clear_sink();
+            Ok(())
+        })
+    }
+
+    /// Sink which pushes all events to the callback object registered from Java.
+    struct JavaEventSink {
+        vm: JavaVM,
+        callback: GlobalRef,
+    }
+
+    impl JavaEventSink {
+        fn deliver(&self, env: &mut JNIEnv, event: Event) -> jni::errors::Result<()> {
+            let cb = self.callback.as_obj();
+
+            match event {
+                Event::Connected => {
+                    env.call_method(cb, "onConnectionChanged", "(ZILjava/lang/String;)V", 
+                        &[JValue::Bool(1), JValue::Int(0), JValue::Object(&JObject::null())])?;
+                },
+                Event::Disconnected(err) => {
+                    let code = err.as_ref().map_or(0, |err| err.code() as jint);
+                    let msg = match err {
+                        Some(err) => env.new_string(err.report())?.into(),
+                        None => JObject::null(),
+                    };
+                    env.call_method(cb, "onConnectionChanged", "(ZILjava/lang/String;)V", 
+                        &[JValue::Bool(0), JValue::Int(code), JValue::Object(&msg)])?;
+                },
+                Event::Disks(disks) => {
+                    let names = string_array(env, disks.iter().map(|disk| disk.name.as_str()))?;
+                    env.call_method(cb, "onDisks", "([Ljava/lang/String;)V", &[JValue::Object(&names)])?;
+                },
+                Event::Partitions(disk, partitions) => {
+                    let disk = env.new_string(&disk.name)?;
+                    let names = string_array(env, partitions.iter().map(|part| part.name.as_str()))?;
+                    env.call_method(cb, "onPartitions", "(Ljava/lang/String;[Ljava/lang/String;)V", 
+                        &[JValue::Object(&disk), JValue::Object(&names)])?;
+                },
+                Event::Files(partition, files) => {
+                    let disk = env.new_string(&partition.disk)?;
+                    let part = env.new_string(&partition.name)?;
+                    let names = string_array(env, files.iter().map(|file| file.path.as_str()))?;
+                    let dirs: Vec<u8> = files.iter().map(|file| (file.kind == EntryKind::Directory) as u8).collect();
+                    let is_dir = env.new_boolean_array(dirs.len() as i32)?;
+                    env.set_boolean_array_region(&is_dir, 0, &dirs)?;
+                    env.call_method(cb, "onFiles", "(Ljava/lang/String;Ljava/lang/String;[Ljava/lang/String;[Z)V", 
+                        &[JValue::Object(&disk), JValue::Object(&part), JValue::Object(&names), JValue::Object(&is_dir)])?;
+                },
+                Event::Progress { file, done, finished } => {
+                    let disk = env.new_string(&file.partition.disk)?;
+                    let part = env.new_string(&file.partition.name)?;
+                    let path = env.new_string(&file.path)?;
+                    env.call_method(cb, "onProgress", "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;JZ)V", 
+                        &[JValue::Object(&disk), JValue::Object(&part), JValue::Object(&path), JValue::Long(done as i64), JValue::Bool(finished as u8)])?;
+                },
+                Event::State(state) => {
+                    env.call_method(cb, "onStateChanged", "(I)V", &[JValue::Int(state as jint)])?;
+                },
+                Event::Reconnecting { attempt, delay, error } => {
+                    let msg = env.new_string(error.report())?;
+                    env.call_method(cb, "onReconnecting", "(IJILjava/lang/String;)V", 
+                        &[JValue::Int(attempt as jint), JValue::Long(delay.as_millis() as i64), JValue::Int(error.code() as jint), JValue::Object(&msg)])?;
+                },
+                Event::Error(err) => {
+                    let msg = env.new_string(err.report())?;
+                    env.call_method(cb, "onError", "(ILjava/lang/String;)V", 
+                        &[JValue::Int(err.code() as jint), JValue::Object(&msg)])?;
+                },
+                Event::Log(level, msg) => {
+                    let msg = env.new_string(msg)?;
+                    env.call_method(cb, "onLog", "(ILjava/lang/String;)V", &[JValue::Int(level as i32), JValue::Object(&msg)])?;
+                },
+            }
+
+            Ok(())
+        }
+    }
+
+    impl EventSink for JavaEventSink {
+        fn handle(&mut self, event: Event) {
+            // The dispatcher thread is attached once and stays attached until it is finished.
+            let mut env = match self.vm.attach_current_thread_permanently() {
+                Ok(env) => env,
+                Err(err) => return log::error!("Unable to attach the event dispatcher to the Java VM: {}", err),
+            };
+
+            // Each event gets its own frame, so that all local references are freed right away.
+            if let Err(err) = env.with_local_frame(16, |env| self.deliver(env, event)) {
+                // An exception thrown by the callback must not stay pending on this thread.
+                if env.exception_check().unwrap_or(false) {
+                    env.exception_describe().ok();
+                    env.exception_clear().ok();
+                }
+                log::error!("Unable to deliver the event to Java: {}", err);
+            }
+        }
+    }
+
+    /// Creates a Java array of strings.
+    fn string_array<'local, 'a>(
+        env: &mut JNIEnv<'local>, 
+        items: impl ExactSizeIterator<Item = &'a str>,
+    ) -> jni::errors::Result<JObjectArray<'local>> {
+        let array = env.new_object_array(items.len() as i32, "java/lang/String", JObject::null())?;
+
+        for (i, item) in items.enumerate() {
+            let item = env.new_string(item)?;
+            env.set_object_array_element(&array, i as i32, &item)?;
+            env.delete_local_ref(item)?;
+        }
 
-        let st = get_conn_info();
-        env.new_string(st).expect("Unable to create new Java string from environment.")
-            .into_raw()
+        Ok(array)
     }
 }
@@ -5,8 +5,10 @@
 
 use firebase_auth_sdk::{FireAuth, Error};
 use firebase_auth_sdk::api::{SignInResponse, UpdateUser, User};
-use crate::sugar::{api::FIREBASE_API_KEY, errors::LoginError, storage::LocalStorage};
-use super::usrsrv::UserServiceStatus;
+use super::usrsrv::{LOGIN_RESPONSE, SECURE_TOKEN};
+use crate::sugar::{
+    api::FIREBASE_API_KEY, errors::{AuthError, ErrorKind, SugarError, SugarResult}, logging::Secret, storage::EncryptedStorage
+};
 
 
 /// Tries to get the current user's email by current session.
@@ -14,9 +16,9 @@ use super::usrsrv::UserServiceStatus;
 /// # Returns
 ///
 /// Will return an email as a string, if data was obtained successfully from 
-/// the last login. Here, user service status is used for error codes only. 
-pub async fn get_user() -> Result<String, UserServiceStatus> {
-    match LocalStorage::read::<SignInResponse>("login_response") {
+/// the last login.
+pub async fn get_user() -> SugarResult<String> {
+    match EncryptedStorage::read::<SignInResponse>(&LOGIN_RESPONSE) {
         Ok(mut res) => {
             let mut token = res.id_token;
             let auth = FireAuth::new(FIREBASE_API_KEY.to_string());
@@ -24,41 +26,40 @@ pub async fn get_user() -> Result<String, UserServiceStatus> {
             'token: loop {
                 // Verifying the current ID token.
                 return match auth.verify_id_token(token.as_str()).await {
-                    Ok(claim) => {
-                        log::info!("Token login success: {:#?}", claim);
+                    Ok(_) => {
+                        // Claims identify the user, so they are never logged.
+                        log::info!("Token login success.");
 
                         Ok(res.email)
                     },
                     Err(err) => match err.clone() {
                         Error::API(s) => {
                             log::error!("Firebase API error: {}", s);
-                            Err(UserServiceStatus::LoginError(
-                                    LoginError::KEY_PARSING_ERROR
-                            ))
+                            Err(SugarError::new(AuthError::KEY_PARSING_ERROR).with_source(err))
                         }, 
                         Error::Token(s) => {
                             log::error!("Token error: {}", s);
 
-                            let token_err = err.into();
+                            let token_err = SugarError::from(err);
 
                             // Trying to refresh the expired token right away.
-                            match token_err {
+                            match token_err.kind() {
                                 // If expired, refreshing.
-                                LoginError::TOKEN_EXPIRED => {
+                                ErrorKind::Auth(AuthError::TOKEN_EXPIRED) => {
                                     if let Some(refresh_token) = res.refresh_token {
                                         match auth.refresh_id_token(refresh_token.as_str()).await {
                                             Ok(claim) => {
-                                                log::info!("Token refreshed, retrying to verify. Claim: {:#?}", claim);
+                                                log::info!("Token refreshed, retrying to verify.");
                                                 token = claim.id_token;
                                                 res.id_token = token.clone();
                                                 res.refresh_token = Some(claim.refresh_token);
 
                                                 // Writing new token data to the
-                                                LocalStorage::write(&res, "login_response").ok();
+                                                EncryptedStorage::write(&res, &LOGIN_RESPONSE).ok();
                                             },
                                             Err(err) => {
                                                 log::error!("Unable to refresh the token. Login is required: {:#?}", err);
-                                                return Err(UserServiceStatus::LoginError(token_err))
+                                                return Err(token_err)
                                             },
                                         };
                                     }
@@ -66,70 +67,72 @@ pub async fn get_user() -> Result<String, UserServiceStatus> {
                                     // Retrying with new token.
                                     continue 'token
                                 },
-                                _ => Err(UserServiceStatus::LoginError(token_err)),
+                                _ => Err(token_err),
                             }
                         },
-                        _ => unreachable!(),
+                        _ => Err(err.into()),
                     },
                 }
             }
         },
-        Err(err) => Err(UserServiceStatus::StorageError(err)),
+        Err(err) => Err(err),
     }
 }
 
 /// Gets user id based on the current session.
 ///
 /// Will return an error if the current session is expired, or unabling to
-/// read data from the local storage.
-pub async fn get_user_id() -> Result<usize, UserServiceStatus> {
-    match LocalStorage::read::<SignInResponse>("login_response") {
-        Ok(res) => Ok(res.local_id.parse().expect("Unable to represent user's id as a number value.")),
-        Err(err) => Err(UserServiceStatus::StorageError(err)),    
+/// read data from the local storage. Ids, which are not numbers, are refused
+/// with [`AuthError::INVALID_USER_ID`].
+pub async fn get_user_id() -> SugarResult<usize> {
+    match EncryptedStorage::read::<SignInResponse>(&LOGIN_RESPONSE) {
+        Ok(res) => res.local_id.parse().map_err(|err| {
+            log::error!("Unable to represent user's id as a number value: {}", err);
+            SugarError::new(AuthError::INVALID_USER_ID).with_source(err)
+        }),
+        Err(err) => Err(err),    
     }
 }
 
 /// Gets current logged in user as User structure.
-pub async fn get_self() -> Result<User, UserServiceStatus> {
+pub async fn get_self() -> SugarResult<User> {
     let auth = FireAuth::new(FIREBASE_API_KEY.to_string());
 
-    match LocalStorage::read::<SignInResponse>("login_response") {
+    match EncryptedStorage::read::<SignInResponse>(&LOGIN_RESPONSE) {
         Ok(res) => {
             match auth.get_user_info(&res.id_token).await {
                 Ok(user) => Ok(user),
                 Err(err) => {
                     log::error!("Obtained error while trying to obtain user info: {}", err);
 
-                    Err(UserServiceStatus::LoginError(err.into()))
+                    Err(err.into())
                 }
             }
         },
-        Err(err) => Err(UserServiceStatus::StorageError(err)),    
+        Err(err) => Err(err),    
     }
 }
 
 /// Gets current user password based on the session.
 ///
 /// The returned password will be a hashed version.
-pub async fn get_current_pass() -> Result<String, UserServiceStatus> {
+pub async fn get_current_pass() -> SugarResult<Secret<String>> {
     match get_self().await {
-        Ok(user) => Ok(user.password_hash),
+        Ok(user) => Ok(Secret::new(user.password_hash)),
         Err(err) => Err(err),
     }
 }
 
 /// Makes a request to firebase for mail changing.
-#[tokio::main]
-pub async fn change_mail(mail: String) -> UserServiceStatus {
-    use crate::sugar::errors::SignupError;
+pub async fn change_mail(mail: String) -> SugarResult<()> {
     let auth = FireAuth::new(FIREBASE_API_KEY.to_string());
 
     // If the mail is the same, there is no need to load the server.  
     if mail == get_user().await.ok().unwrap_or_default() {
-        return UserServiceStatus::SignupError(SignupError::EMAIL_EXISTS);
+        return Err(AuthError::EMAIL_EXISTS.into());
     }
     
-    match LocalStorage::read::<SignInResponse>("login_response") {
+    match EncryptedStorage::read::<SignInResponse>(&LOGIN_RESPONSE) {
         Ok(mut res) => {
             // Trying to change email, since we have obtained the token.
             match auth.change_email(&res.id_token, &mail, true).await {
@@ -142,33 +145,19 @@ pub async fn change_mail(mail: String) -> UserServiceStatus {
                     res.email = mail;
 
                     // Writing changes.
-                    if let Err(err) = LocalStorage::write(&res, "login_response") {
-                        return UserServiceStatus::StorageError(err)
-                    }
+                    EncryptedStorage::write(&res, &LOGIN_RESPONSE)?;
 
                     // Writing new info about the updated user to the local storage for not
                     // overloading the server.
-                    match LocalStorage::write::<UpdateUser>(&secure_token, "secure_token") {
-                        Ok(_) => UserServiceStatus::NoError,
-                        Err(err) => UserServiceStatus::StorageError(err),
-                    }
+                    EncryptedStorage::write::<UpdateUser>(&secure_token, &SECURE_TOKEN).map(|_| ())
                 },
                 Err(err) => { 
-                    match &err {
-                        Error::User(s) => {
-                            if s.contains("EMAIL_EXISTS") {
-                                return UserServiceStatus::SignupError(SignupError::EMAIL_EXISTS)
-                            }
-                        }
-                        _ => (),
-                    }
-
                     log::error!("Obtained error while trying to change email: {}", err);
-                    UserServiceStatus::LoginError(err.into())
+                    Err(err.into())
                 }
             }
         },
-        Err(err) => UserServiceStatus::StorageError(err),
+        Err(err) => Err(err),
     }
 }
 
@@ -176,41 +165,35 @@ pub async fn change_mail(mail: String) -> UserServiceStatus {
 ///
 /// Input data is required, because the application does not physically owns user's
 /// password and only establishes communication between firebase and the mobile.
-#[tokio::main]
-pub async fn change_pass(old_pass: String, new_pass: String) -> UserServiceStatus {
+pub async fn change_pass(old_pass: Secret<String>, new_pass: Secret<String>) -> SugarResult<()> {
     // If we will obtain a proper login response, it would mean that old_pass was correct.
     if let Ok(mail) = get_user().await {
-        match super::usrsrv::service::login(mail, old_pass) {
-            UserServiceStatus::NoError => (), // Continuing.
-            status @ _ => return status, // Passing the status forward.
-        } 
+        // Passing the error forward.
+        super::usrsrv::service::login(mail, old_pass).await?;
     }
 
     let auth = FireAuth::new(FIREBASE_API_KEY.to_string());
 
     // Trying to change the password.
-    match LocalStorage::read::<SignInResponse>("login_response") {
+    match EncryptedStorage::read::<SignInResponse>(&LOGIN_RESPONSE) {
         Ok(res) => {
             // Trying to change email, since we have obtained the token.
-            match auth.change_password(&res.id_token, &new_pass, true).await {
+            match auth.change_password(&res.id_token, new_pass.expose(), true).await {
                 Ok(secure_token) => {
                     // If secured token is obtained, trying to save it for the future use.
                     // Email is updated by that point.
-                    log::info!("Changed password successfully to: {}", &new_pass);
+                    log::info!("Changed password successfully.");
 
                     // Writing new info about the updated user to the local storage for not
                     // overloading the server.
-                    match LocalStorage::write::<UpdateUser>(&secure_token, "secure_token") {
-                        Ok(_) => UserServiceStatus::NoError,
-                        Err(err) => UserServiceStatus::StorageError(err),
-                    }
+                    EncryptedStorage::write::<UpdateUser>(&secure_token, &SECURE_TOKEN).map(|_| ())
                 },
                 Err(err) => {
                     log::error!("Obtained error while trying to change the password: {}", err);
-                    UserServiceStatus::LoginError(err.into())
+                    Err(err.into())
                 }
             }
         },
-        Err(err) => UserServiceStatus::StorageError(err),
+        Err(err) => Err(err),
     }
 }
@@ -3,37 +3,56 @@
 //! This module handles all events related to user authentications, which includes logins,
 //! registration and modifications requested by users.
 
-use crate::sugar::errors::{InternalError, LoginError, SignupError, StorageError};
-
-/// Special wrapper around service status number, that will be returned to the front-end.
-#[repr(u8)]
-pub enum UserServiceStatus {
-    /// Status which indicates that everything went right.
-    NoError = 0,
-    /// Error, which comes from the application itself.
-    InternalError(InternalError),
-    /// Error was related to storage operation.
-    StorageError(StorageError),
-    /// Errors from the firebase server.
-    SignupError(SignupError),
-    LoginError(LoginError),
+use firebase_auth_sdk::api::{SignInResponse, SignUpResponse, UpdateUser};
+use crate::sugar::{errors::SugarResult, storage::{Document, EncryptedStorage, Namespace, StorageKey}};
+
+// All credential material is kept in the encrypted storage.
+
+/// Response of the last login, which keeps the current session.
+pub(crate) const LOGIN_RESPONSE: StorageKey = StorageKey::fixed(Namespace::Auth, "login_response");
+/// Response of the last signup.
+pub(crate) const SIGNUP_RESPONSE: StorageKey = StorageKey::fixed(Namespace::Auth, "signup_response");
+/// Token obtained after the last change of user's profile.
+pub(crate) const SECURE_TOKEN: StorageKey = StorageKey::fixed(Namespace::Auth, "secure_token");
+
+// Responses are stored exactly as firebase sends them. Files written before the versioning have
+// the same structure, so nothing has to be migrated yet.
+impl Document for SignInResponse {
+    const VERSION: u32 = 1;
+}
+
+impl Document for SignUpResponse {
+    const VERSION: u32 = 1;
+}
+
+impl Document for UpdateUser {
+    const VERSION: u32 = 1;
+}
+
+/// Encrypts credentials, which were stored in plaintext by older versions of the application.
+///
+/// Happens only once, plaintext credentials are refused afterwards.
+pub(crate) fn seal_credentials() -> SugarResult<()> {
+    EncryptedStorage::seal_plaintext::<SignInResponse>(&LOGIN_RESPONSE)?;
+    EncryptedStorage::seal_plaintext::<SignUpResponse>(&SIGNUP_RESPONSE)?;
+    EncryptedStorage::seal_plaintext::<UpdateUser>(&SECURE_TOKEN)?;
+    EncryptedStorage::finish_migration()
 }
 
 /// Module which contains all service functions related to user authentications.
 pub mod service {
     use firebase_auth_sdk::FireAuth;
-    use firebase_auth_sdk::api::{SignInResponse, SignUpResponse};
 
-    use super::UserServiceStatus;
+    use super::{LOGIN_RESPONSE, SIGNUP_RESPONSE};
     use crate::sugar::{
-        api::FIREBASE_API_KEY, errors::StorageError, storage::LocalStorage
+        api::FIREBASE_API_KEY, errors::{ErrorKind, StorageError, SugarError, SugarResult}, logging::Secret,
+        storage::EncryptedStorage,
     };
 
     /// Performs fast login via firebase token.
     ///
     /// This will omit the need of writing login credentials each time. Only works for logged in
     /// users. If the token is expired, user must login once more.
-    #[tokio::main]
     pub async fn fast_login() -> Option<String> {
         log::debug!("Encountered fast login request.");
 
@@ -50,8 +69,7 @@ pub mod service {
     ///
     /// Performs communication with firebase server and provides full login routine. Starts user's
     /// session if data will match.
-    #[tokio::main]
-    pub async fn login(mail: String, pass: String) -> UserServiceStatus { 
+    pub async fn login(mail: String, pass: Secret<String>) -> SugarResult<()> { 
         log::debug!("Encountered login request with data: {:#?}", (&mail, &pass));
         
         // Authentications service.
@@ -59,18 +77,18 @@ pub mod service {
 
         'main: loop {
             // Parsing an output from firebase server.
-            return match auth.sign_in_email(&mail, &pass, true).await {
+            return match auth.sign_in_email(&mail, pass.expose(), true).await {
                 Ok(res) => {
-                    log::debug!("Obtained response: {:#?}", res);
+                    log::debug!("Obtained response for user: {}", res.local_id);
 
                     // Writing newest response to the local storage for use later.
                     'inner: loop {
-                        if let Err(err) = LocalStorage::write::<SignInResponse>(&res, "login_response") {
-                            match err {
+                        if let Err(err) = EncryptedStorage::write(&res, &LOGIN_RESPONSE) {
+                            match err.kind() {
                                 // It is better to retry if our write was interrupted at that point.
-                                StorageError::INTERRUPTED => continue 'inner,
+                                ErrorKind::Storage(StorageError::INTERRUPTED) => continue 'inner,
                                 // Obtained bad data for some reason. Retrying the whole procedure.
-                                StorageError::NO_DATA | StorageError::BAD_DATA => continue 'main,
+                                ErrorKind::Storage(StorageError::NO_DATA | StorageError::BAD_DATA) => continue 'main,
                                 _ => (),
                             }
                         }
@@ -80,14 +98,13 @@ pub mod service {
                     }
 
                     log::info!("Login: OK");
-                    UserServiceStatus::NoError
+                    Ok(())
                 },
                 Err(err) => {
-                    let converted = err.clone().into();
-                    log::error!("Login error: {}", &converted);
-                    log::error!("Details: {}", &err);
+                    let err = SugarError::from(err);
+                    log::error!("Login error: {}", err.report());
 
-                    UserServiceStatus::LoginError(converted)
+                    Err(err)
                 },
             }
         }
@@ -98,8 +115,7 @@ pub mod service {
     ///
     /// With data provided, creates new 'Sugar' user, while checking if such user is not already
     /// exist.
-    #[tokio::main]
-    pub async fn signup(mail: String, pass: String, conf: String) -> UserServiceStatus {
+    pub async fn signup(mail: String, pass: Secret<String>, conf: Secret<String>) -> SugarResult<()> {
         log::debug!("Encountered signup request with data: {:#?}", (&mail, &pass, &conf));
         
         // Authentications service.
@@ -107,18 +123,18 @@ pub mod service {
 
         'main: loop {
             // Parsing an output from firebase server.
-            return match auth.sign_up_email(&mail, &pass, true).await {
+            return match auth.sign_up_email(&mail, pass.expose(), true).await {
                 Ok(res) => {
-                    log::debug!("Obtained response: {:#?}", res);
+                    log::debug!("Obtained response for user: {}", res.local_id);
 
                     // Writing newest response to the local storage for use later.
                     'inner: loop {
-                        if let Err(err) = LocalStorage::write::<SignUpResponse>(&res, "signup_response") {
-                            match err {
+                        if let Err(err) = EncryptedStorage::write(&res, &SIGNUP_RESPONSE) {
+                            match err.kind() {
                                 // It is better to retry if our write was interrupted at that point.
-                                StorageError::INTERRUPTED => continue 'inner,
+                                ErrorKind::Storage(StorageError::INTERRUPTED) => continue 'inner,
                                 // Obtained bad data for some reason. Retrying the whole procedure.
-                                StorageError::NO_DATA | StorageError::BAD_DATA => continue 'main,
+                                ErrorKind::Storage(StorageError::NO_DATA | StorageError::BAD_DATA) => continue 'main,
                                 _ => (),
                             }
                         }
@@ -128,44 +144,29 @@ pub mod service {
                     }
 
                     log::info!("Signup: OK");
-                    UserServiceStatus::NoError
+                    Ok(())
                 },
                 Err(err) => {
-                    let converted = err.clone().into();
-                    log::error!("Signup error: {}", &converted);
-                    log::error!("Details: {}", &err);
+                    let err = SugarError::from(err);
+                    log::error!("Signup error: {}", err.report());
 
-                    UserServiceStatus::SignupError(converted)
+                    Err(err)
                 },
             }
         }
     }
 
     /// Logs out from the current session, while also deleting the last session's trace.
-    #[tokio::main]
-    pub async fn logout() -> UserServiceStatus {
+    pub async fn logout() -> SugarResult<()> {
         log::debug!("Encountered logout request."); 
 
         // Just deleting the last sign in responce will prevent auto login.
-        match LocalStorage::remove("login_response") {
+        match EncryptedStorage::remove(&LOGIN_RESPONSE) {
             Ok(_) => {
                 log::info!("Successfully logged out.");
-                UserServiceStatus::NoError
+                Ok(())
             },
-            Err(err) => UserServiceStatus::StorageError(err),
-        }
-    }
-}
-
-// Formatting each error as a status code.
-impl Into<u8> for UserServiceStatus {
-    fn into(self) -> u8 {
-        match self {
-            Self::NoError => 0,
-            Self::InternalError(err) => err as u8,
-            Self::SignupError(err) => err as u8,
-            Self::LoginError(err) => err as u8,
-            Self::StorageError(err) => err as u8,
+            Err(err) => Err(err),
         }
     }
 }
@@ -0,0 +1,280 @@
+//! Actor which owns the bridge of the current connection.
+//!
+//! Java calls never touch the bridge directly. Instead, they send messages to the actor, which
+//! answers them right away, while the bridge itself runs in a separate task. This way no call ever
+//! waits for the connection to close.
+
+use std::sync::Arc;
+use tokio::{sync::{mpsc, oneshot, watch}, task::{JoinError, JoinHandle}};
+
+use super::{
+    bridge::{Bridge, BridgeError, BridgeResult},
+    client::DaemonClient,
+    lifecycle::LifecycleState,
+    stats::{BridgeStats, StatsSnapshot},
+    transport::{Transport, UsbTransport},
+};
+use crate::sugar::{
+    crash::{self, BridgeState},
+    errors::{InternalError, SugarError, SugarResult}, events::{self, Event}, runtime, storage::Database,
+};
+
+const ACTOR_BUFFER_SIZE: usize = 16;
+
+/// Messages handled by the actor. Each of them carries a channel for the answer.
+enum ActorMessage<T: Transport> {
+    /// Starts the communication through the provided bridge. Boxed, since it outweighs the rest.
+    Connect(Box<Bridge<T>>, oneshot::Sender<SugarResult<()>>),
+    /// Requests the current bridge to close.
+    Disconnect(oneshot::Sender<SugarResult<()>>),
+    /// Returns information about the connected device.
+    Info(oneshot::Sender<String>),
+    /// Returns a client of the current bridge.
+    Client(oneshot::Sender<Option<DaemonClient>>),
+    /// Returns the identifier of the connected machine.
+    Machine(oneshot::Sender<Option<String>>),
+    /// Returns the state of the current connection.
+    Report(oneshot::Sender<Option<ConnectionReport>>),
+}
+
+/// State of the current connection, e.g. for bug reports.
+#[derive(Debug, Clone)]
+pub struct ConnectionReport {
+    /// Human readable information about the device.
+    pub info: String,
+    pub machine: String,
+    pub bridge_id: u64,
+    /// USB device descriptor, if the bridge runs over USB.
+    pub descriptor: Option<String>,
+    /// Record of the session in the database.
+    pub session: Option<i64>,
+    pub state: LifecycleState,
+    pub stats: StatsSnapshot,
+}
+
+/// Everything which can wake up the actor.
+enum Wakeup<T: Transport> {
+    Message(ActorMessage<T>),
+    Closed(Result<BridgeResult<()>, JoinError>),
+}
+
+/// Bridge which is currently running.
+struct Connection {
+    client: DaemonClient,
+    info: String,
+    machine: String,
+    bridge_id: u64,
+    descriptor: Option<String>,
+    /// Record of the session in the database, if it could be written.
+    session: Option<i64>,
+    state: watch::Receiver<LifecycleState>,
+    stats: Arc<BridgeStats>,
+    task: JoinHandle<BridgeResult<()>>,
+}
+
+/// Actor which owns the running bridge.
+struct BridgeActor<T: Transport> {
+    rx: mpsc::Receiver<ActorMessage<T>>,
+    connection: Option<Connection>,
+}
+
+impl<T: Transport> BridgeActor<T> {
+    async fn run(mut self) {
+        loop {
+            let event = tokio::select! {
+                msg = self.rx.recv() => match msg {
+                    Some(msg) => Wakeup::Message(msg),
+                    None => break,
+                },
+                result = Self::closed(&mut self.connection) => Wakeup::Closed(result),
+            };
+
+            match event {
+                Wakeup::Message(msg) => self.handle(msg).await,
+                Wakeup::Closed(result) => {
+                    let error = match result {
+                        Ok(Ok(_)) => {
+                            log::info!("Bridge has finished the communication.");
+                            None
+                        },
+                        Ok(Err(err)) => {
+                            log::error!("Bridge has failed: {}", err.report());
+                            Some(err)
+                        },
+                        Err(err) => {
+                            log::error!("Bridge task has failed: {}", err);
+                            Some(SugarError::new(InternalError::TOKIO_THREAD_ERROR).with_source(err))
+                        },
+                    };
+                    crash::track_bridge(None);
+                    if let Some(id) = self.connection.take().and_then(|connection| connection.session) {
+                        let error = error.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = Database::run(move |db| db.sessions().finish(id, error.as_ref())).await {
+                                log::warn!("Unable to record the end of the session: {}", err.report());
+                            }
+                        });
+                    }
+                    events::emit(Event::Disconnected(error));
+                },
+            }
+        }
+
+        log::info!("Bridge actor is stopped.");
+    }
+
+    /// Waits until the running bridge is closed. Never ends if there is no bridge.
+    async fn closed(connection: &mut Option<Connection>) -> Result<BridgeResult<()>, JoinError> {
+        match connection {
+            Some(connection) => (&mut connection.task).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn handle(&mut self, msg: ActorMessage<T>) {
+        match msg {
+            ActorMessage::Connect(bridge, reply) => {
+                reply.send(self.connect(*bridge).await).ok();
+            },
+            ActorMessage::Disconnect(reply) => {
+                let result = match &self.connection {
+                    Some(connection) => connection.client.disconnect().await
+                        .map_err(|err| SugarError::new(BridgeError::BridgeClosed).with_source(err)),
+                    None => Ok(()),
+                };
+                reply.send(result).ok();
+            },
+            ActorMessage::Info(reply) => {
+                let info = self.connection.as_ref()
+                    .map(|connection| connection.info.clone())
+                    .unwrap_or_default();
+                reply.send(info).ok();
+            },
+            ActorMessage::Client(reply) => {
+                reply.send(self.connection.as_ref().map(|connection| connection.client.clone())).ok();
+            },
+            ActorMessage::Machine(reply) => {
+                reply.send(self.connection.as_ref().map(|connection| connection.machine.clone())).ok();
+            },
+            ActorMessage::Report(reply) => {
+                let report = self.connection.as_ref().map(|connection| ConnectionReport {
+                    info: connection.info.clone(),
+                    machine: connection.machine.clone(),
+                    bridge_id: connection.bridge_id,
+                    descriptor: connection.descriptor.clone(),
+                    session: connection.session,
+                    state: *connection.state.borrow(),
+                    stats: connection.stats.snapshot(),
+                });
+                reply.send(report).ok();
+            },
+        }
+    }
+
+    async fn connect(&mut self, bridge: Bridge<T>) -> SugarResult<()> {
+        if self.connection.is_some() {
+            log::warn!("Refusing the new bridge, since the previous one is still running.");
+            return Err(BridgeError::AlreadyConnected.into())
+        }
+
+        let client = bridge.client();
+        let stats = bridge.stats();
+        let state = bridge.subscribe();
+        let descriptor = bridge.dev_desc.as_ref().map(|desc| format!("{:#?}", desc));
+        let (info, machine) = (bridge.device.info(), bridge.device.machine());
+
+        // History is nice to have, so the connection is not refused without it.
+        let bridge_id = bridge.id();
+        let record = machine.clone();
+        let session = Database::run(move |db| db.sessions().start(&record, bridge_id)).await
+            .map_err(|err| log::warn!("Unable to record the session: {}", err.report()))
+            .ok();
+
+        crash::track_bridge(Some(BridgeState {
+            info: info.clone(),
+            machine: machine.clone(),
+            bridge_id,
+            stats: stats.clone(),
+        }));
+
+        let task = tokio::spawn(async move {
+            let mut bridge = bridge;
+            bridge.connect().await
+        });
+
+        self.connection.replace(Connection { client, info, machine, bridge_id, descriptor, session, state, stats, task });
+        Ok(())
+    }
+}
+
+/// Handle to the actor, which owns the bridge of the current connection.
+///
+/// All clones communicate with the same actor. The actor is stopped once all handles are dropped.
+pub struct BridgeHandle<T: Transport = UsbTransport> {
+    tx: mpsc::Sender<ActorMessage<T>>,
+}
+
+impl<T: Transport> Clone for BridgeHandle<T> {
+    fn clone(&self) -> Self {
+        Self { tx: self.tx.clone() }
+    }
+}
+
+impl<T: Transport> BridgeHandle<T> {
+    /// Spawns a new actor on the backend's runtime.
+    pub fn spawn() -> Self {
+        let (tx, rx) = mpsc::channel(ACTOR_BUFFER_SIZE);
+        runtime::spawn(BridgeActor { rx, connection: None }.run());
+
+        Self { tx }
+    }
+
+    /// Starts the communication through the provided bridge.
+    ///
+    /// Returns as soon as the bridge is running, without waiting for the handshake. Only one
+    /// bridge can run at a time.
+    pub async fn connect(&self, bridge: Bridge<T>) -> SugarResult<()> {
+        self.ask(|reply| ActorMessage::Connect(Box::new(bridge), reply)).await
+            .unwrap_or(Err(InternalError::TOKIO_THREAD_ERROR.into()))
+    }
+
+    /// Requests the current bridge to close, without waiting for it.
+    pub async fn disconnect(&self) -> SugarResult<()> {
+        self.ask(ActorMessage::Disconnect).await
+            .unwrap_or(Err(InternalError::TOKIO_THREAD_ERROR.into()))
+    }
+
+    /// Returns information about the connected device, or an empty string if not connected.
+    pub async fn info(&self) -> String {
+        self.ask(ActorMessage::Info).await
+            .unwrap_or_default()
+    }
+
+    /// Returns a client of the current bridge, if any.
+    pub async fn client(&self) -> Option<DaemonClient> {
+        self.ask(ActorMessage::Client).await
+            .flatten()
+    }
+
+    /// Returns the identifier of the connected machine, if connected.
+    pub async fn machine(&self) -> Option<String> {
+        self.ask(ActorMessage::Machine).await
+            .flatten()
+    }
+
+    /// Returns the state of the current connection, if connected.
+    pub async fn report(&self) -> Option<ConnectionReport> {
+        self.ask(ActorMessage::Report).await
+            .flatten()
+    }
+
+    async fn ask<R>(&self, msg: impl FnOnce(oneshot::Sender<R>) -> ActorMessage<T>) -> Option<R> {
+        let (reply, rx) = oneshot::channel();
+        if self.tx.send(msg(reply)).await.is_err() {
+            log::error!("Bridge actor is stopped.");
+            return None
+        }
+
+        rx.await.ok()
+    }
+}
@@ -3,63 +3,76 @@
 //! This module handles all bridge related tasks, that includes connecting, disconnecting and
 //! handling all commands that are coming from the target device and from the mobile device. 
 
-use std::sync::Arc;
-use std::thread;
-use tokio::sync::{Mutex, mpsc::{self, Sender}};
-use rusb::{Context, DeviceDescriptor, DeviceHandle, UsbContext};
-use lazy_static::lazy_static;
+use std::{collections::VecDeque, sync::Arc};
+use tokio::sync::{Mutex, mpsc::{self, Receiver, Sender}, watch};
+use rusb::{Context, DeviceDescriptor, UsbContext};
 
-use crate::sugar::parse::SugarParser;
+use crate::sugar::{errors::{ErrorKind, InternalError, SugarError, SugarResult}, events::{self, Event}, parse::SugarParser};
 use super::{
-    buf::{Buffer, USBV2Buf},
+    buf::{self, Buffer, USBV2Buf},
+    client::{BridgeMessage, ClientError, DaemonClient, PendingRequests, Request},
     cmd::DaemonCommand,
+    hotplug::HotplugMonitor,
+    io::{self, Writer},
+    lifecycle::{Lifecycle, LifecycleState},
+    recovery::ReconnectPolicy,
+    session::{HandshakeError, SessionInfo},
+    stats::BridgeStats,
+    transport::{Transport, UsbTransport},
 };
 
-pub type BridgeResult<T> = Result<T, BridgeError>;
+pub use crate::sugar::errors::BridgeError;
+
+pub type BridgeResult<T> = SugarResult<T>;
 type DataBuffer = Arc<Mutex<Box<dyn Buffer>>>;
-type Device = Arc<Mutex<DeviceHandle<rusb::Context>>>;
-type Tx = Option<Sender<DaemonCommand>>;
+type Device<T> = Arc<T>;
 
 const CHANNEL_BUFFER_SIZE: usize = 1024;
-
-lazy_static! {
-    /// Static reference to a bridge connection.
-    static ref BRIDGE_REF: Arc<Mutex<Option<Bridge>>> = Arc::new(Mutex::new(None));
-}
-
-/// Custom error type for bridge communication.
-#[derive(Debug)]
-pub enum BridgeError {
-    /// Appears when trying to close a bridge, which is yet not initialized fully.
-    BridgeNotReady,
-    /// Appears when the target device has refused a connection. Can happen when trying to connect
-    /// to a device when it is already is connected or when the bridge ID does not match.
-    ConnectionRefused,
-    /// Connection to the device has timed out.
-    ConnectionTimeout,
-    /// Unable to send any data to the bridge since it is closed.
-    BridgeClosed,
-    /// Unable to setup a new libusb context
-    ContextError,
-    /// Unable to read a usb device by a file descriptor.
-    FileDescriptorError
-}
+/// Amount of corrupted commands in a row after which the bridge gives up on retransmission.
+pub const MAX_RETRIES: u8 = 5;
 
 /// A custom structure that is being created on each communication between target devices.
 ///
 /// Each new connection a new bridge is being transformed, while the daemon also expects only one
 /// connection bridge with an exact ID, already known for it. All other bridges with a wrong id
 /// won't be able to connect.
-pub struct Bridge {
-    _id: usize,
-    tx: Tx,
+///
+/// The bridge is generic over the [`Transport`] it communicates through, which is a USB bus by
+/// default.
+pub struct Bridge<T: Transport = UsbTransport> {
+    _id: u64,
+    tx: Sender<BridgeMessage>,
+    /// Taken by the running bridge, therefore available only before the connection.
+    rx: Option<Receiver<BridgeMessage>>,
+    /// Requests sent to the target, which wait for the answer.
+    pub(crate) pending: PendingRequests,
+    /// Requests obtained before the session is negotiated, which are sent once it is.
+    held: VecDeque<Request>,
+    /// Shared by all clients, since the target keeps only one selection.
+    selection: Arc<Mutex<()>>,
+    /// Negotiated session, available after a successful handshake.
+    session: Option<SessionInfo>,
+    /// Last command sent to the target, which is sent again on a retransmission request.
+    last: Option<DaemonCommand>,
+    /// Amount of corrupted commands obtained in a row.
+    pub(crate) retries: u8,
+    /// Traffic counters, shared with the observers of the bridge.
+    stats: Arc<BridgeStats>,
+    /// Performs all writes, available once connected.
+    writer: Option<Writer>,
+    /// Decides whether a failed connection is established once more.
+    reconnect: ReconnectPolicy,
+    /// State of the bridge, observed by its subscribers.
+    lifecycle: Lifecycle,
+    /// Fails the bridge once the device is detached, if hotplug is available.
+    hotplug: Option<HotplugMonitor>,
 
     pub buf: DataBuffer,
-    pub device: Device,
-    pub dev_desc: DeviceDescriptor,
+    pub device: Device<T>,
+    pub dev_desc: Option<DeviceDescriptor>,
 }
 
-impl Bridge {
+impl Bridge<UsbTransport> {
     /// Initializes the bridge and finds a proper device, which is connected to the USB port.
     ///
     /// This method will return a new bridge, which is not yet connected, but ready to do so. If
@@ -67,216 +80,668 @@ impl Bridge {
     /// provided from the Java interface, because only Java android code has permissions to obtain
     /// the device.
     ///
+    /// Bulk endpoints are discovered from the active configuration and their interface is claimed
+    /// until the bridge is dropped. Fails with [`BridgeError::NoSuitableInterface`] if there is no
+    /// such interface. The buffer is picked from the link speed, so SuperSpeed links are read with
+    /// larger transfers.
+    ///
     /// # Warn
     ///
     /// This method does not connect to the target right away, but only obtains all required
     /// information for a proper communication.
-    pub fn new(id: usize, fd: i32) -> BridgeResult<Self> {
+    pub fn new(id: u64, fd: i32) -> BridgeResult<Self> {
         log::info!("Creating a new communication bridge");
         #[cfg(debug_assertions)]
         rusb::disable_device_discovery().map_err(|err| {
             log::error!("Bridge error: Unable to disable device discovery: {}", err);
-            BridgeError::ContextError
+            SugarError::new(BridgeError::ContextError).with_source(err)
         })?;    // This is required since we already have a file desriptor.
 
         // Creating a new libusb context.
         let mut context = Context::new().map_err(|err| {
             log::error!("Bridge error: Unable to create a new context: {}", err);
-            BridgeError::ContextError
+            SugarError::new(BridgeError::ContextError).with_source(err)
         })?;    // Clear libusb context.
 
         context.set_log_level(rusb::LogLevel::Debug);
         // Trying to obtain a device handle from the provided file descriptor.
         let devh = unsafe { context.open_device_with_fd(fd).map_err(|err| { 
             log::error!("Bridge error: Unable to open a device with provided file descriptor: {}", err);
-            BridgeError::FileDescriptorError 
+            SugarError::new(BridgeError::FileDescriptorError).with_source(err)
         })}?;
 
         let devd = devh.device();
         let devdc = devd.device_descriptor().map_err(|err| { 
             log::error!("Bridge error: Unable to obtain the device descriptor: {}", err);
-            BridgeError::FileDescriptorError
+            SugarError::new(BridgeError::FileDescriptorError).with_source(err)
         })?;
 
         log::debug!("Found device: Bus: {:03}, Addr: {:03}, ID: {:04x}:{:04x}\n
             Max supported USB version: {},", 
             devd.bus_number(), devd.address(), devdc.vendor_id(), devdc.product_id(), devdc.usb_version());
 
-        let handle_arc = Arc::new(Mutex::new(devh));
+        let transport = UsbTransport::open(devh)?;
+        let buf = buf::select(devd.speed(), devdc.usb_version(), transport.endpoints().read_packet_size);
+
+        let mut bridge = Self::with_transport(id, transport);
+        bridge.buf = Arc::new(Mutex::new(buf));
+        bridge.hotplug = HotplugMonitor::watch(&devd, bridge.tx.clone());
+        bridge.dev_desc.replace(devdc);
 
-        return Ok(Self {
+        Ok(bridge)
+    }
+}
+
+impl<T: Transport> Bridge<T> {
+    /// Creates a new bridge over an arbitrary transport.
+    ///
+    /// Just like [`Bridge::new`], the bridge is not connected right away. Since the transport is
+    /// not necessarily a USB device, no device descriptor is available.
+    pub fn with_transport(id: u64, transport: T) -> Self {
+        let (tx, rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+
+        Self {
             _id: id,
-            tx: None,
+            tx,
+            rx: Some(rx),
+            pending: PendingRequests::default(),
+            held: VecDeque::new(),
+            selection: Arc::new(Mutex::new(())),
+            session: None,
+            last: None,
+            retries: 0,
+            stats: Arc::default(),
+            writer: None,
+            reconnect: ReconnectPolicy::default(),
+            lifecycle: Lifecycle::default(),
+            hotplug: None,
             buf: Arc::new(Mutex::new(Box::new(USBV2Buf::default()))),
-            device: handle_arc,
-            dev_desc: devdc,
-        });
+            device: Arc::new(transport),
+            dev_desc: None,
+        }
     }
+
+    /// Creates a new client of this bridge.
+    ///
+    /// Clients can be created before the connection. Their requests are held until the session is
+    /// negotiated, and sent to the target afterwards.
+    pub fn client(&self) -> DaemonClient {
+        DaemonClient::new(self.tx.clone(), self.selection.clone())
+    }
+
+    /// Replaces the policy of reconnection, which is used once the connection fails.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect = policy;
+    }
+
     /// Connects the existing bridge to start the communication.
     ///
     /// While connected, listens to any upcoming data from the target machine as well as from the
-    /// host device. 
+    /// host device. If the connection fails, it is established once more according to the
+    /// [`ReconnectPolicy`], while the clients of the bridge stay valid.
     pub async fn connect(&mut self) -> BridgeResult<()> {
         log::info!("Connecting to the bridge...");
-        let cpus = thread::available_parallelism().unwrap();
-        let (tx, mut rx) = mpsc::channel::<DaemonCommand>(CHANNEL_BUFFER_SIZE);
-        log::info!("Available threads: {}", cpus);
-
-        for i in 0..cpus.into() {
-            log::info!("Spawning listener thread: {}", i);
-            let buf_lock = self.buf.clone();
-            let device_lock = self.device.clone();
-            let tx = tx.clone();
-
-            tokio::spawn(async move {
-                loop {
-                    let mut buffer = buf_lock.lock().await;
-                    let mut device = device_lock.lock().await;
-
-                    match buffer.read(&mut *device) {
-                        Ok(bytes) => {
-                            log::info!("Thread ({}): Obtained oncoming data: {}", i, bytes.escape_ascii());
-                            if let Err(tx_err) = tx.send(DaemonCommand::new(bytes)).await {
-                                log::error!("Thread ({}): Unable to send data: {}, channel is closed, aborting...", i, tx_err);
-                                break;
-                            }
-                        },
-                        Err(buf_err) => {
-                            match buf_err {
-                                rusb::Error::InvalidParam => continue,
-                                _ => log::error!("Thread ({}): Error while reading the data from the USB bus: {:#?}", i, buf_err),
-                            }
-                        },
-                    };
-                }
-            });
+        // The bridge can only be connected once.
+        self.lifecycle.advance(LifecycleState::Opening).map_err(|err| {
+            log::error!("Unable to connect the bridge: {}", err);
+            SugarError::new(BridgeError::BridgeClosed).with_source(err)
+        })?;
+        let mut rx = self.rx.take().ok_or(BridgeError::BridgeClosed)?;
+        self.drop_stale(&mut rx);
+        let mut attempt = 0;
+
+        loop {
+            let result = self.run(&mut rx).await;
+            // Attempts are only counted in a row, so a negotiated session starts them over.
+            if self.session.is_some() {
+                attempt = 0;
+            }
+
+            let err = match result {
+                Ok(_) => break,
+                Err(err) => err,
+            };
+            let Some(delay) = self.reconnect.delay(attempt, &err) else {
+                log::info!("Bridge is closed.");
+                // Held requests survive reconnections, but they are never sent from now on.
+                self.held.clear();
+                self.enter(LifecycleState::Failed);
+                return Err(err)
+            };
+
+            attempt += 1;
+            log::warn!("Connection has failed, reconnecting in {:?}, attempt {}/{}: {}", delay, attempt, self.reconnect.max_attempts, err.report());
+            events::emit(Event::Reconnecting { attempt, delay, error: err });
+            self.enter(LifecycleState::Opening);
+            tokio::time::sleep(delay).await;
+        }
+
+        log::info!("Bridge is closed.");
+        self.held.clear();
+        self.enter(LifecycleState::Closed);
+        Ok(())
+    }
+
+    /// Drops failures queued while the bridge was idle, e.g. by a hotplug event, since they concern
+    /// no connection yet. Requests of the clients are kept.
+    fn drop_stale(&self, rx: &mut Receiver<BridgeMessage>) {
+        let queued: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+
+        for msg in queued {
+            match msg {
+                BridgeMessage::Failed(err) => log::warn!("Dropping the failure obtained before the connection. {}", err.report()),
+                msg => if self.tx.try_send(msg).is_err() {
+                    log::error!("Unable to keep the message obtained before the connection.");
+                },
+            }
         }
+    }
+
+    /// Performs a single connection, from the handshake until the bridge is closed.
+    async fn run(&mut self, rx: &mut Receiver<BridgeMessage>) -> BridgeResult<()> {
+        // The daemon resumes the session of the same bridge ID, but it is negotiated once more.
+        self.session = None;
+        self.last = None;
+        self.retries = 0;
+
+        // Blocking I/O is done by dedicated threads, so the runtime stays free.
+        let threads = io::spawn(self.device.clone(), self.buf.clone(), self.tx.clone(), self.stats.clone());
+        let (reader, writer) = threads.map_err(|err| {
+            log::error!("Unable to spawn the reader and the writer: {}", err);
+            SugarError::new(InternalError::TOKIO_THREAD_ERROR).with_source(err)
+        })?;
+        self.writer.replace(writer);
 
         log::info!("Connection established. Writing the initialization command..."); 
-        // Sending the init command right away. It must always be Ok since we havent even closed
-        // the channel yet.
-        tx.send(DaemonCommand::init(self._id)).await.ok();
-        self.tx.replace(tx); // after this replacement, it is possible to disconnect. 
+        // Sending the init command right away.
+        if let Err(err) = self.send(DaemonCommand::init(self._id)).await {
+            self.writer.take();
+            reader.stop().await;
+            return Err(err)
+        }
+        self.enter(LifecycleState::Handshaking);
+
+        let result = self.listen(rx).await;
+        // Dropping all pending requests, so that their clients will know the bridge is closed.
+        self.pending.clear();
+        self.writer.take();
 
+        // The transport must be released before the next attempt starts reading it.
+        match result {
+            Ok(_) => drop(reader),
+            Err(_) => reader.stop().await,
+        }
+        result
+    }
+
+    /// Handles all messages until the bridge is closed.
+    async fn listen(&mut self, rx: &mut Receiver<BridgeMessage>) -> BridgeResult<()> {
         let mut cmds = 0;
 
         // All obtained bytes are then parsed and sent to the front-end or to the target device.
-        while let Some(bytes) = rx.recv().await {
+        while let Some(msg) = rx.recv().await {
             use crate::sugar::parse::ParseOutput;
+
+            let bytes = match msg {
+                BridgeMessage::Command(bytes) => {
+                    self.stats.command();
+                    bytes
+                },
+                // The target does not handle requests before it has acknowledged the handshake.
+                BridgeMessage::Request(request) if self.session.is_none() => {
+                    self.held.push_back(request);
+                    continue
+                },
+                BridgeMessage::Request(request) => {
+                    self.request(request).await;
+                    continue
+                },
+                BridgeMessage::Failed(err) => {
+                    log::error!("Transport has failed. {}", err.report());
+                    return Err(err)
+                },
+            };
+
             match SugarParser::parse_byte_code(self, bytes).await {
-                ParseOutput::Success => { log::info!("Successfully parsed request number: {}", cmds); cmds += 1; },
+                ParseOutput::Success => {
+                    log::info!("Successfully parsed request number: {}", cmds);
+                    cmds += 1;
+                    self.release_held().await;
+                },
                 ParseOutput::Empty => log::warn!("Obtained empty command. Ignoring..."),
-                ParseOutput::Checksum => log::error!("Obtained message has a wrong checksum. Sending a retry request."),
+                ParseOutput::Checksum => {
+                    self.stats.corrupted();
+                    log::error!("Obtained message has a wrong checksum. Retry request: {}/{}.", self.retries, MAX_RETRIES);
+                    if self.retries >= MAX_RETRIES {
+                        return Err(BridgeError::RetryLimitExceeded.into())
+                    }
+                },
                 ParseOutput::UnparsableTokens => log::error!("Obtained unparsable command. Please check the connection."),
+                ParseOutput::Protocol(err) => log::error!("Obtained command was refused. {}", err),
+                ParseOutput::Refused => {
+                    log::error!("Target device has refused the connection.");
+                    return Err(BridgeError::ConnectionRefused.into())
+                },
+                ParseOutput::Handshake(err) => {
+                    log::error!("Unable to negotiate the session. {}", err);
+                    return Err(BridgeError::IncompatibleTarget.into())
+                },
+                ParseOutput::Shutdown => {
+                    log::info!("Shutting down the bridge.");
+                    break
+                },
             }
         }
 
-        log::info!("Bridge is closed.");
+        // Already entered once the shutdown was requested, unless the channel is gone.
+        self.enter(LifecycleState::Draining);
         Ok(()) // A properly closed bridge.
     }
 
+    /// Sends the requests held before the handshake, once the session is negotiated.
+    async fn release_held(&mut self) {
+        if self.session.is_none() {
+            return
+        }
+
+        while let Some(request) = self.held.pop_front() {
+            if !request.is_abandoned() {
+                self.request(request).await;
+            }
+        }
+    }
+
+    /// Sends the client's request to the target and waits for the answer.
+    async fn request(&mut self, request: Request) {
+        match self.send(request.command.clone()).await {
+            Ok(_) => self.pending.push(request),
+            Err(err) if err.kind() == ErrorKind::Bridge(BridgeError::DataTooLarge) => request.fail(ClientError::TooLarge),
+            Err(_) => request.fail(ClientError::BridgeClosed),
+        }
+    }
+
+    /// Returns the ID of the bridge, which is known to the daemon.
+    pub fn id(&self) -> u64 {
+        self._id
+    }
+
+    /// Returns the traffic counters, which stay available after the bridge is moved to its task.
+    pub fn stats(&self) -> Arc<BridgeStats> {
+        self.stats.clone()
+    }
+
+    /// Returns the current state of the bridge.
+    pub fn state(&self) -> LifecycleState {
+        self.lifecycle.state()
+    }
+
+    /// Returns a receiver of the state changes, which stays valid after the bridge is moved to its
+    /// task.
+    pub fn subscribe(&self) -> watch::Receiver<LifecycleState> {
+        self.lifecycle.subscribe()
+    }
+
+    /// Moves the bridge to the state. Refused transitions are only logged, since the bridge itself
+    /// decides about all of them.
+    pub(crate) fn enter(&self, state: LifecycleState) {
+        if let Err(err) = self.lifecycle.advance(state) {
+            log::error!("{}", err);
+        }
+    }
+
+    /// Returns the negotiated session, if the handshake is done.
+    pub fn session(&self) -> Option<&SessionInfo> {
+        self.session.as_ref()
+    }
+
+    /// Validates the target's answer to the handshake and stores the negotiated session.
+    pub(crate) fn establish(&mut self, data: &[u8]) -> Result<SessionInfo, HandshakeError> {
+        let answer = SessionInfo::decode(data)?;
+        let session = SessionInfo::offer(self._id).accept(&answer)?;
+
+        log::info!("Session negotiated: version {}, capabilities: {:#010x}", session.version, session.capabilities.0);
+        self.session.replace(session);
+        self.stats.negotiated(session);
+        self.enter(LifecycleState::Connected);
+        events::emit(Event::Connected);
+        Ok(session)
+    }
+
+    /// Sends the command to the target device right away.
+    ///
+    /// The command is remembered, so it can be sent once more if the target will request a
+    /// retransmission.
+    pub async fn send(&mut self, cmd: DaemonCommand) -> BridgeResult<usize> {
+        let len = self.transmit(&cmd).await?;
+        self.last.replace(cmd);
+        Ok(len)
+    }
+
+    /// Sends the last command once more.
+    pub(crate) async fn resend(&mut self) -> BridgeResult<usize> {
+        match self.last.clone() {
+            Some(cmd) => {
+                self.stats.retransmitted();
+                self.transmit(&cmd).await
+            },
+            None => Ok(0),
+        }
+    }
+
+    /// Writes the command to the transport without remembering it.
+    pub(crate) async fn transmit(&self, cmd: &DaemonCommand) -> BridgeResult<usize> {
+        let writer = self.writer.as_ref().ok_or(BridgeError::BridgeNotReady)?;
+        let code = cmd.byte_code();
+
+        if code.len() > self.buf.lock().await.max_write() {
+            log::error!("Unable to write {} bytes to the target device at once.", code.len());
+            return Err(BridgeError::DataTooLarge.into())
+        }
+
+        let len = writer.write(code.to_vec()).await.map_err(|err| {
+            log::error!("Unable to write data to the target device: {}", err);
+            SugarError::from(err)
+        })?;
+
+        self.stats.sent(len);
+        Ok(len)
+    }
+
     /// Disconnects the communication by sending a shutdown command.
     ///
-    /// If the Tx is not ready yet, halts until it appears.
+    /// Fails if the bridge was never connected.
     pub async fn disconnect(&self) -> BridgeResult<()> {
-        if let Some(tx) = &self.tx {
-            if let Err(tx_err) = tx.send(DaemonCommand::user_disconnect()).await {
-                log::error!("Unable to disconnect the bridge: {}", tx_err);
-                return Err(BridgeError::BridgeClosed);
-            }
+        if self.rx.is_some() {
+            return Err(BridgeError::BridgeNotReady.into())
+        }
 
-            Ok(())
-        } else { Err(BridgeError::BridgeNotReady) }
+        self.client().disconnect().await.map_err(|err| {
+            log::error!("Unable to disconnect the bridge: {}", err);
+            SugarError::new(BridgeError::BridgeClosed).with_source(err)
+        })
     }
 }
 
 pub mod service {
-    use super::{Bridge, BridgeError, BRIDGE_REF};
-
-    #[tokio::main]
-    pub async fn connect(fd: i32) -> ConnectionStatus {
-        let id = rand::random();
-
-        match Bridge::new(id, fd) {
-            Ok(bridge) => {
-                BRIDGE_REF.lock().await.replace(bridge);
-                if let Err(_err) = BRIDGE_REF.lock().await.as_mut().unwrap().connect().await {
-                    match _err {
-                        BridgeError::BridgeClosed => ConnectionStatus::BridgeClosed,
-                        BridgeError::ConnectionRefused => ConnectionStatus::Refused,
-                        BridgeError::ConnectionTimeout => ConnectionStatus::Timeout,
-                        BridgeError::FileDescriptorError => ConnectionStatus::WrongData,
-                        BridgeError::ContextError => ConnectionStatus::InnerError,
-                        e @ _ => {
-                            log::error!("Unhandled error has occur: {:#?}", e);
-                            unreachable!()
-                        },
-                    }
-                } else {
-                    ConnectionStatus::Connected
-                }
-            },
-            Err(_err) => match _err {
-                BridgeError::FileDescriptorError => ConnectionStatus::WrongData,
-                BridgeError::ContextError => ConnectionStatus::InnerError,
-                e @ _ => {
-                    log::error!("Unhandled error has occur: {:#?}", e);
-                    unreachable!()
-                },
-            } 
-        }
+    use std::{future::Future, path::PathBuf, sync::{Mutex, OnceLock}};
+
+    use super::{Bridge, BridgeError};
+    use crate::sugar::{
+        conn::{
+            actor::{BridgeHandle, ConnectionReport},
+            client::{DaemonClient, Disk, Partition, RemoteEntry},
+            recovery::ReconnectPolicy,
+        },
+        errors::{StorageError, SugarError, SugarResult},
+        events::{self, Event},
+        runtime,
+        storage::{db::{self, TransferRecord}, Database},
+    };
+
+    /// Amount of transferred bytes between two progress events.
+    const PROGRESS_STEP: u64 = 64 * 1024;
+
+    static ACTOR: OnceLock<BridgeHandle> = OnceLock::new();
+    /// Policy of reconnection applied to all new bridges, the default one if never set.
+    static RECONNECT: Mutex<Option<ReconnectPolicy>> = Mutex::new(None);
+
+    /// Returns the actor, which owns the bridge of the current connection.
+    fn actor() -> &'static BridgeHandle {
+        ACTOR.get_or_init(BridgeHandle::spawn)
+    }
+
+    /// Opens the USB device by its file descriptor and starts the connection.
+    ///
+    /// Returns as soon as the bridge is running, the connection continues in the background.
+    pub async fn connect(fd: i32) -> SugarResult<()> {
+        let mut bridge = Bridge::new(rand::random(), fd)?;
+        bridge.set_reconnect_policy(RECONNECT.lock().unwrap_or_else(|err| err.into_inner()).unwrap_or_default());
+        actor().connect(bridge).await
+    }
+
+    /// Replaces the policy of reconnection. Applied from the next connection on.
+    pub fn set_reconnect_policy(policy: ReconnectPolicy) {
+        log::info!("Reconnection policy: {:?}", policy);
+        RECONNECT.lock().unwrap_or_else(|err| err.into_inner()).replace(policy);
     }
 
     /// Disconnects from the currently existing bridge.
-    #[tokio::main]
-    pub async fn disconnect() -> ConnectionStatus {
-        let mut bridge_ref = BRIDGE_REF.lock().await;
-
-        if let Some(bridge) = bridge_ref.as_mut() {
-            match bridge.disconnect().await {
-                Ok(_) => {
-                    *bridge_ref = None;
-                    ConnectionStatus::Disconnected
-                },
-                Err(_err) => match _err {
-                    BridgeError::BridgeClosed => ConnectionStatus::BridgeClosed,
-                    BridgeError::ContextError => ConnectionStatus::InnerError,
-                    e @ _ => {
-                        log::error!("Unhandled error has occurred: {:#?}", e);
-                        unreachable!()
-                    },
-                },
-            }
-        } else {
-            ConnectionStatus::Disconnected
-        }
+    pub async fn disconnect() -> SugarResult<()> {
+        actor().disconnect().await
     }
 
     /// Gets info about a current connection.
-    #[tokio::main]
     pub async fn get_conn_info() -> String {
-        let mut bridge_ref = BRIDGE_REF.lock().await;
+        actor().info().await
+    }
+
+    /// Returns the state of the current connection, if any.
+    pub async fn connection_report() -> Option<ConnectionReport> {
+        actor().report().await
+    }
 
-        if let Some(bridge) = bridge_ref.as_mut() {
-            let devd = bridge.device.lock().await.device();
-            let devdc = &bridge.dev_desc;
-            format!("Bus: {:03}, Addr: {:03}, ID: {:04x}:{:04x}\nMax supported USB version: {}.", 
-                devd.bus_number(), devd.address(), devdc.vendor_id(), devdc.product_id(), devdc.usb_version());        
+    /// Returns a client of the current connection, if any.
+    pub async fn client() -> Option<DaemonClient> {
+        actor().client().await
+    }
+
+    /// Lists all disks of the target. The listing is delivered as an event.
+    pub async fn list_disks() -> SugarResult<()> {
+        request(|client| async move {
+            Ok(Event::Disks(client.list_disks().await?))
+        }).await
+    }
+
+    /// Lists all partitions of the disk. The listing is delivered as an event.
+    pub async fn list_partitions(disk: Disk) -> SugarResult<()> {
+        request(|client| async move {
+            let partitions = client.list_partitions(&disk).await?;
+            Ok(Event::Partitions(disk, partitions))
+        }).await
+    }
+
+    /// Lists all files in the root of the partition. The listing is delivered as an event.
+    pub async fn list_files(partition: Partition) -> SugarResult<()> {
+        let machine = actor().machine().await;
+
+        request(|client| async move {
+            let files = client.list_files(&partition).await?;
+            if let Some(machine) = machine {
+                let seen = files.clone();
+                remember(move |db| db.listings().record(&machine, &seen).map(|_| ()));
+            }
+            Ok(Event::Files(partition, files))
+        }).await
+    }
+
+    /// Copies the file from the target to the provided local path.
+    ///
+    /// The progress of the transfer is delivered as events.
+    pub async fn download(file: RemoteEntry, dest: PathBuf) -> SugarResult<()> {
+        let machine = actor().machine().await;
+
+        request(|client| async move {
+            let mut reported = 0;
+            let result = async {
+                let data = client.read_file_with_progress(&file, |done| {
+                    // Reporting every single chunk would only flood the front-end.
+                    if done.abs_diff(reported) >= PROGRESS_STEP {
+                        reported = done;
+                        events::emit(Event::Progress { file: file.clone(), done, finished: false });
+                    }
+                }).await?;
+
+                tokio::fs::write(&dest, &data).await.map_err(|err| {
+                    log::error!("Unable to write the file to {}: {}", dest.display(), err);
+                    SugarError::new(StorageError::IO_ERROR).with_source(err)
+                })?;
+                Ok(data.len() as u64)
+            }.await;
+
+            if let Some(machine) = machine {
+                let transfer = TransferRecord {
+                    machine,
+                    file: file.clone(),
+                    destination: dest.to_string_lossy().into_owned(),
+                    bytes: *result.as_ref().unwrap_or(&reported),
+                    finished_at: db::now(),
+                    error: result.as_ref().err().map(SugarError::code),
+                };
+                remember(move |db| db.transfers().record(&transfer).map(|_| ()));
+            }
+
+            Ok(Event::Progress { done: result?, file, finished: true })
+        }).await
+    }
+
+    /// Writes the history to the database in the background. Failures are only logged.
+    fn remember(f: impl FnOnce(&Database) -> SugarResult<()> + Send + 'static) {
+        runtime::spawn(async move {
+            if let Err(err) = Database::run(f).await {
+                log::warn!("Unable to record the history: {}", err.report());
+            }
+        });
+    }
+
+    /// Runs the request on the current connection in the background.
+    ///
+    /// The resulting event, or an error, is emitted once the request is done.
+    async fn request<F, Fut>(f: F) -> SugarResult<()>
+    where
+        F: FnOnce(DaemonClient) -> Fut,
+        Fut: Future<Output = SugarResult<Event>> + Send + 'static,
+    {
+        let Some(client) = client().await else {
+            return Err(BridgeError::NotConnected.into())
+        };
+
+        let task = f(client);
+        runtime::spawn(async move {
+            match task.await {
+                Ok(event) => events::emit(event),
+                Err(err) => {
+                    log::error!("{}", err.report());
+                    events::emit(Event::Error(err));
+                },
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sugar::{conn::{cmd::DaemonCommandByte, transport::MemoryTransport}, errors::{BridgeError, ErrorKind}, runtime};
+
+    /// Reads the next transfer written by the bridge.
+    fn next_transfer(target: &MemoryTransport) -> Vec<u8> {
+        let mut buf = [0u8; 256];
+        let len = target.read(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    /// Answer, whose checksum does not match.
+    fn corrupted() -> Vec<u8> {
+        use DaemonCommandByte::*;
+        let mut cmd = crate::dcommand!(ACK, SEL);
+        cmd.push_data(b"sda").unwrap();
+
+        let mut bytes = cmd.byte_code().to_vec();
+        bytes[3] ^= 0x01;
+        bytes
+    }
+
+    #[test]
+    fn corrupted_commands_exhaust_retries() {
+        let (host, target) = MemoryTransport::pair();
+        let mut bridge = Bridge::with_transport(1, host);
+        bridge.set_reconnect_policy(ReconnectPolicy::DISABLED);
+        let state = bridge.subscribe();
+        let task = runtime::spawn(async move { bridge.connect().await });
+
+        assert_eq!(next_transfer(&target), DaemonCommand::init(1).byte_code());
+        // Each corrupted command is answered with a retransmission request until the budget is gone.
+        for _ in 0..MAX_RETRIES {
+            target.write(&corrupted()).unwrap();
+            assert_eq!(next_transfer(&target), DaemonCommand::retry().byte_code());
         }
 
-        String::new()
+        let err = runtime::block_on(task).unwrap().unwrap_err();
+        assert!(err.kind() == ErrorKind::Bridge(BridgeError::RetryLimitExceeded));
+        assert_eq!(*state.borrow(), LifecycleState::Failed);
+    }
+
+    #[test]
+    fn shutdown_drains_before_the_bridge_returns() {
+        use crate::sugar::parse::ParseOutput;
+        use DaemonCommandByte::*;
+        let (host, _target) = MemoryTransport::pair();
+        let mut bridge = Bridge::with_transport(1, host);
+        bridge.enter(LifecycleState::Opening);
+        bridge.enter(LifecycleState::Handshaking);
+
+        // Shutdown of the target, which is not forwarded back.
+        let output = runtime::block_on(SugarParser::parse_byte_code(&mut bridge, crate::dcommand!(SHUT)));
+        assert!(matches!(output, ParseOutput::Shutdown));
+        assert_eq!(bridge.state(), LifecycleState::Draining);
+    }
+
+    #[test]
+    fn failure_queued_while_idle_is_dropped_on_connect() {
+        use crate::sugar::conn::sim::{tests::tree, SimDaemon};
+
+        let root = tree("stale");
+        let (host, target) = MemoryTransport::pair();
+        let daemon = SimDaemon::new(&root).spawn(target);
+        let mut bridge = Bridge::with_transport(1, host);
+        bridge.set_reconnect_policy(ReconnectPolicy::DISABLED);
+        let mut state = bridge.subscribe();
+        let client = bridge.client();
+
+        // The device was detached, before this bridge was connected.
+        bridge.tx.try_send(BridgeMessage::Failed(SugarError::new(BridgeError::DeviceLost))).unwrap();
+        let task = runtime::spawn(async move { bridge.connect().await });
+
+        runtime::block_on(state.wait_for(|state| *state == LifecycleState::Connected)).unwrap();
+        runtime::block_on(client.disconnect()).unwrap();
+        runtime::block_on(task).unwrap().unwrap();
+        assert_eq!(*state.borrow(), LifecycleState::Closed);
+
+        daemon.join().unwrap().unwrap();
+        std::fs::remove_dir_all(root).ok();
     }
 
-    /// Local enum to represent the current status of the connection.
-    #[repr(u8)]
-    pub enum ConnectionStatus {
-        Connected           = 0, 
-        Disconnected        = 1, 
-        NoDevice            = 2, 
-        BridgeClosed        = 3, 
-        Refused             = 4,  
-        Timeout             = 5, 
-        WrongData           = 6,
-        InnerError          = 7,
+    #[test]
+    fn requests_wait_for_the_handshake() {
+        use crate::sugar::conn::sim::{tests::tree, SimDaemon};
+        use DaemonCommandByte::*;
+
+        let root = tree("held");
+        let mut daemon = SimDaemon::new(&root);
+        let (host, target) = MemoryTransport::pair();
+        let mut bridge = Bridge::with_transport(1, host);
+        let client = bridge.client();
+        let disks = runtime::spawn(async move { client.list_disks().await });
+        let task = runtime::spawn(async move { bridge.connect().await });
+
+        let init = next_transfer(&target);
+        assert_eq!(init, DaemonCommand::init(1).byte_code());
+        // Nothing but the handshake is sent, until the target acknowledges it.
+        assert!(matches!(target.read(&mut [0; 256]), Err(rusb::Error::Timeout)));
+
+        let answer = |frame: &[u8], daemon: &mut SimDaemon| for cmd in daemon.handle(frame) {
+            target.write(cmd.byte_code()).unwrap();
+        };
+        answer(&init, &mut daemon);
+
+        // The held request follows the acknowledged handshake.
+        let request = next_transfer(&target);
+        assert_eq!(request, crate::dcommand!(REQ, NAME).byte_code());
+        answer(&request, &mut daemon);
+        assert_eq!(runtime::block_on(disks).unwrap().unwrap().len(), 2);
+
+        task.abort();
+        std::fs::remove_dir_all(root).ok();
     }
 }
@@ -1,86 +1,149 @@
+use rusb::{Speed, Version};
 
-use rusb::{DeviceHandle, Error as RusbError};
-use std::io::Write;
-use std::ptr::write_bytes;
-use std::mem;
+use super::{cmd::DaemonCommand, codec::{FrameDecoder, FrameError, MAX_FRAME_SIZE}};
 
-use super::cmd::DaemonCommand;
-
-/// Amount of bytes that will be held for user's commands input.
-const INPUT_BUFFER_SIZE: usize = 128;
 /// Amount of bytes that will be stored in the buffer from the target device.
 const OUTPUT_BUFFER_SIZE: usize = 512;
-
-/// Communication timeout for one atomic read/write in ms.
-const TIMEOUT: std::time::Duration = std::time::Duration::from_millis(2000);
+/// Amount of read transfers kept in flight on a USB v2.0 bus.
+const TRANSFERS_IN_FLIGHT: usize = 4;
+/// Size of a bulk packet on a SuperSpeed link.
+const SUPERSPEED_PACKET_SIZE: usize = 1024;
+/// Packets within one read transfer on a SuperSpeed link, which is the largest bulk burst.
+const SUPERSPEED_PACKETS: usize = 16;
+/// Packets within one read transfer on a SuperSpeedPlus link.
+const SUPERSPEED_PLUS_PACKETS: usize = 64;
+/// Amount of read transfers kept in flight on a SuperSpeed link.
+const SUPERSPEED_TRANSFERS_IN_FLIGHT: usize = 8;
 
 /// Custom trait for buffers.
+///
+/// The buffer describes how the bus is read, while the reading itself is done by the reader of
+/// the bridge. Obtained transfers are pushed into the buffer, which splits them into commands.
 pub(crate) trait Buffer: Send + Sync + 'static {
-    /// Reads data from the bus and returns read bytes.
-    fn read(&mut self, dev: &DeviceHandle<rusb::Context>) -> Result<&[u8], RusbError>;
-    /// Writes data to the bus and returns the amount of bytes written.
-    fn write(&mut self, dev: &DeviceHandle<rusb::Context>, cmd: DaemonCommand) -> Result<usize, RusbError>;
+    /// Size of a single read transfer.
+    fn transfer_size(&self) -> usize;
+    /// Amount of read transfers kept in flight at once.
+    fn in_flight(&self) -> usize;
+    /// Largest command, which can be written to the bus at once.
+    fn max_write(&self) -> usize;
+    /// Appends the data of one transfer.
+    ///
+    /// Obtained commands are available via [`Buffer::next_frame`] afterwards.
+    fn push(&mut self, data: &[u8]);
+    /// Returns the next complete command obtained from the bus, if any.
+    fn next_frame(&mut self) -> Option<Result<DaemonCommand, FrameError>>;
+    /// Drops the incomplete command, which is no longer expected to be finished.
+    fn flush(&mut self) -> Result<(), FrameError>;
 }
 
 /// Buffer for USB v2.0.
 ///
-/// Communication is done in full duplex, where writes are less common than reads. All
-/// I/O is done via libusb protocol.
-#[derive(Debug)]
+/// Communication is done in full duplex, where writes are less common than reads. Each transfer
+/// is read into a whole output buffer and then split into commands by the frame decoder.
+#[derive(Debug, Default)]
 pub(crate) struct USBV2Buf {
-    pub _in: [u8; INPUT_BUFFER_SIZE],
-    pub _out: [u8; OUTPUT_BUFFER_SIZE],
-    read_ptr: usize,
-    write_ptr: usize,
+    decoder: FrameDecoder,
 }
 
-impl Default for USBV2Buf {
-    fn default() -> Self {
-        Self {
-            _in: [0u8; INPUT_BUFFER_SIZE],
-            _out: [0u8; OUTPUT_BUFFER_SIZE],
-            read_ptr: 0,
-            write_ptr: 0,
-        }
+impl Buffer for USBV2Buf {
+    fn transfer_size(&self) -> usize {
+        OUTPUT_BUFFER_SIZE
+    }
+
+    fn in_flight(&self) -> usize {
+        TRANSFERS_IN_FLIGHT
+    }
+
+    /// One bulk write may span several packets, so any command is written at once.
+    fn max_write(&self) -> usize {
+        MAX_FRAME_SIZE
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.decoder.push(data);
+    }
+
+    fn next_frame(&mut self) -> Option<Result<DaemonCommand, FrameError>> {
+        self.decoder.next_frame()
+    }
+
+    fn flush(&mut self) -> Result<(), FrameError> {
+        self.decoder.flush()
     }
 }
 
-impl Buffer for USBV2Buf {
-    fn read(&mut self, dev: &DeviceHandle<rusb::Context>) -> Result<&[u8], RusbError> {
-        let ptr = self.read_ptr;
-        let slice = &mut self._out[ptr..];
-
-        // Reading with defined timeout.
-        match dev.read_bulk(1, slice, TIMEOUT) {
-            Ok(len) => {
-                // Reading the latest data.
-                let data = &self._out[ptr..ptr + len];
-
-                // Moving the pointer forward without overflowing.
-                self.read_ptr = (self.read_ptr + len) % OUTPUT_BUFFER_SIZE;
-
-                Ok(data)
-            }
-            Err(err) => Err(err),
-        }
+/// Buffer for SuperSpeed links of USB v3.x.
+///
+/// Each read transfer spans many packets, so that a whole burst of the target is obtained at once,
+/// while more transfers are kept in flight to keep up with the link. The transfer is still
+/// completed early by a short packet, therefore small commands are not delayed.
+#[derive(Debug)]
+pub(crate) struct USBV3Buf {
+    decoder: FrameDecoder,
+    transfer_size: usize,
+}
+
+impl USBV3Buf {
+    /// Creates a buffer for the link speed and the packet size of the read endpoint.
+    pub(crate) fn new(speed: Speed, packet_size: u16) -> Self {
+        let packet_size = match packet_size {
+            0 => SUPERSPEED_PACKET_SIZE,
+            size => size as usize,
+        };
+        let packets = match speed {
+            Speed::SuperPlus => SUPERSPEED_PLUS_PACKETS,
+            _ => SUPERSPEED_PACKETS,
+        };
+
+        Self { decoder: FrameDecoder::default(), transfer_size: packet_size * packets }
+    }
+}
+
+impl Buffer for USBV3Buf {
+    fn transfer_size(&self) -> usize {
+        self.transfer_size
+    }
+
+    fn in_flight(&self) -> usize {
+        SUPERSPEED_TRANSFERS_IN_FLIGHT
+    }
+
+    /// Any command fits into a single SuperSpeed packet.
+    fn max_write(&self) -> usize {
+        MAX_FRAME_SIZE
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.decoder.push(data);
+    }
+
+    fn next_frame(&mut self) -> Option<Result<DaemonCommand, FrameError>> {
+        self.decoder.next_frame()
     }
 
-    fn write(&mut self, dev: &DeviceHandle<rusb::Context>, cmd: DaemonCommand) -> Result<usize, RusbError> {
-        let ptr = self.write_ptr;
-        let offset = cmd.size();
-        let mut slice = &mut self._in[ptr + 1..ptr + 1 + offset];
-        slice.write(unsafe { mem::transmute(cmd.byte_code()) });
-
-        // Writing the slice in.
-        match dev.write_bulk(1, slice, TIMEOUT) {
-            Ok(len) => {
-                // Moving the pointer forward without overflowing.
-                self.write_ptr = (self.write_ptr + len) % INPUT_BUFFER_SIZE;
-
-                Ok(len)
-            }
-            Err(err) => Err(err),
-        }
+    fn flush(&mut self) -> Result<(), FrameError> {
+        self.decoder.flush()
     }
 }
 
+/// Picks the buffer for the link to the device.
+///
+/// The speed is the one negotiated by the host, while the USB version only tells what the device
+/// supports. Some hosts do not know the speed of a device opened via a file descriptor, in which
+/// case a USB v3.x device with SuperSpeed sized packets is assumed to run at SuperSpeed.
+pub(crate) fn select(speed: Speed, version: Version, packet_size: u16) -> Box<dyn Buffer> {
+    let superspeed = match speed {
+        Speed::Super | Speed::SuperPlus => true,
+        Speed::Unknown => version.major() >= 3 && packet_size as usize >= SUPERSPEED_PACKET_SIZE,
+        _ => false,
+    };
+
+    if superspeed {
+        let buf = USBV3Buf::new(speed, packet_size);
+        log::info!("Using the USB v3.x buffer: {:?} link, {} transfers of {} bytes.", speed, SUPERSPEED_TRANSFERS_IN_FLIGHT, buf.transfer_size);
+        Box::new(buf)
+    } else {
+        log::info!("Using the USB v2.0 buffer: {:?} link, USB {}.", speed, version);
+        Box::new(USBV2Buf::default())
+    }
+}
@@ -0,0 +1,412 @@
+//! Typed client of the daemon.
+//!
+//! The client is a cheap handle to a running bridge. Each method sends one or several requests to
+//! the target and waits for their answers, which are decoded into typed structures. Requests from
+//! all clients of the bridge are pipelined, so several of them can be in flight at once. The
+//! daemon answers in the same order as it obtains requests, therefore each answer is correlated
+//! with the oldest pending request which expects it.
+
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+use tokio::sync::{Mutex, mpsc::{self, Sender, UnboundedSender}, oneshot};
+
+use super::cmd::{DaemonCommand, DaemonCommandByte, DecodedCommand};
+use crate::{dcommand, sugar::errors::SugarError};
+
+pub use crate::sugar::errors::ClientError;
+
+/// Time to wait for an answer from the target.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Disk of the target device.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Disk {
+    pub name: String,
+}
+
+/// Partition of a disk on the target device.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Partition {
+    pub disk: String,
+    pub name: String,
+}
+
+/// Kind of an entry within a partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+/// File or directory within a partition.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RemoteEntry {
+    pub partition: Partition,
+    /// Path relative to the partition.
+    pub path: String,
+    pub kind: EntryKind,
+}
+
+/// Answer of the target: command byte and data of each obtained command.
+type Answer = Vec<(DaemonCommandByte, Vec<u8>)>;
+
+/// Request which waits for an answer from the target.
+pub(crate) struct Request {
+    pub command: DaemonCommand,
+    /// Command byte the answer must carry.
+    expect: DaemonCommandByte,
+    /// The answer is a listing which ends with an empty command.
+    listing: bool,
+    /// Obtains the amount of data bytes of the listing collected so far.
+    progress: Option<UnboundedSender<usize>>,
+    reply: oneshot::Sender<ClientResult<Answer>>,
+}
+
+/// Message which is handled by a running bridge.
+pub(crate) enum BridgeMessage {
+    /// Command to be parsed, either obtained from the target or issued by the user.
+    Command(DaemonCommand),
+    /// Request from a client, which must be sent to the target.
+    Request(Request),
+    /// The transport has failed beyond recovery.
+    Failed(SugarError),
+}
+
+/// Request which was sent to the target and waits for the answer.
+struct Pending {
+    expect: DaemonCommandByte,
+    listing: bool,
+    answer: Answer,
+    /// Amount of data bytes in the answer.
+    received: usize,
+    progress: Option<UnboundedSender<usize>>,
+    reply: oneshot::Sender<ClientResult<Answer>>,
+}
+
+impl Pending {
+    /// Whether the answer of the target belongs to this request.
+    fn expects(&self, command: DaemonCommandByte) -> bool {
+        command == self.expect || (self.expect == DaemonCommandByte::FILE && command == DaemonCommandByte::DIR)
+    }
+
+    fn report(&self) {
+        if let Some(progress) = &self.progress {
+            progress.send(self.received).ok();
+        }
+    }
+}
+
+/// Queue of requests sent to the target, in the order they were sent.
+#[derive(Default)]
+pub(crate) struct PendingRequests {
+    queue: VecDeque<Pending>,
+    /// A corrupted command was obtained, everything is dropped until the retransmission.
+    restarting: bool,
+}
+
+impl PendingRequests {
+    /// Adds the request, which was just sent to the target.
+    pub fn push(&mut self, request: Request) {
+        self.prune();
+        self.queue.push_back(Pending {
+            expect: request.expect,
+            listing: request.listing,
+            answer: Vec::new(),
+            received: 0,
+            progress: request.progress,
+            reply: request.reply,
+        });
+    }
+
+    /// Routes the answer of the target to the oldest pending request which expects it.
+    ///
+    /// Returns false if no request waits for such answer.
+    pub fn answer(&mut self, decoded: &DecodedCommand) -> bool {
+        use DaemonCommandByte::*;
+
+        self.prune();
+        let Some(index) = self.queue.iter().position(|pending| pending.expects(decoded.command)) else { return false };
+        let pending = &mut self.queue[index];
+        if self.restarting {
+            log::warn!("Dropping the answer while waiting for the retransmission.");
+            return true
+        }
+
+        let result = match (decoded.prefix, pending.listing, decoded.data) {
+            (Some(NACK), _, _) => Err(ClientError::Refused),
+            (_, true, []) => Ok(std::mem::take(&mut pending.answer)),
+            (_, true, data) => {
+                pending.answer.push((decoded.command, data.to_vec()));
+                pending.received += data.len();
+                pending.report();
+                return true
+            },
+            (_, false, data) => Ok(vec![(decoded.command, data.to_vec())]),
+        };
+
+        if let Some(pending) = self.queue.remove(index) {
+            // The client could have already given up waiting.
+            pending.reply.send(result).ok();
+        }
+        true
+    }
+
+    /// Drops the partial answer after a corrupted command.
+    ///
+    /// All following answers are dropped until the target confirms the retransmission, since the
+    /// rest of the corrupted answer may still be on its way.
+    pub fn restart(&mut self) {
+        self.prune();
+        if let Some(pending) = self.queue.front_mut() {
+            pending.answer.clear();
+            pending.received = 0;
+            pending.report();
+            self.restarting = true;
+        }
+    }
+
+    /// Continues to collect answers after the target has confirmed the retransmission.
+    pub fn resume(&mut self) {
+        self.restarting = false;
+    }
+
+    /// Fails all pending requests.
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.restarting = false;
+    }
+
+    /// Removes requests whose clients gave up waiting, e.g. after a timeout.
+    fn prune(&mut self) {
+        self.queue.retain(|pending| !pending.reply.is_closed());
+    }
+}
+
+/// Typed client of the daemon.
+///
+/// Obtained from [`super::Bridge::client`]. All clones share the same bridge.
+#[derive(Clone)]
+pub struct DaemonClient {
+    tx: Sender<BridgeMessage>,
+    /// The daemon keeps only one selection, so operations which depend on it must not interleave.
+    selection: Arc<Mutex<()>>,
+}
+
+impl DaemonClient {
+    pub(crate) fn new(tx: Sender<BridgeMessage>, selection: Arc<Mutex<()>>) -> Self {
+        Self { tx, selection }
+    }
+
+    /// Lists all disks of the target.
+    pub async fn list_disks(&self) -> ClientResult<Vec<Disk>> {
+        use DaemonCommandByte::*;
+
+        let answer = self.request(dcommand!(REQ, NAME), NAME, true).await?;
+        answer.into_iter()
+            .map(|(_, name)| Ok(Disk { name: Self::string(name)? }))
+            .collect()
+    }
+
+    /// Lists all partitions of the disk.
+    pub async fn list_partitions(&self, disk: &Disk) -> ClientResult<Vec<Partition>> {
+        use DaemonCommandByte::*;
+        let _selection = self.selection.lock().await;
+
+        self.select_unlocked(&disk.name).await?;
+        let answer = self.request(dcommand!(REQ, PART), PART, true).await?;
+        answer.into_iter()
+            .map(|(_, name)| Ok(Partition { disk: disk.name.clone(), name: Self::string(name)? }))
+            .collect()
+    }
+
+    /// Lists all files and directories in the root of the partition.
+    pub async fn list_files(&self, partition: &Partition) -> ClientResult<Vec<RemoteEntry>> {
+        use DaemonCommandByte::*;
+        let _selection = self.selection.lock().await;
+
+        self.select_partition(partition).await?;
+        let answer = self.request(dcommand!(REQ, FILE), FILE, true).await?;
+        answer.into_iter()
+            .map(|(cmd, path)| Ok(RemoteEntry {
+                partition: partition.clone(),
+                path: Self::string(path)?,
+                kind: if cmd == DIR { EntryKind::Directory } else { EntryKind::File },
+            }))
+            .collect()
+    }
+
+    /// Reads the whole content of the file.
+    pub async fn read_file(&self, file: &RemoteEntry) -> ClientResult<Vec<u8>> {
+        self.read_file_with_progress(file, |_| ()).await
+    }
+
+    /// Reads the whole content of the file, reporting the amount of bytes obtained so far.
+    ///
+    /// The amount may go back, if a part of the file has to be transferred once more.
+    pub async fn read_file_with_progress(&self, file: &RemoteEntry, mut progress: impl FnMut(u64)) -> ClientResult<Vec<u8>> {
+        use DaemonCommandByte::*;
+        let _selection = self.selection.lock().await;
+
+        self.select_partition(&file.partition).await?;
+        let mut cmd = dcommand!(REQ, READ, FILE);
+        cmd.push_data(file.path.as_bytes()).map_err(|_| ClientError::TooLarge)?;
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut answer = self.submit(cmd, READ, true, Some(tx)).await?;
+
+        // The timeout only covers the time without any new data, so big files are not cut off.
+        let answer = loop {
+            tokio::select! {
+                // All progress is reported before the answer, so it must be checked first.
+                biased;
+                Some(done) = rx.recv() => progress(done as u64),
+                answer = &mut answer => break answer.map_err(|_| ClientError::BridgeClosed)??,
+                _ = tokio::time::sleep(REQUEST_TIMEOUT) => return Err(ClientError::Timeout),
+            }
+        };
+        Ok(answer.into_iter().flat_map(|(_, chunk)| chunk).collect())
+    }
+
+    /// Selects a disk, or a partition of the currently selected disk.
+    pub async fn select(&self, name: &str) -> ClientResult<()> {
+        let _selection = self.selection.lock().await;
+        self.select_unlocked(name).await
+    }
+
+    /// Removes any selection.
+    pub async fn unselect(&self) -> ClientResult<()> {
+        use DaemonCommandByte::*;
+        let _selection = self.selection.lock().await;
+
+        self.request(dcommand!(REQ, UNSEL), UNSEL, false).await.map(|_| ())
+    }
+
+    /// Closes the bridge.
+    pub async fn disconnect(&self) -> ClientResult<()> {
+        self.tx.send(BridgeMessage::Command(DaemonCommand::user_disconnect())).await
+            .map_err(|_| ClientError::BridgeClosed)
+    }
+
+    async fn select_partition(&self, partition: &Partition) -> ClientResult<()> {
+        // Partitions can only be selected within the selected disk.
+        self.select_unlocked(&partition.disk).await?;
+        self.select_unlocked(&partition.name).await
+    }
+
+    async fn select_unlocked(&self, name: &str) -> ClientResult<()> {
+        use DaemonCommandByte::*;
+
+        let mut cmd = dcommand!(REQ, SEL);
+        cmd.push_data(name.as_bytes()).map_err(|_| ClientError::TooLarge)?;
+        self.request(cmd, SEL, false).await.map(|_| ())
+    }
+
+    /// Sends the request to the bridge and waits for the answer.
+    async fn request(&self, command: DaemonCommand, expect: DaemonCommandByte, listing: bool) -> ClientResult<Answer> {
+        let rx = self.submit(command, expect, listing, None).await?;
+
+        match tokio::time::timeout(REQUEST_TIMEOUT, rx).await {
+            Ok(Ok(answer)) => answer,
+            Ok(Err(_)) => Err(ClientError::BridgeClosed),
+            Err(_) => Err(ClientError::Timeout),
+        }
+    }
+
+    /// Sends the request to the bridge, returning the receiver of the answer.
+    async fn submit(
+        &self,
+        command: DaemonCommand,
+        expect: DaemonCommandByte,
+        listing: bool,
+        progress: Option<UnboundedSender<usize>>,
+    ) -> ClientResult<oneshot::Receiver<ClientResult<Answer>>> {
+        let (reply, rx) = oneshot::channel();
+        self.tx.send(BridgeMessage::Request(Request { command, expect, listing, progress, reply })).await
+            .map_err(|_| ClientError::BridgeClosed)?;
+
+        Ok(rx)
+    }
+
+    fn string(bytes: Vec<u8>) -> ClientResult<String> {
+        String::from_utf8(bytes).map_err(|_| ClientError::BadData)
+    }
+}
+
+impl Request {
+    /// Fails the request, which could not be sent.
+    pub fn fail(self, err: ClientError) {
+        self.reply.send(Err(err)).ok();
+    }
+
+    /// Whether the client gave up waiting, e.g. after a timeout.
+    pub fn is_abandoned(&self) -> bool {
+        self.reply.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sugar::runtime;
+    use DaemonCommandByte::*;
+
+    /// Takes the next request of the client, just like the bridge does.
+    async fn next_request(rx: &mut mpsc::Receiver<BridgeMessage>) -> Request {
+        match rx.recv().await {
+            Some(BridgeMessage::Request(request)) => request,
+            _ => panic!("Expected a request."),
+        }
+    }
+
+    fn answer(bytes: &[u8]) -> DaemonCommand {
+        let mut cmd = dcommand!(ACK, SEL);
+        cmd.push_data(bytes).unwrap();
+        cmd
+    }
+
+    #[test]
+    fn timed_out_request_is_removed() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let client = DaemonClient::new(tx, Arc::default());
+        let mut pending = PendingRequests::default();
+
+        runtime::block_on(async {
+            let late = client.submit(dcommand!(REQ, SEL), SEL, false, None).await.unwrap();
+            pending.push(next_request(&mut rx).await);
+            // The client gives up waiting, so the request must never obtain an answer.
+            let timeout = tokio::time::timeout(Duration::from_millis(10), late).await;
+            assert!(timeout.is_err());
+
+            let next = client.submit(dcommand!(REQ, SEL), SEL, false, None).await.unwrap();
+            pending.push(next_request(&mut rx).await);
+            assert_eq!(pending.queue.len(), 1);
+
+            let cmd = answer(b"sda");
+            assert!(pending.answer(&cmd.decode().unwrap()));
+            assert_eq!(next.await.unwrap().unwrap(), vec![(SEL, b"sda".to_vec())]);
+            assert!(pending.queue.is_empty());
+        });
+    }
+
+    #[test]
+    fn answer_is_routed_past_other_requests() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let client = DaemonClient::new(tx, Arc::default());
+        let mut pending = PendingRequests::default();
+
+        runtime::block_on(async {
+            let listing = client.submit(dcommand!(REQ, NAME), NAME, true, None).await.unwrap();
+            pending.push(next_request(&mut rx).await);
+            let select = client.submit(dcommand!(REQ, SEL), SEL, false, None).await.unwrap();
+            pending.push(next_request(&mut rx).await);
+
+            assert!(pending.answer(&answer(b"sdb").decode().unwrap()));
+            assert_eq!(select.await.unwrap().unwrap(), vec![(SEL, b"sdb".to_vec())]);
+
+            assert!(!pending.answer(&dcommand!(ACK, READ).decode().unwrap()));
+            assert!(pending.answer(&dcommand!(ACK, NAME).decode().unwrap()));
+            assert_eq!(listing.await.unwrap().unwrap(), Vec::new());
+        });
+    }
+}
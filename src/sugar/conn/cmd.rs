@@ -1,7 +1,10 @@
 //! This module defines a bytecode communication language for communication between the daemon and
 //! the mobile device. The command then has to be parsed on both sides to perform different tasks.
 
-use std::mem;
+use std::fmt::Display;
+
+use super::session::SessionInfo;
+use crate::sugar::errors::BridgeError;
 
 /// A bytecode command that is being used to communicate between two devices.
 ///
@@ -10,22 +13,33 @@ use std::mem;
 ///
 /// # Representation:
 ///
+/// ```text
 ///          (opt)                     (opt)
 /// *------*--------*---------*------*----------*
 /// | SIZE | PREFIX | COMMAND | DATA | CHECKSUM |
 /// *------*--------*---------*------*----------*
+/// ```
 ///
 /// The size of the command can vary a lot based on the command itself and data that comes with it.
 /// Some fields like a prefix and data can be optional. Commands are parsed sequentially, therefore
 /// they can be stacked.
+///
+/// The command is stored as raw bytes, since data bytes are not necessarily valid command bytes.
 #[repr(transparent)]
 #[derive(Debug, Clone)]
-pub struct DaemonCommand(Vec<DaemonCommandByte>);
+pub struct DaemonCommand(Vec<u8>);
 
 impl DaemonCommand {
     /// Creates a new daemon command based on the obtained slice of bytes. 
     pub fn new(slice: &[u8]) -> Self {
-        Self(unsafe{ mem::transmute(slice.to_vec()) })
+        Self(slice.to_vec())
+    }
+
+    /// Creates a command which never passes the validation.
+    ///
+    /// Used in place of bytes which could not be split into a proper command.
+    pub fn corrupted() -> Self {
+        Self(vec![0])
     }
 
     /// Creates a new blank command for future dynamic structuring.
@@ -36,19 +50,27 @@ impl DaemonCommand {
     /// Pushes new data before the checksum while counting the new one.
     ///
     /// This allows to push new data into the command. This must not be used to stack commands.
-    pub fn push_data(&mut self, data: &[u8]) {
-        let mut checksum = u8::MAX - Into::<u8>::into(self.0.pop().unwrap());  // Commands would never be empty.
-        for byte in data.to_owned() {
-            let _ = checksum.wrapping_add(byte);
-            self.push_value(byte)
+    /// Fails with [`BridgeError::DataTooLarge`] if the command would be bigger than u8::MAX, in
+    /// which case the command is left as is.
+    pub fn push_data(&mut self, data: &[u8]) -> Result<(), BridgeError> {
+        // The size byte must always cover the whole command, including the checksum.
+        let size = self.0.len() + data.len();
+        if size >= u8::MAX.into() {
+            return Err(BridgeError::DataTooLarge)
         }
-        checksum = u8::MAX - checksum;
-        self.push_value(checksum.into());
+
+        self.0.pop();  // Commands would never be empty.
+        self.0.extend_from_slice(data);
+        self.0[0] = size as u8;
+
+        let checksum = Self::checksum(&self.0);
+        self.push_value(checksum);
+        Ok(())
     }
 
     /// Pushes one byte to the commands top. Does not change the checksum.
     pub fn push_value(&mut self, value: u8) {
-        self.0.push(value.into());
+        self.0.push(value);
     }
 
     /// Returns the size of a command. Panics if the command is empty
@@ -57,19 +79,79 @@ impl DaemonCommand {
     }
 
     /// Returns the slice of byte code written in the command.
-    pub fn byte_code(&self) -> &[DaemonCommandByte] {
+    pub fn byte_code(&self) -> &[u8] {
         self.0.as_ref()
     }
 
+    /// Returns the bytes between the size byte and the checksum.
+    ///
+    /// The returned slice is empty for commands that are not complete.
+    pub fn body(&self) -> &[u8] {
+        match self.0.len() {
+            0..=2 => &[],
+            len => &self.0[1..len - 1],
+        }
+    }
+
+    /// Decodes the prefix and the command byte, leaving the data as is.
+    ///
+    /// The prefix is optional, therefore the first byte is treated as a command if it is not a
+    /// prefix. Only the command must be valid, while all following bytes are treated as data.
+    pub fn decode(&self) -> Result<DecodedCommand<'_>, ProtocolError> {
+        use DaemonCommandByte::*;
+
+        let (first, rest) = self.body().split_first().ok_or(ProtocolError::MissingCommand)?;
+        match DaemonCommandByte::try_from(*first)? {
+            prefix @ (REQ | ACK | NACK) => {
+                let (cmd, data) = rest.split_first().ok_or(ProtocolError::MissingCommand)?;
+                match DaemonCommandByte::try_from(*cmd)? {
+                    REQ | ACK | NACK => Err(ProtocolError::MissingCommand),
+                    command => Ok(DecodedCommand { prefix: Some(prefix), command, data }),
+                }
+            },
+            command => Ok(DecodedCommand { prefix: None, command, data: rest }),
+        }
+    }
+
+    /// Checks if the command is complete and not corrupted.
+    ///
+    /// The size byte must match the amount of bytes in the command, while all bytes including the
+    /// checksum must sum up to zero.
+    pub fn is_valid(&self) -> bool {
+        self.0.len() >= 2 && self.size() == self.0.len() && Self::checksum(&self.0) == 0
+    }
+
+    /// Calculates the checksum byte for the provided bytes.
+    ///
+    /// The checksum is the value which makes the wrapping sum of all bytes equal to zero.
+    pub fn checksum(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg()
+    }
+
     /// Creates a initialization command that the daemon expects from the target's side.
-    pub fn init(id: usize) -> Self {
+    ///
+    /// The command carries the bridge's ID together with the supported protocol version and
+    /// capabilities, encoded independently of the architecture.
+    pub fn init(id: u64) -> Self {
         use DaemonCommandByte::*;
         let mut cmd = crate::dcommand!(REQ, CONN, BID);
-        cmd.push_data(&id.to_ne_bytes());
+        cmd.push_data(&SessionInfo::offer(id).encode()).expect("The offer always fits into one command.");
         cmd
     }
 
-    /// Asks to retry the command under a specific number.
+    /// Refuses a command which could not be decoded.
+    ///
+    /// The offending byte is sent back as data, if there is one.
+    pub fn nack(err: ProtocolError) -> Self {
+        use DaemonCommandByte::*;
+        let mut cmd = crate::dcommand!(NACK);
+        if let ProtocolError::UnknownOpcode(byte) = err {
+            cmd.push_data(&[byte]).expect("One byte always fits into the command.");
+        }
+        cmd
+    }
+
+    /// Asks the other side to send its last answer once more, e.g. after a corrupted command.
     pub fn retry() -> Self {
         use DaemonCommandByte::*;
         crate::dcommand!(REQ, RET)
@@ -90,32 +172,60 @@ impl DaemonCommand {
 #[macro_export]
 macro_rules! dcommand {
     ($($args:tt),*) => {{
-        let all = [$($args.into()),*];
+        let all: Vec<u8> = vec![$($args.into()),*];
         let size = all.len() + 2;
     
         assert!(size < u8::MAX.into(), "The amount of bytes in one command cannot be bigger than u8::MAX.");
 
         let mut v = Vec::with_capacity(size);
-        let checksum = size as u8;
+        let mut checksum = size as u8;
 
-        v.push(size.into()); // Pushing the size first.
+        v.push(size as u8); // Pushing the size first.
         for arg in all {
             // Pushing all 
-            let _ = checksum.wrapping_add(Into::<u8>::into(arg));
+            checksum = checksum.wrapping_add(arg);
             v.push(arg)
         }
 
         // Obtaining the amount of bytes to add for this command, so that the overall sum will be 0
-        let checksum = u8::MAX - checksum;
-        v.push(checksum.into());
+        v.push(checksum.wrapping_neg());
 
-        DaemonCommand(v)
+        $crate::sugar::conn::cmd::DaemonCommand::new(&v)
     }};
 }
 
+/// Command with decoded prefix and command bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedCommand<'a> {
+    /// Optional prefix of the command.
+    pub prefix: Option<DaemonCommandByte>,
+    /// The command itself.
+    pub command: DaemonCommandByte,
+    /// Raw data which comes after the command.
+    pub data: &'a [u8],
+}
+
+/// Errors which occur while decoding a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The byte does not represent any known command byte.
+    UnknownOpcode(u8),
+    /// The command has no command byte after the prefix or is empty.
+    MissingCommand,
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownOpcode(byte) => write!(f, "Protocol error: unknown command byte: {:#04x}.", byte),
+            Self::MissingCommand => write!(f, "Protocol error: no command byte found."),
+        }
+    }
+}
+
 /// A byte value of a command for both daemon and an application to communicate. 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DaemonCommandByte {
     // Prefixes (Second byte of the command.)
 
@@ -165,21 +275,94 @@ pub enum DaemonCommandByte {
     BID =   0x24,
 }
 
-impl Into<u8> for DaemonCommandByte {
-    fn into(self) -> u8 {
-        self as u8
+impl From<DaemonCommandByte> for u8 {
+    fn from(byte: DaemonCommandByte) -> Self {
+        byte as u8
     }
 }
 
-impl Into<DaemonCommandByte> for u8 {
-    fn into(self) -> DaemonCommandByte {
-        unsafe{ mem::transmute(self) }
+impl TryFrom<u8> for DaemonCommandByte {
+    type Error = ProtocolError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        use DaemonCommandByte::*;
+
+        Ok(match byte {
+            0x00 => REQ,
+            0x01 => ACK,
+            0x02 => NACK,
+            0xff => SIZE,
+            0x03 => CONN,
+            0x04 => SHUT,
+            0x05 => SEL,
+            0x06 => UNSEL,
+            0x07 => READ,
+            0x08 => RET,
+            0x20 => NAME,
+            0x21 => PART,
+            0x22 => FILE,
+            0x23 => DIR,
+            0x24 => BID,
+            _ => return Err(ProtocolError::UnknownOpcode(byte)),
+        })
     }
 }
 
 
-impl Into<DaemonCommandByte> for usize {
-    fn into(self) -> DaemonCommandByte {
-        unsafe{ mem::transmute(self as u8) }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DaemonCommandByte::*;
+
+    #[test]
+    fn checksum_makes_the_sum_zero() {
+        assert_eq!(DaemonCommand::checksum(&[]), 0);
+        assert_eq!(DaemonCommand::checksum(&[0x01]), 0xff);
+        assert_eq!(DaemonCommand::checksum(&[0xff, 0x02, 0x10]), 0xef);
+
+        let bytes = [0x05, 0x00, 0x07, 0x42];
+        let sum = bytes.iter().fold(DaemonCommand::checksum(&bytes), |sum, b| sum.wrapping_add(*b));
+        assert_eq!(sum, 0);
+    }
+
+    #[test]
+    fn valid_frame_with_data() {
+        let mut cmd = crate::dcommand!(REQ, READ, FILE);
+        cmd.push_data(b"docs/a.txt").unwrap();
+
+        assert!(cmd.is_valid());
+        assert_eq!(cmd.size(), cmd.byte_code().len());
+        let decoded = cmd.decode().unwrap();
+        assert_eq!(decoded.prefix, Some(REQ));
+        assert_eq!(decoded.command, READ);
+        assert_eq!(decoded.data, b"\x22docs/a.txt");
+    }
+
+    #[test]
+    fn corrupted_byte_fails_validation() {
+        let mut cmd = crate::dcommand!(ACK, SEL);
+        cmd.push_data(b"sda").unwrap();
+
+        let mut bytes = cmd.byte_code().to_vec();
+        bytes[3] ^= 0x01;
+        assert!(!DaemonCommand::new(&bytes).is_valid());
+        // Size byte not matching the amount of bytes.
+        let truncated = &cmd.byte_code()[..cmd.size() - 1];
+        assert!(!DaemonCommand::new(truncated).is_valid());
+        assert!(!DaemonCommand::corrupted().is_valid());
+    }
+
+    #[test]
+    fn data_too_large_is_refused() {
+        let mut cmd = crate::dcommand!(REQ, SEL);
+        let before = cmd.byte_code().to_vec();
+
+        assert_eq!(cmd.push_data(&[b'a'; 251]), Err(BridgeError::DataTooLarge));
+        assert_eq!(cmd.byte_code(), before);
+
+        // The biggest command still fits.
+        cmd.push_data(&[b'a'; 250]).unwrap();
+        assert_eq!(cmd.size(), u8::MAX as usize - 1);
+        assert!(cmd.is_valid());
     }
 }
@@ -0,0 +1,191 @@
+//! Streaming decoder for daemon commands.
+//!
+//! A transport does not preserve command boundaries. One transfer may contain only a part of a
+//! command, or several stacked commands at once. The decoder accumulates all obtained bytes and
+//! splits them into separate commands based on their size byte.
+
+use std::fmt::Display;
+
+use super::cmd::DaemonCommand;
+
+/// The biggest possible command, including the size byte and the checksum.
+pub const MAX_FRAME_SIZE: usize = u8::MAX as usize - 1;
+/// The smallest possible command: the size byte, one command byte and the checksum.
+pub const MIN_FRAME_SIZE: usize = 3;
+
+/// Errors which occur while splitting the obtained bytes into commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The size byte is bigger than the allowed command size.
+    Oversize(usize),
+    /// The size byte is too small to hold any command.
+    Undersize(usize),
+    /// The command ended before all of its bytes were obtained. Contains the obtained bytes.
+    Truncated(Vec<u8>),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Oversize(size) => write!(f, "Frame error: command of {} bytes is too big.", size),
+            Self::Undersize(size) => write!(f, "Frame error: command of {} bytes is too small.", size),
+            Self::Truncated(bytes) => write!(f, "Frame error: truncated command: {}", bytes.escape_ascii()),
+        }
+    }
+}
+
+/// Decoder which reassembles commands from a stream of bytes.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    pending: Vec<u8>,
+    max_size: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::with_max_size(MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameDecoder {
+    /// Creates a new decoder, which rejects all commands bigger than the provided size.
+    ///
+    /// The size is clamped to the range of possible command sizes.
+    pub fn with_max_size(max_size: usize) -> Self {
+        Self {
+            pending: Vec::with_capacity(MAX_FRAME_SIZE),
+            max_size: max_size.clamp(MIN_FRAME_SIZE, MAX_FRAME_SIZE),
+        }
+    }
+
+    /// Appends newly obtained bytes to the decoder.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete command, if any.
+    ///
+    /// On a malformed size byte the decoder resynchronizes by skipping bytes one by one, until
+    /// the first one which could be a size byte. A run of malformed bytes is reported only once.
+    pub fn next_frame(&mut self) -> Option<Result<DaemonCommand, FrameError>> {
+        let size = *self.pending.first()? as usize;
+
+        let err = match self.check(size) {
+            Err(err) => err,
+            Ok(s) if s > self.pending.len() => return None,
+            Ok(s) => {
+                let cmd = DaemonCommand::new(&self.pending[..s]);
+                self.pending.drain(..s);
+                return Some(Ok(cmd))
+            },
+        };
+
+        let skip = self.pending.iter()
+            .skip(1)
+            .position(|&byte| self.check(byte as usize).is_ok())
+            .map_or(self.pending.len(), |pos| pos + 1);
+        self.pending.drain(..skip);
+        Some(Err(err))
+    }
+
+    /// Drops the incomplete command, if any.
+    ///
+    /// Must be called when no more bytes are expected to arrive, e.g. after a read timeout.
+    pub fn flush(&mut self) -> Result<(), FrameError> {
+        if self.pending.is_empty() {
+            return Ok(())
+        }
+
+        Err(FrameError::Truncated(std::mem::take(&mut self.pending)))
+    }
+
+    /// Checks whether the size byte is allowed by this decoder.
+    fn check(&self, size: usize) -> Result<usize, FrameError> {
+        match size {
+            s if s < MIN_FRAME_SIZE => Err(FrameError::Undersize(s)),
+            s if s > self.max_size => Err(FrameError::Oversize(s)),
+            s => Ok(s),
+        }
+    }
+
+    /// Returns the amount of bytes waiting for the rest of their command.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sugar::conn::cmd::DaemonCommandByte::*;
+
+    fn command(data: &[u8]) -> DaemonCommand {
+        let mut cmd = crate::dcommand!(ACK, READ);
+        cmd.push_data(data).unwrap();
+        cmd
+    }
+
+    fn frames(decoder: &mut FrameDecoder) -> Vec<Result<Vec<u8>, FrameError>> {
+        std::iter::from_fn(|| decoder.next_frame())
+            .map(|frame| frame.map(|cmd| cmd.byte_code().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn split_command() {
+        let cmd = command(b"split between transfers");
+        let (head, tail) = cmd.byte_code().split_at(7);
+        let mut decoder = FrameDecoder::default();
+
+        decoder.push(head);
+        assert!(decoder.next_frame().is_none());
+        assert_eq!(decoder.pending(), 7);
+
+        decoder.push(tail);
+        assert_eq!(frames(&mut decoder), vec![Ok(cmd.byte_code().to_vec())]);
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn coalesced_commands() {
+        let (a, b) = (command(b"first"), command(b"second"));
+        let mut decoder = FrameDecoder::default();
+
+        decoder.push(&[a.byte_code(), b.byte_code()].concat());
+        assert_eq!(frames(&mut decoder), vec![Ok(a.byte_code().to_vec()), Ok(b.byte_code().to_vec())]);
+    }
+
+    #[test]
+    fn oversize_command_resyncs() {
+        let cmd = command(b"after garbage");
+        let mut decoder = FrameDecoder::with_max_size(32);
+
+        // Both leading bytes are too big, the valid command must still be found.
+        decoder.push(&[[200, 0xfe].as_slice(), cmd.byte_code()].concat());
+        assert_eq!(frames(&mut decoder), vec![Err(FrameError::Oversize(200)), Ok(cmd.byte_code().to_vec())]);
+    }
+
+    #[test]
+    fn undersize_command_resyncs() {
+        let cmd = command(b"x");
+        let mut decoder = FrameDecoder::default();
+
+        decoder.push(&[[0, 1].as_slice(), cmd.byte_code()].concat());
+        assert_eq!(frames(&mut decoder), vec![Err(FrameError::Undersize(0)), Ok(cmd.byte_code().to_vec())]);
+    }
+
+    #[test]
+    fn truncated_command() {
+        let cmd = command(b"never finished");
+        let mut decoder = FrameDecoder::default();
+
+        decoder.push(&cmd.byte_code()[..5]);
+        assert!(decoder.next_frame().is_none());
+        assert_eq!(decoder.flush(), Err(FrameError::Truncated(cmd.byte_code()[..5].to_vec())));
+        assert_eq!(decoder.flush(), Ok(()));
+
+        // Decoding starts over with the next command.
+        decoder.push(cmd.byte_code());
+        assert_eq!(frames(&mut decoder), vec![Ok(cmd.byte_code().to_vec())]);
+    }
+}
@@ -0,0 +1,101 @@
+//! Removal of the USB device noticed via libusb hotplug events.
+//!
+//! Hotplug is not available everywhere, e.g. a device opened via the file descriptor of Android is
+//! never seen by the device discovery of libusb. There the removal is only noticed by the failed
+//! transfers, while here the bridge fails right away, even if it is idle.
+
+use std::{
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    thread,
+    time::Duration,
+};
+use rusb::{constants::LIBUSB_CAP_HAS_HOTPLUG, ffi, Context, Device, Hotplug, HotplugBuilder, UsbContext};
+use tokio::sync::mpsc::Sender;
+
+use super::client::BridgeMessage;
+use crate::sugar::errors::{BridgeError, SugarError};
+
+/// Longest time the event thread waits before checking whether it must stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches for the removal of the device. Stops once dropped.
+///
+/// The callback stays registered until the event thread notices the request.
+pub(crate) struct HotplugMonitor {
+    stop: Arc<AtomicBool>,
+}
+
+impl HotplugMonitor {
+    /// Starts watching the device. The bridge obtains [`BridgeError::DeviceLost`] once it is gone.
+    ///
+    /// Returns nothing, if hotplug is not available for the device.
+    pub(crate) fn watch(device: &Device<Context>, tx: Sender<BridgeMessage>) -> Option<Self> {
+        if unsafe { ffi::libusb_has_capability(LIBUSB_CAP_HAS_HOTPLUG) } == 0 {
+            log::info!("Hotplug is not supported, the device removal is noticed by transfers.");
+            return None
+        }
+
+        let context = device.context().clone();
+        let descriptor = device.device_descriptor().ok()?;
+        let watcher = Watcher { bus: device.bus_number(), address: device.address(), tx };
+
+        let registration = HotplugBuilder::new()
+            .vendor_id(descriptor.vendor_id())
+            .product_id(descriptor.product_id())
+            .register(&context, Box::new(watcher))
+            .map_err(|err| log::warn!("Unable to watch the device removal: {}", err))
+            .ok()?;
+
+        // Hotplug callbacks are only called while libusb events are handled.
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let spawned = thread::Builder::new().name("sugar-hotplug".into()).spawn(move || {
+            let _registration = registration;
+
+            while !stopped.load(Ordering::Relaxed) {
+                if let Err(err) = context.handle_events(Some(POLL_INTERVAL)) {
+                    log::error!("Hotplug: unable to handle USB events: {}", err);
+                    break
+                }
+            }
+        });
+        if let Err(err) = spawned {
+            log::warn!("Unable to spawn the hotplug thread: {}", err);
+            return None
+        }
+
+        log::info!("Watching the device removal via hotplug events.");
+        Some(Self { stop })
+    }
+}
+
+impl Drop for HotplugMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Hotplug callback, which only cares about the watched device.
+struct Watcher {
+    bus: u8,
+    address: u8,
+    tx: Sender<BridgeMessage>,
+}
+
+impl Hotplug<Context> for Watcher {
+    fn device_arrived(&mut self, device: Device<Context>) {
+        log::debug!("Hotplug: device arrived at bus {:03}, address {:03}.", device.bus_number(), device.address());
+    }
+
+    fn device_left(&mut self, device: Device<Context>) {
+        if (device.bus_number(), device.address()) != (self.bus, self.address) {
+            return
+        }
+
+        log::warn!("Hotplug: the device has been detached.");
+        // Called on the event thread, which must not wait for the bridge.
+        if self.tx.try_send(BridgeMessage::Failed(SugarError::new(BridgeError::DeviceLost))).is_err() {
+            log::error!("Hotplug: unable to notify the bridge about the device removal.");
+        }
+    }
+}
@@ -0,0 +1,484 @@
+//! Dedicated reader and writer of the bridge.
+//!
+//! Transports only provide blocking I/O, which must never run on the workers of the runtime.
+//! Instead, each running bridge owns two threads: the reader streams transfers from the bus and
+//! turns them into commands, while the writer performs the writes requested by the bridge.
+//!
+//! Failed transfers of both directions are recovered by the reader alone. Its pipeline is dropped
+//! first, which cancels all transfers in flight, and no write is done until the recovery is over,
+//! since libusb must not clear a halt condition or reset the device under submitted transfers.
+
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{atomic::{AtomicBool, Ordering}, mpsc as std_mpsc, Arc, Mutex as StdMutex, MutexGuard},
+    thread::{self, JoinHandle},
+};
+use rusb::{Direction, Error as RusbError};
+use tokio::{
+    runtime,
+    sync::{mpsc::{error::TrySendError, Sender}, oneshot, Mutex, Notify},
+};
+
+use super::{
+    buf::Buffer,
+    client::BridgeMessage,
+    cmd::DaemonCommand,
+    recovery::{self, Recoverer, Recovery},
+    stats::BridgeStats,
+    transport::Transport,
+};
+
+/// Reader of the bus. Stops once dropped.
+///
+/// The thread notices the request on the next completed transfer, which happens at least once per
+/// [`TIMEOUT`](super::transport::TIMEOUT), or right away while it waits for the bridge. Reads,
+/// which fail beyond recovery, are reported to the bridge as [`BridgeMessage::Failed`].
+pub(crate) struct Reader {
+    stop: Arc<Stop>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Request to stop the reader.
+#[derive(Default)]
+struct Stop {
+    requested: AtomicBool,
+    /// Wakes the reader up, while it waits for the bridge.
+    notify: Notify,
+}
+
+impl Stop {
+    fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+        // The permit is kept, if the reader is not waiting right now.
+        self.notify.notify_one();
+    }
+
+    fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+}
+
+/// State shared by the reader and the writer.
+#[derive(Default)]
+struct Shared {
+    /// Held by the writer during each write, so that the device is never recovered meanwhile.
+    writing: StdMutex<()>,
+    recoverer: StdMutex<Recoverer>,
+    /// Wakes the reader up for a failed write, while it waits for the bridge.
+    recovery: Notify,
+}
+
+/// Failed write, which the reader recovers from, along with the channel for the outcome.
+type RecoveryRequest = (RusbError, std_mpsc::Sender<bool>);
+
+/// Locks the mutex, even if the other thread has panicked while holding it.
+fn lock<T>(mutex: &StdMutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Starts reading the bus, passing all obtained commands to the bridge, and returns the writer
+/// which shares the recovery with the reader.
+pub(crate) fn spawn<T: Transport>(
+    device: Arc<T>,
+    buf: Arc<Mutex<Box<dyn Buffer>>>,
+    tx: Sender<BridgeMessage>,
+    stats: Arc<BridgeStats>,
+) -> io::Result<(Reader, Writer)> {
+    let shared = Arc::new(Shared::default());
+    let (requests, recoveries) = std_mpsc::channel();
+
+    let writer = Writer::spawn(device.clone(), shared.clone(), requests)?;
+    let reader = Reader::spawn(device, buf, tx, stats, shared, recoveries)?;
+    Ok((reader, writer))
+}
+
+impl Reader {
+    fn spawn<T: Transport>(
+        device: Arc<T>,
+        buf: Arc<Mutex<Box<dyn Buffer>>>,
+        tx: Sender<BridgeMessage>,
+        stats: Arc<BridgeStats>,
+        shared: Arc<Shared>,
+        recoveries: std_mpsc::Receiver<RecoveryRequest>,
+    ) -> io::Result<Self> {
+        let stop = Arc::new(Stop::default());
+        let stopped = stop.clone();
+        // Only used to wait for the bridge, so that the reader can still be stopped or interrupted
+        // by a failed write meanwhile.
+        let waiter = runtime::Builder::new_current_thread().build()?;
+        let notified = shared.clone();
+        let deliver = move |msg, interruptible: bool| {
+            let interrupt = interruptible.then_some(&notified.recovery);
+            Self::deliver(&waiter, &tx, &stopped, interrupt, msg)
+        };
+        let stopped = stop.clone();
+
+        let thread = thread::Builder::new().name("sugar-reader".into()).spawn(move || {
+            let (depth, size) = {
+                let buffer = buf.blocking_lock();
+                (buffer.in_flight(), buffer.transfer_size())
+            };
+            log::info!("Reading the bus with {} transfers of {} bytes in flight.", depth, size);
+
+            // Commands, which were not delivered before a recovery, come first afterwards.
+            let mut backlog = VecDeque::new();
+            let flush = |backlog: &mut VecDeque<BridgeMessage>| {
+                while let Some(msg) = backlog.pop_front() {
+                    match deliver(msg, true) {
+                        Ok(_) => (),
+                        Err(Undelivered::Interrupted(msg)) => {
+                            backlog.push_front(msg);
+                            return false
+                        },
+                        Err(Undelivered::Closed) => {
+                            log::error!("Reader: Unable to send data, the bridge is gone or the reader is stopped, aborting...");
+                            backlog.clear();
+                            return false
+                        },
+                    }
+                }
+                true
+            };
+
+            let fatal = loop {
+                // Failed transfer, which stops the pipeline to be recovered from.
+                let mut failure = None;
+
+                let result = match flush(&mut backlog) {
+                    true => device.stream(depth, size, &mut |result| {
+                        if stopped.is_requested() {
+                            return false
+                        }
+                        if let Ok((err, reply)) = recoveries.try_recv() {
+                            failure = Some((Direction::Out, err, Some(reply)));
+                            return false
+                        }
+
+                        match result {
+                            Ok(data) => {
+                                log::debug!("Obtained {} bytes of oncoming data", data.len());
+                                stats.received(data.len());
+                                lock(&shared.recoverer).succeeded();
+
+                                // One transfer may hold a part of a command or several commands at once.
+                                let mut buffer = buf.blocking_lock();
+                                buffer.push(data);
+                                backlog.extend(std::iter::from_fn(|| buffer.next_frame()).map(|frame| {
+                                    BridgeMessage::Command(frame.unwrap_or_else(|err| {
+                                        log::error!("Reader: {}", err);
+                                        // Rejected bytes are passed as a corrupted command, so that the
+                                        // parser will request a retransmission.
+                                        DaemonCommand::corrupted()
+                                    }))
+                                }));
+                            },
+                            // No more data is coming, so an incomplete command will never be finished.
+                            Err(RusbError::Timeout) => {
+                                stats.timeout();
+                                if let Err(err) = buf.blocking_lock().flush() {
+                                    log::error!("Reader: {}", err);
+                                    backlog.push_back(BridgeMessage::Command(DaemonCommand::corrupted()));
+                                }
+                            },
+                            Err(err) => {
+                                stats.read_error();
+                                log::error!("Reader: Error while reading the data from the USB bus: {:#?}", err);
+
+                                if Recovery::classify(err) != Recovery::Retry {
+                                    failure = Some((Direction::In, err, None));
+                                    return false
+                                }
+                            },
+                        }
+                        flush(&mut backlog)
+                    }),
+                    false => Ok(()),
+                };
+
+                if stopped.is_requested() {
+                    log::info!("Reader is stopped.");
+                    break None
+                }
+                // The bridge may be waiting for the failed write, while the reader waits for the bridge.
+                if failure.is_none() {
+                    failure = recoveries.try_recv().ok().map(|(err, reply)| (Direction::Out, err, Some(reply)));
+                }
+
+                // The pipeline is gone at this point, so no transfer is in flight.
+                let (direction, err, reply) = match (result, failure) {
+                    (_, Some(failure)) => failure,
+                    // Interrupted by a request, which was already taken by the pipeline.
+                    (Ok(_), None) if !backlog.is_empty() => continue,
+                    (Ok(_), None) => {
+                        log::info!("Reader is stopped.");
+                        break None
+                    },
+                    (Err(err), None) => {
+                        log::error!("Reader has failed: {}", err);
+                        break Some(recovery::fatal(err))
+                    },
+                };
+
+                let recovered = {
+                    let _writing = lock(&shared.writing);
+                    lock(&shared.recoverer).recover(&*device, direction, err)
+                };
+
+                match (recovered, reply) {
+                    // Failed writes are reported to the bridge by the writer itself.
+                    (recovered, Some(reply)) => {
+                        reply.send(recovered.is_ok()).ok();
+                    },
+                    (Ok(_), None) => (),
+                    (Err(err), None) => break Some(err),
+                }
+            };
+
+            // Writes waiting for a recovery fail right away from now on.
+            drop(recoveries);
+            if let Some(err) = fatal.filter(|_| !stopped.is_requested()) {
+                deliver(BridgeMessage::Failed(err), false).ok();
+            }
+        })?;
+
+        Ok(Self { stop, thread: Some(thread) })
+    }
+
+    /// Stops the reader and waits until it releases the transport.
+    ///
+    /// The bridge does not have to receive anything meanwhile.
+    pub(crate) async fn stop(mut self) {
+        self.stop.request();
+
+        if let Some(thread) = self.thread.take() {
+            if tokio::task::spawn_blocking(move || thread.join()).await.is_err() {
+                log::error!("Unable to wait for the reader.");
+            }
+        }
+    }
+
+    /// Passes the message to the bridge.
+    ///
+    /// The bridge stops the reader without receiving anything, and it may wait for a write, which
+    /// must be recovered first. A full channel therefore never blocks the reader alone.
+    fn deliver(
+        waiter: &runtime::Runtime,
+        tx: &Sender<BridgeMessage>,
+        stop: &Stop,
+        interrupt: Option<&Notify>,
+        msg: BridgeMessage,
+    ) -> Result<(), Undelivered> {
+        let msg = match tx.try_send(msg) {
+            Ok(_) => return Ok(()),
+            Err(TrySendError::Closed(_)) => return Err(Undelivered::Closed),
+            Err(TrySendError::Full(msg)) => msg,
+        };
+
+        waiter.block_on(async {
+            let interrupted = async {
+                match interrupt {
+                    Some(notify) => notify.notified().await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                permit = tx.reserve() => match permit {
+                    Ok(permit) => {
+                        permit.send(msg);
+                        Ok(())
+                    },
+                    Err(_) => Err(Undelivered::Closed),
+                },
+                _ = stop.notify.notified() => Err(Undelivered::Closed),
+                _ = interrupted => Err(Undelivered::Interrupted(msg)),
+            }
+        })
+    }
+}
+
+/// Message, which was not passed to the bridge.
+enum Undelivered {
+    /// The bridge is gone or the reader is stopped.
+    Closed,
+    /// A failed write must be recovered first, so the message is returned to be sent afterwards.
+    Interrupted(BridgeMessage),
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.stop.request();
+    }
+}
+
+/// Write request along with the channel for its result.
+type Job = (Vec<u8>, oneshot::Sender<Result<usize, RusbError>>);
+
+/// Writer of the bus. The thread is stopped once the writer is dropped.
+///
+/// A failed write is recovered by the reader, which notices the request on its next completed
+/// transfer, or right away while it waits for the bridge. Once the reader is gone, the write fails
+/// right away.
+pub(crate) struct Writer {
+    tx: std_mpsc::Sender<Job>,
+}
+
+impl Writer {
+    fn spawn<T: Transport>(device: Arc<T>, shared: Arc<Shared>, requests: std_mpsc::Sender<RecoveryRequest>) -> io::Result<Self> {
+        let (tx, rx) = std_mpsc::channel::<Job>();
+
+        thread::Builder::new().name("sugar-writer".into()).spawn(move || {
+            let write = |data: &[u8]| {
+                let _writing = lock(&shared.writing);
+                device.write(data)
+            };
+            // Waits until the reader has recovered from the failed write.
+            let recover = |err| {
+                let (reply, outcome) = std_mpsc::channel();
+                if requests.send((err, reply)).is_err() {
+                    return false
+                }
+                shared.recovery.notify_one();
+                outcome.recv().unwrap_or(false)
+            };
+
+            for (data, reply) in rx {
+                // The write is repeated once, if the transport was recovered.
+                let result = write(&data).or_else(|err| match Recovery::classify(err) {
+                    Recovery::Retry => Err(err),
+                    _ if recover(err) => write(&data),
+                    _ => Err(err),
+                });
+                if result.is_ok() {
+                    lock(&shared.recoverer).succeeded();
+                }
+                reply.send(result).ok();
+            }
+            log::info!("Writer is stopped.");
+        })?;
+
+        Ok(Self { tx })
+    }
+
+    /// Writes the data to the bus without blocking the runtime.
+    pub(crate) async fn write(&self, data: Vec<u8>) -> Result<usize, RusbError> {
+        let (reply, rx) = oneshot::channel();
+        self.tx.send((data, reply)).map_err(|_| RusbError::NoDevice)?;
+
+        rx.await.unwrap_or(Err(RusbError::NoDevice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::sugar::{conn::{buf::USBV2Buf, transport::MemoryTransport}, runtime::{self as sugar_runtime, block_on}};
+
+    /// Memory transport, whose first write stalls.
+    #[derive(Default)]
+    struct Stalling {
+        inner: Option<MemoryTransport>,
+        stalled: AtomicBool,
+        reading: AtomicBool,
+        /// Direction, thread and whether a read was in flight, once the halt is cleared.
+        cleared: StdMutex<Option<(Direction, Option<String>, bool)>>,
+    }
+
+    impl Transport for Stalling {
+        fn read(&self, buf: &mut [u8]) -> Result<usize, RusbError> {
+            self.reading.store(true, Ordering::SeqCst);
+            let result = self.inner.as_ref().unwrap().read(buf);
+            self.reading.store(false, Ordering::SeqCst);
+            result
+        }
+
+        fn write(&self, buf: &[u8]) -> Result<usize, RusbError> {
+            if !self.stalled.swap(true, Ordering::SeqCst) {
+                return Err(RusbError::Pipe)
+            }
+            self.inner.as_ref().unwrap().write(buf)
+        }
+
+        fn clear_halt(&self, direction: Direction) -> Result<(), RusbError> {
+            let thread = thread::current().name().map(String::from);
+            *lock(&self.cleared) = Some((direction, thread, self.reading.load(Ordering::SeqCst)));
+            Ok(())
+        }
+
+        fn info(&self) -> String {
+            "Stalling transport".to_string()
+        }
+
+        fn machine(&self) -> String {
+            "stalling".to_string()
+        }
+    }
+
+    #[test]
+    fn stops_while_the_bridge_is_not_receiving() {
+        let (host, target) = MemoryTransport::pair();
+        let buf: Box<dyn Buffer> = Box::new(USBV2Buf::default());
+        let (tx, _rx) = mpsc::channel(1);
+        let (reader, _writer) = spawn(Arc::new(host), Arc::new(Mutex::new(buf)), tx, Arc::default()).unwrap();
+
+        // The first command fills the channel, while the next one waits for space.
+        for _ in 0..3 {
+            target.write(DaemonCommand::retry().byte_code()).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+
+        block_on(async { tokio::time::timeout(Duration::from_secs(5), reader.stop()).await }).unwrap();
+    }
+
+    #[test]
+    fn stalled_write_is_recovered_by_the_reader() {
+        let (host, target) = MemoryTransport::pair();
+        let device = Arc::new(Stalling { inner: Some(host), ..Default::default() });
+        let buf: Box<dyn Buffer> = Box::new(USBV2Buf::default());
+        let (tx, _rx) = mpsc::channel(16);
+        let (reader, writer) = spawn(device.clone(), Arc::new(Mutex::new(buf)), tx, Arc::default()).unwrap();
+
+        let data = DaemonCommand::retry().byte_code().to_vec();
+        let write = sugar_runtime::spawn(async move { writer.write(data).await });
+        // Completes the read in flight, so that the reader notices the request sooner.
+        target.write(DaemonCommand::retry().byte_code()).unwrap();
+
+        assert!(block_on(write).unwrap().is_ok());
+        assert_eq!(target.read(&mut [0; 64]).unwrap(), DaemonCommand::retry().byte_code().len());
+        // The halt was cleared by the reader, while none of its transfers were in flight.
+        let cleared = lock(&device.cleared).take();
+        assert_eq!(cleared, Some((Direction::Out, Some("sugar-reader".to_string()), false)));
+
+        block_on(reader.stop());
+    }
+
+    #[test]
+    fn stalled_write_is_recovered_while_the_channel_is_full() {
+        let (host, target) = MemoryTransport::pair();
+        let device = Arc::new(Stalling { inner: Some(host), ..Default::default() });
+        let buf: Box<dyn Buffer> = Box::new(USBV2Buf::default());
+        let (tx, mut rx) = mpsc::channel(1);
+        let (reader, writer) = spawn(device.clone(), Arc::new(Mutex::new(buf)), tx, Arc::default()).unwrap();
+
+        // The bridge is busy with the write, so the reader waits for space in the channel.
+        for _ in 0..3 {
+            target.write(DaemonCommand::retry().byte_code()).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+
+        let data = DaemonCommand::retry().byte_code().to_vec();
+        let written = block_on(async { tokio::time::timeout(Duration::from_secs(5), writer.write(data)).await });
+        assert!(written.unwrap().is_ok());
+        assert!(lock(&device.cleared).is_some());
+
+        // Commands, which waited for the recovery, are delivered afterwards.
+        for _ in 0..3 {
+            assert!(matches!(block_on(rx.recv()), Some(BridgeMessage::Command(_))));
+        }
+        block_on(reader.stop());
+    }
+}
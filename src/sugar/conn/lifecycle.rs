@@ -0,0 +1,170 @@
+//! Lifecycle of the bridge.
+//!
+//! The bridge moves through a fixed set of states, from opening the transport until it is closed
+//! or has failed. Only the transitions allowed by [`LifecycleState::allows`] are performed, while
+//! all others are refused. Subscribers observe the latest state of the bridge, and the front-end
+//! obtains each change as [`Event::State`].
+
+use std::fmt::Display;
+use tokio::sync::watch;
+
+use crate::sugar::events::{self, Event};
+
+/// State of the bridge.
+///
+/// The numeric values are shared with the front-end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(i32)]
+pub enum LifecycleState {
+    /// Created, but not connected yet.
+    Idle = 0,
+    /// Transport is being opened, which also happens before each reconnection.
+    Opening = 1,
+    /// Initialization command is sent, the session is being negotiated.
+    Handshaking = 2,
+    /// Session is negotiated.
+    Connected = 3,
+    /// Shutdown is requested, pending requests are dropped and the transport is released.
+    Draining = 4,
+    /// Bridge is closed properly.
+    Closed = 5,
+    /// Bridge has failed, e.g. since the device is gone.
+    Failed = 6,
+}
+
+impl LifecycleState {
+    /// Whether the bridge may move from this state to the provided one.
+    pub fn allows(self, next: Self) -> bool {
+        use LifecycleState::*;
+
+        matches!((self, next),
+            (Idle, Opening)
+            | (Opening, Handshaking | Failed)
+            | (Handshaking | Connected, Opening | Draining | Failed)
+            | (Handshaking, Connected)
+            | (Draining, Closed | Failed)
+        )
+    }
+
+    /// Whether the bridge will never leave this state.
+    pub fn is_final(self) -> bool {
+        matches!(self, Self::Closed | Self::Failed)
+    }
+}
+
+/// Transition, which is not allowed from the current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionError {
+    pub from: LifecycleState,
+    pub to: LifecycleState,
+}
+
+impl Display for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Lifecycle error: bridge cannot move from {:?} to {:?}.", self.from, self.to)
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+/// Current state of the bridge along with its subscribers.
+#[derive(Debug)]
+pub struct Lifecycle {
+    tx: watch::Sender<LifecycleState>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        // Subscribers come and go, so the initial receiver is not needed.
+        let (tx, _) = watch::channel(LifecycleState::Idle);
+        Self { tx }
+    }
+}
+
+impl Lifecycle {
+    pub fn state(&self) -> LifecycleState {
+        *self.tx.borrow()
+    }
+
+    /// Returns a receiver of all following changes.
+    ///
+    /// Subscribers, which are slower than the bridge, only observe the latest state.
+    pub fn subscribe(&self) -> watch::Receiver<LifecycleState> {
+        self.tx.subscribe()
+    }
+
+    /// Moves the bridge to the provided state, if the transition is allowed.
+    ///
+    /// Moving to the current state does nothing.
+    pub(crate) fn advance(&self, next: LifecycleState) -> Result<(), TransitionError> {
+        let mut result = Ok(());
+
+        let changed = self.tx.send_if_modified(|state| {
+            if *state == next {
+                return false
+            }
+            if !state.allows(next) {
+                result = Err(TransitionError { from: *state, to: next });
+                return false
+            }
+
+            log::info!("Bridge state: {:?} -> {:?}", state, next);
+            *state = next;
+            true
+        });
+
+        if changed {
+            events::emit(Event::State(next));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LifecycleState::*;
+
+    const STATES: [LifecycleState; 7] = [Idle, Opening, Handshaking, Connected, Draining, Closed, Failed];
+
+    #[test]
+    fn transition_table() {
+        let allowed = [
+            (Idle, Opening),
+            (Opening, Handshaking), (Opening, Failed),
+            (Handshaking, Opening), (Handshaking, Connected), (Handshaking, Draining), (Handshaking, Failed),
+            (Connected, Opening), (Connected, Draining), (Connected, Failed),
+            (Draining, Closed), (Draining, Failed),
+        ];
+
+        for from in STATES {
+            for to in STATES {
+                assert_eq!(from.allows(to), allowed.contains(&(from, to)), "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn final_states_are_never_left() {
+        for state in STATES {
+            assert_eq!(state.is_final(), STATES.iter().all(|&next| !state.allows(next)), "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn refused_transition_keeps_the_state() {
+        let lifecycle = Lifecycle::default();
+        let mut state = lifecycle.subscribe();
+
+        assert_eq!(lifecycle.advance(Closed), Err(TransitionError { from: Idle, to: Closed }));
+        assert_eq!(lifecycle.state(), Idle);
+        assert!(!state.has_changed().unwrap());
+
+        lifecycle.advance(Opening).unwrap();
+        // Moving to the current state is not a change.
+        lifecycle.advance(Opening).unwrap();
+        assert!(state.has_changed().unwrap());
+        assert_eq!(*state.borrow_and_update(), Opening);
+        assert!(!state.has_changed().unwrap());
+    }
+}
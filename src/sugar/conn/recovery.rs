@@ -0,0 +1,150 @@
+//! Recovery from failed transfers and reconnection of failed bridges.
+//!
+//! Each failed transfer is classified first. Timeouts are expected on an idle bus, a stalled
+//! endpoint gets its halt condition cleared and a broken bus is reset, while a device which is
+//! gone closes the bridge. If the recovery does not help, the bridge fails and may then be
+//! reconnected according to the [`ReconnectPolicy`].
+
+use std::time::Duration;
+use rusb::{Direction, Error as RusbError};
+
+use super::transport::Transport;
+use crate::sugar::errors::{BridgeError, ErrorKind, SugarError};
+
+/// Amount of recoveries in a row, after which the transfers are considered broken.
+const MAX_RECOVERIES: u32 = 3;
+
+/// Action taken after a failed transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Nothing to do, the next transfer may succeed, e.g. after a timeout.
+    Retry,
+    /// The endpoint has stalled, so its halt condition is cleared.
+    ClearHalt,
+    /// The device is reset, e.g. after an error on the bus.
+    Reset,
+    /// The device is gone, so the bridge is closed.
+    Shutdown,
+}
+
+impl Recovery {
+    pub fn classify(err: RusbError) -> Self {
+        match err {
+            RusbError::Timeout | RusbError::Interrupted | RusbError::Busy | RusbError::Overflow => Self::Retry,
+            RusbError::Pipe => Self::ClearHalt,
+            RusbError::NoDevice | RusbError::NotFound => Self::Shutdown,
+            _ => Self::Reset,
+        }
+    }
+}
+
+/// Error, which closes the bridge after the failed transfer.
+pub(crate) fn fatal(err: RusbError) -> SugarError {
+    match Recovery::classify(err) {
+        Recovery::Shutdown => SugarError::new(BridgeError::DeviceLost).with_source(err),
+        _ => SugarError::new(BridgeError::TransferError).with_source(err),
+    }
+}
+
+/// Recovers the transport after failed transfers.
+///
+/// A stall, which comes back right after its halt condition was cleared, is escalated to a reset.
+#[derive(Debug, Default)]
+pub(crate) struct Recoverer {
+    /// Recoveries in a row, without a successful transfer in between.
+    attempts: u32,
+}
+
+impl Recoverer {
+    /// Marks the transfer as successful, so the following failures are handled from scratch.
+    pub(crate) fn succeeded(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Handles the failed transfer in the provided direction.
+    ///
+    /// Returns the action which was taken, or the error if the bridge must be closed.
+    pub(crate) fn recover<T: Transport + ?Sized>(&mut self, device: &T, direction: Direction, err: RusbError) -> Result<Recovery, SugarError> {
+        let action = match Recovery::classify(err) {
+            Recovery::Retry => return Ok(Recovery::Retry),
+            Recovery::Shutdown => {
+                log::error!("Device is gone: {}", err);
+                return Err(fatal(err))
+            },
+            Recovery::ClearHalt if self.attempts > 0 => Recovery::Reset,
+            action => action,
+        };
+
+        self.attempts += 1;
+        if self.attempts > MAX_RECOVERIES {
+            log::error!("Transfers keep failing after {} recoveries: {}", MAX_RECOVERIES, err);
+            return Err(fatal(err))
+        }
+
+        log::warn!("Recovering from the failed transfer ({}): {:?}, attempt {}/{}.", err, action, self.attempts, MAX_RECOVERIES);
+        let result = match action {
+            Recovery::ClearHalt => device.clear_halt(direction),
+            _ => device.reset(),
+        };
+
+        result.map(|_| action).map_err(|err| {
+            log::error!("Unable to recover the transport: {}", err);
+            fatal(err)
+        })
+    }
+}
+
+/// Policy of reconnection after the bridge has failed.
+///
+/// Each attempt redoes the handshake with the same bridge ID, while clients of the bridge stay
+/// valid. Requests pending at the time of the failure are not replayed, they fail with
+/// [`ClientError::BridgeClosed`](super::client::ClientError::BridgeClosed) instead. The delay
+/// between attempts grows exponentially.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Amount of attempts in a row, where zero disables the reconnection.
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Factor, by which the delay grows after each attempt.
+    pub multiplier: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Policy, which never reconnects.
+    pub const DISABLED: Self = Self {
+        max_attempts: 0,
+        initial_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+        multiplier: 1,
+    };
+
+    /// Returns the delay before the attempt, which is counted from zero, or nothing if the bridge
+    /// must not be reconnected after the error.
+    pub fn delay(&self, attempt: u32, err: &SugarError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !Self::recoverable(err) {
+            return None
+        }
+
+        let factor = self.multiplier.max(1).saturating_pow(attempt);
+        Some(self.initial_delay.saturating_mul(factor).min(self.max_delay))
+    }
+
+    /// Errors, after which the target may still answer on the same device.
+    pub fn recoverable(err: &SugarError) -> bool {
+        matches!(err.kind(), ErrorKind::Bridge(
+            BridgeError::TransferError | BridgeError::ConnectionTimeout | BridgeError::RetryLimitExceeded
+        ))
+    }
+}
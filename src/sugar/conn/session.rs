@@ -0,0 +1,174 @@
+//! Protocol version and capability negotiation.
+//!
+//! The mobile device and the target can be of different architectures, so nothing in the
+//! handshake may depend on the native width or endianness. The handshake data which comes after
+//! `CONN BID` is always encoded as follows, with all numbers in little endian:
+//!
+//! ```text
+//! *-----------*---------*--------------*
+//! | BRIDGE ID | VERSION | CAPABILITIES |
+//! *-----------*---------*--------------*
+//!    8 bytes     1 byte     4 bytes
+//! ```
+//!
+//! Both sides send their own version and capabilities, while the answer of the target holds the
+//! negotiated ones: the lowest version and capabilities supported by both sides.
+
+use std::{fmt::Display, ops::{BitAnd, BitOr}};
+
+/// Current version of the communication protocol.
+pub const PROTOCOL_VERSION: u8 = 1;
+/// The oldest version of the protocol, this side can still communicate with.
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// Bitmap of optional protocol features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    /// No optional features.
+    pub const NONE: Self = Self(0);
+    /// Transferred file data can be compressed.
+    pub const COMPRESSION: Self = Self(1 << 0);
+    /// Files can be read from an arbitrary offset.
+    pub const RANGED_READ: Self = Self(1 << 1);
+    /// The target can calculate hashes of files.
+    pub const HASHING: Self = Self(1 << 2);
+
+    /// Features supported by this side of the bridge.
+    pub const SUPPORTED: Self = Self::NONE;
+
+    /// Checks if all provided features are present.
+    pub fn contains(self, other: Self) -> bool {
+        self & other == other
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Errors which occur during the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// The handshake data has a wrong length.
+    Malformed(usize),
+    /// The other side only supports an older version of the protocol.
+    UnsupportedVersion(u8),
+    /// The answer was meant for another bridge.
+    WrongBridge(u64),
+}
+
+impl Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(len) => write!(f, "Handshake error: {} bytes of data is not a valid handshake.", len),
+            Self::UnsupportedVersion(version) => write!(f, "Handshake error: protocol version {} is not supported.", version),
+            Self::WrongBridge(id) => write!(f, "Handshake error: answer is meant for the bridge {:#018x}.", id),
+        }
+    }
+}
+
+/// Information about the session, shared by both sides of the bridge.
+///
+/// Sent by the bridge as an offer and obtained back from the target as a negotiated result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// ID of the bridge, which owns the session.
+    pub bridge_id: u64,
+    /// Version of the protocol.
+    pub version: u8,
+    /// Optional features of the protocol.
+    pub capabilities: Capabilities,
+}
+
+impl SessionInfo {
+    /// Amount of bytes in the encoded handshake.
+    pub const ENCODED_SIZE: usize = 13;
+
+    /// Creates an offer of this side of the bridge.
+    pub fn offer(bridge_id: u64) -> Self {
+        Self {
+            bridge_id,
+            version: PROTOCOL_VERSION,
+            capabilities: Capabilities::SUPPORTED,
+        }
+    }
+
+    /// Encodes the session info as handshake data.
+    pub fn encode(&self) -> [u8; Self::ENCODED_SIZE] {
+        let mut out = [0u8; Self::ENCODED_SIZE];
+        out[..8].copy_from_slice(&self.bridge_id.to_le_bytes());
+        out[8] = self.version;
+        out[9..].copy_from_slice(&self.capabilities.0.to_le_bytes());
+        out
+    }
+
+    /// Decodes the session info from handshake data.
+    pub fn decode(data: &[u8]) -> Result<Self, HandshakeError> {
+        let data: &[u8; Self::ENCODED_SIZE] = data.try_into()
+            .map_err(|_| HandshakeError::Malformed(data.len()))?;
+
+        let mut id = [0u8; 8];
+        let mut caps = [0u8; 4];
+        id.copy_from_slice(&data[..8]);
+        caps.copy_from_slice(&data[9..]);
+
+        Ok(Self {
+            bridge_id: u64::from_le_bytes(id),
+            version: data[8],
+            capabilities: Capabilities(u32::from_le_bytes(caps)),
+        })
+    }
+
+    /// Negotiates the session between this side and the offer of the other side.
+    ///
+    /// The lowest version and the common capabilities are chosen, while the bridge ID is taken
+    /// from the offer.
+    pub fn negotiate(&self, offer: &Self) -> Result<Self, HandshakeError> {
+        let version = self.version.min(offer.version);
+        if version < MIN_PROTOCOL_VERSION {
+            return Err(HandshakeError::UnsupportedVersion(version))
+        }
+
+        Ok(Self {
+            bridge_id: offer.bridge_id,
+            version,
+            capabilities: self.capabilities & offer.capabilities,
+        })
+    }
+
+    /// Validates the negotiated answer of the other side against this offer.
+    ///
+    /// The other side may never choose a newer version or features which were not offered.
+    pub fn accept(&self, answer: &Self) -> Result<Self, HandshakeError> {
+        if answer.bridge_id != self.bridge_id {
+            return Err(HandshakeError::WrongBridge(answer.bridge_id))
+        }
+        if answer.version < MIN_PROTOCOL_VERSION || answer.version > self.version {
+            return Err(HandshakeError::UnsupportedVersion(answer.version))
+        }
+
+        Ok(Self {
+            capabilities: self.capabilities & answer.capabilities,
+            ..*answer
+        })
+    }
+
+    /// Checks if all provided features are available within this session.
+    pub fn supports(&self, capabilities: Capabilities) -> bool {
+        self.capabilities.contains(capabilities)
+    }
+}
@@ -0,0 +1,461 @@
+//! Simulated daemon, which serves a local directory tree over the Sugar protocol.
+//!
+//! This is a Rust stand-in for the C daemon running on the target. It speaks the same
+//! [`DaemonCommandByte`] protocol over any [`Transport`], which allows to check the bridge without
+//! booting a real target. The served directory replaces `/mnt/disks` and must have the following
+//! layout:
+//!
+//! ```text
+//! <root>/<disk>/<partition>/<files and directories>
+//! ```
+//!
+//! # Protocol
+//!
+//! Every request is answered with the same command byte and an ACK or NACK prefix:
+//!
+//! - `REQ CONN BID <offer>` is answered with `ACK CONN BID <session>`, holding the negotiated
+//! protocol version and capabilities. Another bridge ID or an unsupported version is refused.
+//! - `REQ SEL <name>` selects a disk, or a partition of the selected disk.
+//! - `REQ UNSEL` removes any selection.
+//! - `REQ NAME`, `REQ PART` and `REQ FILE` list disks, partitions of the selected disk and
+//! entries of the selected partition. Each entry comes in a separate command, directories within
+//! a partition are marked with `DIR`. The listing ends with an empty command.
+//! - `REQ READ FILE <path>` sends the file from the selected partition in chunks, ending with an
+//! empty `ACK READ`.
+//! - `REQ RET` is confirmed with `ACK RET`, followed by the last answer once more. Corrupted
+//! commands are answered with `REQ RET`.
+//! - `SHUT` closes the daemon.
+//!
+//! Unknown commands are answered with `NACK` and the refused byte.
+
+use std::{fs, path::{Component, Path, PathBuf}, thread::{self, JoinHandle}};
+use rusb::Error as RusbError;
+
+use super::{
+    cmd::{DaemonCommand, DaemonCommandByte, DecodedCommand},
+    codec::FrameDecoder,
+    session::{Capabilities, SessionInfo},
+    transport::Transport,
+};
+use crate::dcommand;
+
+/// Maximal amount of data bytes in one command.
+///
+/// The size, prefix, command and checksum bytes are taking the rest of the command.
+pub const MAX_DATA_SIZE: usize = u8::MAX as usize - 5;
+
+/// Amount of bytes read from the transport at once.
+const READ_SIZE: usize = 512;
+
+/// Simulated daemon state.
+pub struct SimDaemon {
+    root: PathBuf,
+    offer: SessionInfo,
+    bridge_id: Option<u64>,
+    disk: Option<String>,
+    partition: Option<String>,
+    last: Vec<DaemonCommand>,
+    running: bool,
+}
+
+impl SimDaemon {
+    /// Creates a new simulated daemon, which serves the provided directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            offer: SessionInfo::offer(0),
+            bridge_id: None,
+            disk: None,
+            partition: None,
+            last: Vec::new(),
+            running: true,
+        }
+    }
+
+    /// Sets the protocol version the daemon will negotiate with.
+    pub fn with_version(mut self, version: u8) -> Self {
+        self.offer.version = version;
+        self
+    }
+
+    /// Sets the optional protocol features the daemon will negotiate with.
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.offer.capabilities = capabilities;
+        self
+    }
+
+    /// Moves the daemon into a separate thread, which serves the transport until it is closed.
+    pub fn spawn<T: Transport>(mut self, transport: T) -> JoinHandle<Result<(), RusbError>> {
+        thread::spawn(move || self.serve(&transport))
+    }
+
+    /// Serves the transport until a shutdown command is obtained or the other side is gone.
+    ///
+    /// Timeouts are ignored, while all other transport errors are returned.
+    pub fn serve<T: Transport + ?Sized>(&mut self, transport: &T) -> Result<(), RusbError> {
+        let mut buf = [0u8; READ_SIZE];
+        let mut decoder = FrameDecoder::default();
+
+        log::info!("Simulated daemon: serving {}", self.root.to_string_lossy());
+        while self.running {
+            match transport.read(&mut buf) {
+                Ok(len) => decoder.push(&buf[..len]),
+                Err(RusbError::Timeout) => {
+                    if let Err(err) = decoder.flush() {
+                        log::warn!("Simulated daemon: {}", err);
+                        transport.write(DaemonCommand::retry().byte_code())?;
+                    }
+                    continue
+                },
+                Err(RusbError::NoDevice) => break,
+                Err(err) => return Err(err),
+            }
+
+            // Several commands can be stacked within one transfer.
+            while let Some(frame) = decoder.next_frame() {
+                let out = match frame {
+                    Ok(cmd) => self.handle(cmd.byte_code()),
+                    Err(err) => {
+                        log::warn!("Simulated daemon: {}", err);
+                        vec![DaemonCommand::retry()]
+                    },
+                };
+
+                for cmd in out {
+                    transport.write(cmd.byte_code())?;
+                }
+            }
+        }
+
+        log::info!("Simulated daemon: closed");
+        Ok(())
+    }
+
+    /// Handles one command and returns all commands which must be sent back.
+    pub fn handle(&mut self, frame: &[u8]) -> Vec<DaemonCommand> {
+        use DaemonCommandByte::*;
+
+        // Corrupted commands are never handled, but requested once more.
+        let frame = DaemonCommand::new(frame);
+        if !frame.is_valid() {
+            log::warn!("Simulated daemon: corrupted command: {}", frame.byte_code().escape_ascii());
+            return vec![DaemonCommand::retry()];
+        }
+
+        let decoded = match frame.decode() {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                log::warn!("Simulated daemon: {}", err);
+                // Refusals are never refused back.
+                if frame.body().first() == Some(&NACK.into()) {
+                    return Vec::new();
+                }
+                return vec![DaemonCommand::nack(err)];
+            },
+        };
+
+        let (cmd, data) = match decoded {
+            DecodedCommand { prefix: None, command: SHUT, .. } => {
+                self.running = false;
+                return vec![dcommand!(ACK, SHUT)];
+            },
+            DecodedCommand { prefix: Some(REQ), command: RET, .. } => {
+                // The confirmation lets the bridge tell the retransmission apart from the rest of
+                // the corrupted answer.
+                let mut out = vec![dcommand!(ACK, RET)];
+                out.extend_from_slice(&self.last);
+                return out;
+            },
+            DecodedCommand { prefix: Some(REQ), command, data } => (command, data),
+            _ => {
+                log::warn!("Simulated daemon: unexpected command: {}", frame.body().escape_ascii());
+                return Vec::new();
+            },
+        };
+
+        let out = match cmd {
+            CONN => self.conn(data),
+            SEL => self.select(data),
+            UNSEL => {
+                self.disk = None;
+                self.partition = None;
+                vec![dcommand!(ACK, UNSEL)]
+            },
+            NAME => self.list(NAME, self.root.clone(), false),
+            PART => match self.disk_path() {
+                Some(path) => self.list(PART, path, false),
+                None => vec![dcommand!(NACK, PART)],
+            },
+            FILE => match self.partition_path() {
+                Some(path) => self.list(FILE, path, true),
+                None => vec![dcommand!(NACK, FILE)],
+            },
+            READ => self.read(data),
+            cmd => vec![dcommand!(NACK, cmd)],
+        };
+
+        self.last.clone_from(&out);
+        out
+    }
+
+    fn conn(&mut self, data: &[u8]) -> Vec<DaemonCommand> {
+        use DaemonCommandByte::*;
+
+        let offer = match data.split_first() {
+            Some((&b, offer)) if b == BID as u8 => SessionInfo::decode(offer),
+            _ => return vec![dcommand!(NACK, CONN)],
+        };
+
+        let session = match offer.and_then(|offer| self.offer.negotiate(&offer)) {
+            Ok(session) => session,
+            Err(err) => {
+                log::warn!("Simulated daemon: {}", err);
+                return vec![dcommand!(NACK, CONN)];
+            },
+        };
+
+        match self.bridge_id {
+            Some(known) if known != session.bridge_id => vec![dcommand!(NACK, CONN, BID)],
+            _ => {
+                self.bridge_id = Some(session.bridge_id);
+                let mut ack = dcommand!(ACK, CONN, BID);
+                ack.push_data(&session.encode()).expect("The session always fits into one command.");
+                vec![ack]
+            },
+        }
+    }
+
+    fn select(&mut self, data: &[u8]) -> Vec<DaemonCommand> {
+        use DaemonCommandByte::*;
+
+        let name = String::from_utf8_lossy(data).into_owned();
+        let answer = |prefix: DaemonCommandByte| {
+            let mut cmd = dcommand!(prefix, SEL);
+            match cmd.push_data(data) {
+                Ok(()) => vec![cmd],
+                Err(_) => vec![dcommand!(NACK, SEL)],
+            }
+        };
+
+        // Trying to select a disk first, just like the C daemon does.
+        if Self::is_entry(&self.root, &name) {
+            self.disk = Some(name);
+            self.partition = None;
+            return answer(ACK);
+        }
+
+        if let Some(disk) = self.disk_path() {
+            if Self::is_entry(&disk, &name) {
+                self.partition = Some(name);
+                return answer(ACK);
+            }
+        }
+
+        answer(NACK)
+    }
+
+    fn list(&self, kind: DaemonCommandByte, path: PathBuf, mark_dirs: bool) -> Vec<DaemonCommand> {
+        use DaemonCommandByte::*;
+
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::error!("Simulated daemon: unable to list {}: {}", path.to_string_lossy(), err);
+                return vec![dcommand!(NACK, kind)];
+            },
+        };
+
+        let mut names: Vec<(String, bool)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let is_dir = entry.file_type().ok()?.is_dir();
+                // Disks and partitions are always directories.
+                if !mark_dirs && !is_dir {
+                    return None;
+                }
+                Some((entry.file_name().to_string_lossy().into_owned(), is_dir))
+            })
+            .filter(|(name, _)| name.len() <= MAX_DATA_SIZE)
+            .collect();
+        names.sort();
+
+        let mut out: Vec<DaemonCommand> = names.into_iter().map(|(name, is_dir)| {
+            let mut cmd = if mark_dirs && is_dir { dcommand!(ACK, DIR) } else { dcommand!(ACK, kind) };
+            cmd.push_data(name.as_bytes()).expect("Longer names are filtered out.");
+            cmd
+        }).collect();
+
+        out.push(dcommand!(ACK, kind));
+        out
+    }
+
+    fn read(&self, data: &[u8]) -> Vec<DaemonCommand> {
+        use DaemonCommandByte::*;
+
+        let path = match (data.split_first(), self.partition_path()) {
+            (Some((&b, path)), Some(partition)) if b == FILE as u8 => {
+                let path = Path::new(std::str::from_utf8(path).unwrap_or_default());
+                // Paths must never escape the selected partition.
+                if path.as_os_str().is_empty() || path.components().any(|c| !matches!(c, Component::Normal(_))) {
+                    return vec![dcommand!(NACK, READ)];
+                }
+                partition.join(path)
+            },
+            _ => return vec![dcommand!(NACK, READ)],
+        };
+
+        match fs::read(&path) {
+            Ok(content) => {
+                let mut out: Vec<DaemonCommand> = content.chunks(MAX_DATA_SIZE).map(|chunk| {
+                    let mut cmd = dcommand!(ACK, READ);
+                    cmd.push_data(chunk).expect("Chunks never exceed MAX_DATA_SIZE.");
+                    cmd
+                }).collect();
+
+                out.push(dcommand!(ACK, READ));
+                out
+            },
+            Err(err) => {
+                log::error!("Simulated daemon: unable to read {}: {}", path.to_string_lossy(), err);
+                vec![dcommand!(NACK, READ)]
+            },
+        }
+    }
+
+    fn disk_path(&self) -> Option<PathBuf> {
+        self.disk.as_ref().map(|disk| self.root.join(disk))
+    }
+
+    fn partition_path(&self) -> Option<PathBuf> {
+        self.partition.as_ref().and_then(|part| self.disk_path().map(|disk| disk.join(part)))
+    }
+
+    /// Checks if the name is a plain directory entry of the provided directory.
+    fn is_entry(dir: &Path, name: &str) -> bool {
+        let mut components = Path::new(name).components();
+        matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+            && dir.join(name).is_dir()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{fs, path::PathBuf, thread::JoinHandle};
+    use rusb::Error as RusbError;
+    use tokio::task::JoinHandle as TaskHandle;
+
+    use super::*;
+    use crate::sugar::{
+        conn::{client::{ClientError, DaemonClient, Disk, EntryKind, Partition}, lifecycle::LifecycleState, transport::MemoryTransport, Bridge},
+        errors::SugarResult,
+        runtime,
+    };
+
+    /// Content of the file, which does not fit into one command.
+    pub(crate) fn content() -> Vec<u8> {
+        (0..1000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Creates a fresh directory tree to be served by the daemon.
+    pub(crate) fn tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("sugar-sim-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&root).ok();
+
+        fs::create_dir_all(root.join("sda/sda1/docs")).unwrap();
+        fs::create_dir_all(root.join("sda/sda2")).unwrap();
+        fs::create_dir_all(root.join("sdb")).unwrap();
+        fs::write(root.join("sda/sda1/data.bin"), content()).unwrap();
+        root
+    }
+
+    /// Bridge connected to the simulated daemon over the in-memory transport.
+    pub(crate) struct Running {
+        pub client: DaemonClient,
+        pub bridge: TaskHandle<SugarResult<Bridge<MemoryTransport>>>,
+        pub daemon: JoinHandle<Result<(), RusbError>>,
+    }
+
+    /// Connects a new bridge to the daemon, waiting until the session is negotiated.
+    pub(crate) fn connect(daemon: SimDaemon, id: u64) -> Running {
+        let (host, target) = MemoryTransport::pair();
+        let daemon = daemon.spawn(target);
+
+        let mut bridge = Bridge::with_transport(id, host);
+        let mut state = bridge.subscribe();
+        let client = bridge.client();
+        let bridge = runtime::spawn(async move { bridge.connect().await.map(|_| bridge) });
+
+        runtime::block_on(state.wait_for(|state| *state == LifecycleState::Connected)).unwrap();
+        Running { client, bridge, daemon }
+    }
+
+    impl Running {
+        /// Closes the bridge and waits for both sides.
+        pub(crate) fn close(self) -> Bridge<MemoryTransport> {
+            runtime::block_on(self.client.disconnect()).unwrap();
+            let bridge = runtime::block_on(self.bridge).unwrap().unwrap();
+            self.daemon.join().unwrap().unwrap();
+            bridge
+        }
+    }
+
+    #[test]
+    fn handshake_listing_and_read() {
+        let root = tree("roundtrip");
+        let running = connect(SimDaemon::new(&root), 0x5eed);
+        let client = running.client.clone();
+
+        runtime::block_on(async {
+            let disks = client.list_disks().await.unwrap();
+            assert_eq!(disks, vec![Disk { name: "sda".into() }, Disk { name: "sdb".into() }]);
+
+            let partitions = client.list_partitions(&disks[0]).await.unwrap();
+            let names: Vec<_> = partitions.iter().map(|part| part.name.as_str()).collect();
+            assert_eq!(names, ["sda1", "sda2"]);
+
+            let files = client.list_files(&partitions[0]).await.unwrap();
+            let entries: Vec<_> = files.iter().map(|file| (file.path.as_str(), file.kind)).collect();
+            assert_eq!(entries, [("data.bin", EntryKind::File), ("docs", EntryKind::Directory)]);
+
+            assert_eq!(client.read_file(&files[0]).await.unwrap(), content());
+        });
+
+        let bridge = running.close();
+        let session = bridge.session().expect("session is negotiated");
+        assert_eq!(session.bridge_id, 0x5eed);
+        assert_eq!(bridge.state(), LifecycleState::Closed);
+        fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn refuses_unknown_entries() {
+        let root = tree("refusals");
+        let running = connect(SimDaemon::new(&root), 1);
+        let client = running.client.clone();
+
+        runtime::block_on(async {
+            let missing = Partition { disk: "sda".into(), name: "sda9".into() };
+            assert!(client.list_files(&missing).await.is_err());
+            assert!(client.select("../sda").await.is_err());
+            // A refusal does not break the following requests.
+            assert_eq!(client.list_disks().await.unwrap().len(), 2);
+        });
+
+        running.close();
+        fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn long_names_fit_into_one_write() {
+        let root = tree("long");
+        let running = connect(SimDaemon::new(&root), 2);
+        let client = running.client.clone();
+
+        // Longer than a USB v2.0 packet, but still within one command.
+        let name = "a".repeat(200);
+        assert_eq!(runtime::block_on(client.select(&name)), Err(ClientError::Refused));
+
+        running.close();
+        fs::remove_dir_all(root).ok();
+    }
+}
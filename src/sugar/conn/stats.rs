@@ -0,0 +1,142 @@
+//! Counters of the bridge traffic.
+//!
+//! The bridge runs in its own task, so the counters are shared atomics, which can be read at any
+//! time without disturbing the communication.
+
+use std::{fmt::{self, Display}, sync::{atomic::{AtomicU64, Ordering}, Mutex, TryLockError}};
+
+use super::session::SessionInfo;
+
+/// Statistics of a single bridge, shared between the bridge and its observers.
+#[derive(Debug, Default)]
+pub struct BridgeStats {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    commands_in: AtomicU64,
+    commands_out: AtomicU64,
+    corrupted: AtomicU64,
+    retransmissions: AtomicU64,
+    timeouts: AtomicU64,
+    read_errors: AtomicU64,
+    session: Mutex<Option<SessionInfo>>,
+}
+
+/// Copy of the statistics at some moment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub commands_in: u64,
+    pub commands_out: u64,
+    /// Commands rejected by the codec or with a wrong checksum.
+    pub corrupted: u64,
+    /// Commands sent once more on the target's request.
+    pub retransmissions: u64,
+    /// Reads, which have obtained no data in time.
+    pub timeouts: u64,
+    /// Reads, which have failed for any other reason.
+    pub read_errors: u64,
+    /// Negotiated session, if the handshake is done.
+    pub session: Option<SessionInfo>,
+}
+
+impl BridgeStats {
+    pub(crate) fn received(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.commands_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn command(&self) {
+        self.commands_in.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn corrupted(&self) {
+        self.corrupted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn retransmitted(&self) {
+        self.retransmissions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn read_error(&self) {
+        self.read_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn negotiated(&self, session: SessionInfo) {
+        self.session.lock().unwrap_or_else(|err| err.into_inner()).replace(session);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        self.snapshot_with(*self.session.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Same as [`BridgeStats::snapshot`], but returns nothing instead of waiting for the session.
+    pub(crate) fn try_snapshot(&self) -> Option<StatsSnapshot> {
+        let session = match self.session.try_lock() {
+            Ok(session) => *session,
+            Err(TryLockError::Poisoned(err)) => *err.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+
+        Some(self.snapshot_with(session))
+    }
+
+    fn snapshot_with(&self, session: Option<SessionInfo>) -> StatsSnapshot {
+        StatsSnapshot {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            commands_in: self.commands_in.load(Ordering::Relaxed),
+            commands_out: self.commands_out.load(Ordering::Relaxed),
+            corrupted: self.corrupted.load(Ordering::Relaxed),
+            retransmissions: self.retransmissions.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            read_errors: self.read_errors.load(Ordering::Relaxed),
+            session,
+        }
+    }
+}
+
+impl Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Bytes in: {}", self.bytes_in)?;
+        writeln!(f, "Bytes out: {}", self.bytes_out)?;
+        writeln!(f, "Commands in: {}", self.commands_in)?;
+        writeln!(f, "Commands out: {}", self.commands_out)?;
+        writeln!(f, "Corrupted commands: {}", self.corrupted)?;
+        writeln!(f, "Retransmissions: {}", self.retransmissions)?;
+        writeln!(f, "Read timeouts: {}", self.timeouts)?;
+        writeln!(f, "Read errors: {}", self.read_errors)?;
+        match self.session {
+            Some(session) => writeln!(f, "Session: bridge {:#018x}, protocol version {}, capabilities {:#010x}",
+                session.bridge_id, session.version, session.capabilities.0),
+            None => writeln!(f, "Session: not negotiated"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_snapshot_never_waits() {
+        let stats = BridgeStats::default();
+        stats.received(10);
+        stats.sent(4);
+
+        let session = stats.session.lock().unwrap();
+        assert_eq!(stats.try_snapshot(), None);
+        drop(session);
+
+        assert_eq!(stats.try_snapshot(), Some(stats.snapshot()));
+        assert_eq!(stats.snapshot().bytes_in, 10);
+    }
+}
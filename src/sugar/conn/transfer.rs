@@ -0,0 +1,192 @@
+//! Pipelined bulk transfers of libusb.
+//!
+//! Synchronous reads leave the bus idle between the end of one transfer and the submission of the
+//! next one. Instead, several asynchronous transfers are kept submitted at once, so the host
+//! controller always has a buffer to fill. Transfers on one endpoint are completed in the order of
+//! their submission, therefore the data is delivered in the same order it was sent.
+//!
+//! All events are handled on the calling thread, which must be a dedicated one.
+
+use std::{
+    collections::VecDeque, ffi::{c_int, c_uint, c_void}, ptr, sync::atomic::{AtomicI32, Ordering}, time::Duration
+};
+use rusb::{constants::*, ffi, Context, DeviceHandle, Error as RusbError, UsbContext};
+
+/// Single transfer along with the memory it reads into.
+struct Slot {
+    transfer: *mut ffi::libusb_transfer,
+    buf: Vec<u8>,
+    /// Set by the callback, once libusb is done with the transfer.
+    ///
+    /// Boxed, so that its address stays the same for libusb.
+    completed: Box<AtomicI32>,
+}
+
+/// Set of transfers, which are read in the order of their submission.
+///
+/// Dropping the pipeline cancels all transfers in flight and waits for their completion, so that
+/// libusb never touches freed memory.
+pub(crate) struct Pipeline<'h> {
+    handle: &'h DeviceHandle<Context>,
+    endpoint: u8,
+    timeout: Duration,
+    slots: Vec<Slot>,
+    /// Submitted slots, the oldest first.
+    queue: VecDeque<usize>,
+}
+
+impl<'h> Pipeline<'h> {
+    /// Allocates transfers of the provided size. Nothing is submitted yet.
+    pub(crate) fn new(handle: &'h DeviceHandle<Context>, endpoint: u8, depth: usize, size: usize, timeout: Duration) -> Result<Self, RusbError> {
+        // Same check as libusb's synchronous reads do.
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
+            return Err(RusbError::InvalidParam)
+        }
+
+        let mut pipeline = Self { handle, endpoint, timeout, slots: Vec::with_capacity(depth), queue: VecDeque::with_capacity(depth) };
+
+        for _ in 0..depth.max(1) {
+            let transfer = unsafe { ffi::libusb_alloc_transfer(0) };
+            if transfer.is_null() {
+                return Err(RusbError::NoMem)
+            }
+            pipeline.slots.push(Slot { transfer, buf: vec![0; size], completed: Box::new(AtomicI32::new(1)) });
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Submits all transfers, which are not in flight yet.
+    pub(crate) fn fill(&mut self) -> Result<(), RusbError> {
+        for i in 0..self.slots.len() {
+            if !self.queue.contains(&i) {
+                self.submit(i)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the oldest transfer and passes its data to the function.
+    ///
+    /// The transfer is submitted once more afterwards, unless the device is gone.
+    pub(crate) fn next<R>(&mut self, f: impl FnOnce(Result<&[u8], RusbError>) -> R) -> Result<R, RusbError> {
+        // Transfers are only left unsubmitted once the device is gone.
+        let i = *self.queue.front().ok_or(RusbError::NoDevice)?;
+        self.wait(i)?;
+        self.queue.pop_front();
+
+        let slot = &self.slots[i];
+        let (status, len) = unsafe { ((*slot.transfer).status, (*slot.transfer).actual_length as usize) };
+
+        // A timed out transfer may still carry a part of the data.
+        let result = match status {
+            LIBUSB_TRANSFER_COMPLETED => Ok(&slot.buf[..len]),
+            LIBUSB_TRANSFER_TIMED_OUT if len > 0 => Ok(&slot.buf[..len]),
+            LIBUSB_TRANSFER_TIMED_OUT => Err(RusbError::Timeout),
+            LIBUSB_TRANSFER_NO_DEVICE => Err(RusbError::NoDevice),
+            LIBUSB_TRANSFER_STALL => Err(RusbError::Pipe),
+            LIBUSB_TRANSFER_OVERFLOW => Err(RusbError::Overflow),
+            LIBUSB_TRANSFER_CANCELLED => Err(RusbError::Interrupted),
+            _ => Err(RusbError::Io),
+        };
+        let gone = status == LIBUSB_TRANSFER_NO_DEVICE;
+        let out = f(result);
+
+        if !gone {
+            self.submit(i)?;
+        }
+        Ok(out)
+    }
+
+    fn submit(&mut self, i: usize) -> Result<(), RusbError> {
+        let slot = &mut self.slots[i];
+        slot.completed.store(0, Ordering::Release);
+
+        let rc = unsafe {
+            ffi::libusb_fill_bulk_transfer(
+                slot.transfer,
+                self.handle.as_raw(),
+                self.endpoint,
+                slot.buf.as_mut_ptr(),
+                slot.buf.len() as c_int,
+                Self::callback,
+                slot.completed.as_ptr() as *mut c_void,
+                self.timeout.as_millis() as c_uint,
+            );
+            ffi::libusb_submit_transfer(slot.transfer)
+        };
+
+        if rc != 0 {
+            slot.completed.store(1, Ordering::Release);
+            return Err(error(rc))
+        }
+
+        self.queue.push_back(i);
+        Ok(())
+    }
+
+    /// Handles libusb events until the transfer is completed.
+    fn wait(&self, i: usize) -> Result<(), RusbError> {
+        let completed = &self.slots[i].completed;
+
+        while completed.load(Ordering::Acquire) == 0 {
+            // Events may also be handled by other threads, e.g. the writer, in which case the call
+            // returns once they are done.
+            let rc = unsafe { ffi::libusb_handle_events_completed(self.handle.context().as_raw(), completed.as_ptr()) };
+            if rc < 0 && rc != LIBUSB_ERROR_INTERRUPTED {
+                return Err(error(rc))
+            }
+        }
+
+        Ok(())
+    }
+
+    extern "system" fn callback(transfer: *mut ffi::libusb_transfer) {
+        unsafe {
+            let completed = (*transfer).user_data as *const AtomicI32;
+            (*completed).store(1, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for Pipeline<'_> {
+    fn drop(&mut self) {
+        for &i in &self.queue {
+            unsafe { ffi::libusb_cancel_transfer(self.slots[i].transfer) };
+        }
+        // Cancellation is asynchronous as well, so the memory stays alive until libusb is done.
+        for i in std::mem::take(&mut self.queue) {
+            if self.wait(i).is_err() {
+                log::error!("Unable to cancel a USB transfer, leaking its memory.");
+                std::mem::forget(std::mem::take(&mut self.slots[i].buf));
+                std::mem::forget(std::mem::replace(&mut self.slots[i].completed, Box::new(AtomicI32::new(1))));
+                self.slots[i].transfer = ptr::null_mut();
+            }
+        }
+        for slot in &self.slots {
+            if !slot.transfer.is_null() {
+                unsafe { ffi::libusb_free_transfer(slot.transfer) };
+            }
+        }
+    }
+}
+
+/// Converts the libusb error code.
+fn error(rc: c_int) -> RusbError {
+    match rc {
+        LIBUSB_ERROR_IO => RusbError::Io,
+        LIBUSB_ERROR_INVALID_PARAM => RusbError::InvalidParam,
+        LIBUSB_ERROR_ACCESS => RusbError::Access,
+        LIBUSB_ERROR_NO_DEVICE => RusbError::NoDevice,
+        LIBUSB_ERROR_NOT_FOUND => RusbError::NotFound,
+        LIBUSB_ERROR_BUSY => RusbError::Busy,
+        LIBUSB_ERROR_TIMEOUT => RusbError::Timeout,
+        LIBUSB_ERROR_OVERFLOW => RusbError::Overflow,
+        LIBUSB_ERROR_PIPE => RusbError::Pipe,
+        LIBUSB_ERROR_INTERRUPTED => RusbError::Interrupted,
+        LIBUSB_ERROR_NO_MEM => RusbError::NoMem,
+        LIBUSB_ERROR_NOT_SUPPORTED => RusbError::NotSupported,
+        _ => RusbError::Other,
+    }
+}
@@ -0,0 +1,315 @@
+//! Transport layer of the communication bridge.
+//!
+//! The bridge itself does not care how the bytes travel between the mobile device and the target,
+//! as long as something can read and write them. This module defines such abstraction and ships
+//! two implementations of it: a real USB transport backed by libusb and an in-memory duplex
+//! transport, which allows to run the whole bridge without any target attached.
+
+use std::sync::{Mutex, mpsc::{self, Receiver, RecvTimeoutError, Sender}};
+use rusb::{Context, Device, DeviceHandle, Direction, Error as RusbError, TransferType, UsbContext};
+
+use super::transfer::Pipeline;
+use crate::sugar::errors::{BridgeError, SugarError, SugarResult};
+
+/// Class of vendor specific interfaces, which the daemon exposes.
+const VENDOR_SPECIFIC_CLASS: u8 = 0xff;
+
+/// Communication timeout for one atomic read/write.
+pub const TIMEOUT: std::time::Duration = std::time::Duration::from_millis(2000);
+
+/// Consumer of streamed transfers, which returns `false` once no more of them are needed.
+pub type Sink<'s> = dyn FnMut(Result<&[u8], RusbError>) -> bool + 's;
+
+/// Custom trait for transports.
+///
+/// A transport moves raw bytes between two devices. Each read returns the data of one transfer,
+/// so the caller must not expect that one read will always contain exactly one command.
+pub trait Transport: Send + Sync + 'static {
+    /// Reads one transfer from the bus into the provided slice and returns the amount of bytes read.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, RusbError>;
+    /// Writes the whole slice to the bus and returns the amount of bytes written.
+    fn write(&self, buf: &[u8]) -> Result<usize, RusbError>;
+    /// Reads transfers one after another and passes each of them to the sink, until the sink
+    /// returns `false`.
+    ///
+    /// Up to `depth` transfers of `size` bytes are kept in flight, if the transport supports it.
+    /// By default they are read one at a time via [`Transport::read`].
+    fn stream(&self, depth: usize, size: usize, sink: &mut Sink<'_>) -> Result<(), RusbError> {
+        let _ = depth;
+        let mut buf = vec![0; size];
+
+        while sink(self.read(&mut buf).map(|len| &buf[..len])) {}
+        Ok(())
+    }
+    /// Clears the halt condition of the endpoint in the direction. Does nothing by default.
+    fn clear_halt(&self, direction: Direction) -> Result<(), RusbError> {
+        let _ = direction;
+        Ok(())
+    }
+    /// Resets the other side of the transport. Does nothing by default.
+    fn reset(&self) -> Result<(), RusbError> {
+        Ok(())
+    }
+    /// Returns a human readable information about the other side of the transport.
+    fn info(&self) -> String;
+    /// Returns an identifier of the other side, which stays the same between connections.
+    fn machine(&self) -> String;
+}
+
+/// Bulk endpoints of the interface, which the daemon communicates through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Endpoints {
+    pub interface: u8,
+    /// Alternate setting of the interface.
+    pub setting: u8,
+    /// Address of the bulk IN endpoint.
+    pub read: u8,
+    pub read_packet_size: u16,
+    /// Address of the bulk OUT endpoint.
+    pub write: u8,
+    pub write_packet_size: u16,
+}
+
+impl Endpoints {
+    /// Finds the interface with a pair of bulk endpoints within the active configuration.
+    ///
+    /// Vendor specific interfaces are preferred, since that is what the daemon exposes, while
+    /// any other interface is only used if there is no such one.
+    pub fn discover<C: UsbContext>(device: &Device<C>) -> Result<Option<Self>, RusbError> {
+        let config = device.active_config_descriptor()?;
+        let mut found = None;
+
+        for interface in config.interfaces() {
+            for desc in interface.descriptors() {
+                let bulk = |direction| desc.endpoint_descriptors()
+                    .find(|ep| ep.transfer_type() == TransferType::Bulk && ep.direction() == direction);
+
+                let (read, write) = match (bulk(Direction::In), bulk(Direction::Out)) {
+                    (Some(read), Some(write)) => (read, write),
+                    _ => continue,
+                };
+                let endpoints = Self {
+                    interface: desc.interface_number(),
+                    setting: desc.setting_number(),
+                    read: read.address(),
+                    read_packet_size: read.max_packet_size(),
+                    write: write.address(),
+                    write_packet_size: write.max_packet_size(),
+                };
+
+                if desc.class_code() == VENDOR_SPECIFIC_CLASS {
+                    return Ok(Some(endpoints))
+                }
+                found.get_or_insert(endpoints);
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+/// Transport over a USB bus.
+///
+/// All I/O is done via libusb bulk transfers on the claimed interface. Streamed reads keep several
+/// transfers in flight. The interface is released once the transport is dropped.
+pub struct UsbTransport {
+    handle: DeviceHandle<Context>,
+    endpoints: Endpoints,
+    /// Kernel driver was detached from the interface, so it is attached back on release.
+    reattach: bool,
+}
+
+impl UsbTransport {
+    /// Claims the interface of an already opened device.
+    ///
+    /// A kernel driver bound to the interface is detached first. Fails with
+    /// [`BridgeError::NoSuitableInterface`] if the device has no interface with bulk endpoints.
+    pub fn open(handle: DeviceHandle<Context>) -> SugarResult<Self> {
+        let endpoints = Endpoints::discover(&handle.device())
+            .map_err(|err| {
+                log::error!("Bridge error: Unable to read the configuration descriptor: {}", err);
+                SugarError::new(BridgeError::NoSuitableInterface).with_source(err)
+            })?
+            .ok_or_else(|| {
+                log::error!("Bridge error: Device has no interface with bulk endpoints.");
+                SugarError::new(BridgeError::NoSuitableInterface)
+            })?;
+
+        log::info!("Using interface {} (setting {}), IN: {:#04x} ({} bytes), OUT: {:#04x} ({} bytes).",
+            endpoints.interface, endpoints.setting, endpoints.read, endpoints.read_packet_size,
+            endpoints.write, endpoints.write_packet_size);
+
+        let claim_error = |err: RusbError| {
+            log::error!("Bridge error: Unable to claim the interface {}: {}", endpoints.interface, err);
+            SugarError::new(BridgeError::InterfaceClaimError).with_source(err)
+        };
+
+        // Platforms without kernel drivers do not support the check at all.
+        let reattach = match handle.kernel_driver_active(endpoints.interface) {
+            Ok(true) => {
+                handle.detach_kernel_driver(endpoints.interface).map_err(claim_error)?;
+                true
+            },
+            Ok(false) | Err(RusbError::NotSupported) => false,
+            Err(err) => return Err(claim_error(err)),
+        };
+        // From now on the interface is released by the transport itself.
+        let transport = Self { handle, endpoints, reattach };
+
+        transport.handle.claim_interface(endpoints.interface).map_err(claim_error)?;
+        if endpoints.setting != 0 {
+            transport.handle.set_alternate_setting(endpoints.interface, endpoints.setting).map_err(claim_error)?;
+        }
+
+        Ok(transport)
+    }
+
+    /// Returns a reference to the underlying libusb device handle.
+    pub fn handle(&self) -> &DeviceHandle<Context> {
+        &self.handle
+    }
+
+    /// Returns the endpoints of the claimed interface.
+    pub fn endpoints(&self) -> Endpoints {
+        self.endpoints
+    }
+}
+
+impl Drop for UsbTransport {
+    fn drop(&mut self) {
+        let interface = self.endpoints.interface;
+
+        // Releasing an interface, which was never claimed, fails harmlessly.
+        if let Err(err) = self.handle.release_interface(interface) {
+            log::debug!("Unable to release the interface {}: {}", interface, err);
+        }
+        if self.reattach {
+            if let Err(err) = self.handle.attach_kernel_driver(interface) {
+                log::warn!("Unable to attach the kernel driver back to the interface {}: {}", interface, err);
+            }
+        }
+    }
+}
+
+impl Transport for UsbTransport {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, RusbError> {
+        self.handle.read_bulk(self.endpoints.read, buf, TIMEOUT)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, RusbError> {
+        self.handle.write_bulk(self.endpoints.write, buf, TIMEOUT)
+    }
+
+    fn stream(&self, depth: usize, size: usize, sink: &mut Sink<'_>) -> Result<(), RusbError> {
+        let mut pipeline = Pipeline::new(&self.handle, self.endpoints.read, depth, size, TIMEOUT)?;
+        pipeline.fill()?;
+
+        while pipeline.next(&mut *sink)? {}
+        Ok(())
+    }
+
+    fn clear_halt(&self, direction: Direction) -> Result<(), RusbError> {
+        match direction {
+            Direction::In => self.handle.clear_halt(self.endpoints.read),
+            Direction::Out => self.handle.clear_halt(self.endpoints.write),
+        }
+    }
+
+    fn reset(&self) -> Result<(), RusbError> {
+        self.handle.reset()
+    }
+
+    fn info(&self) -> String {
+        let devd = self.handle.device();
+        match devd.device_descriptor() {
+            Ok(devdc) => format!("Bus: {:03}, Addr: {:03}, ID: {:04x}:{:04x}\nMax supported USB version: {}.",
+                devd.bus_number(), devd.address(), devdc.vendor_id(), devdc.product_id(), devdc.usb_version()),
+            Err(_) => format!("Bus: {:03}, Addr: {:03}", devd.bus_number(), devd.address()),
+        }
+    }
+
+    fn machine(&self) -> String {
+        let devd = self.handle.device();
+        match devd.device_descriptor() {
+            // Not every device has a serial number, then all devices of the same model are one machine.
+            Ok(devdc) => match self.handle.read_serial_number_string_ascii(&devdc) {
+                Ok(serial) => format!("usb:{:04x}:{:04x}:{}", devdc.vendor_id(), devdc.product_id(), serial),
+                Err(_) => format!("usb:{:04x}:{:04x}", devdc.vendor_id(), devdc.product_id()),
+            },
+            Err(_) => format!("usb:{:03}:{:03}", devd.bus_number(), devd.address()),
+        }
+    }
+}
+
+/// In-memory duplex transport.
+///
+/// Always created in pairs, where everything written to one end can be read from the other one.
+/// Each write is delivered as a separate transfer, just like a bulk transfer on a real bus. Once
+/// one end is dropped, the other one will obtain [`RusbError::NoDevice`] on any I/O.
+pub struct MemoryTransport {
+    tx: Mutex<Sender<Vec<u8>>>,
+    rx: Mutex<MemoryRx>,
+}
+
+/// Receiving half of the memory transport with the data left from the last transfer.
+struct MemoryRx {
+    rx: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl MemoryTransport {
+    /// Creates two connected ends of the in-memory transport.
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = mpsc::channel();
+        let (b_tx, b_rx) = mpsc::channel();
+
+        (Self::new(a_tx, b_rx), Self::new(b_tx, a_rx))
+    }
+
+    fn new(tx: Sender<Vec<u8>>, rx: Receiver<Vec<u8>>) -> Self {
+        Self {
+            tx: Mutex::new(tx),
+            rx: Mutex::new(MemoryRx { rx, pending: Vec::new() }),
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, RusbError> {
+        if buf.is_empty() {
+            return Err(RusbError::InvalidParam)
+        }
+
+        let mut rx = self.rx.lock().map_err(|_| RusbError::Other)?;
+
+        // Leftovers of the previous transfer are always returned first.
+        if rx.pending.is_empty() {
+            rx.pending = match rx.rx.recv_timeout(TIMEOUT) {
+                Ok(data) => data,
+                Err(RecvTimeoutError::Timeout) => return Err(RusbError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => return Err(RusbError::NoDevice),
+            };
+        }
+
+        let len = rx.pending.len().min(buf.len());
+        buf[..len].copy_from_slice(&rx.pending[..len]);
+        rx.pending.drain(..len);
+
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, RusbError> {
+        let tx = self.tx.lock().map_err(|_| RusbError::Other)?;
+
+        tx.send(buf.to_vec()).map_err(|_| RusbError::NoDevice)?;
+        Ok(buf.len())
+    }
+
+    fn info(&self) -> String {
+        "In-memory transport".to_string()
+    }
+
+    fn machine(&self) -> String {
+        "memory".to_string()
+    }
+}
@@ -0,0 +1,197 @@
+//! Crash reports captured by the panic hook.
+//!
+//! Panics within detached tasks, e.g. the listeners of the bridge, are never observed by anyone,
+//! while panics on the JNI thread are only turned into an exception. The hook writes a record of
+//! each panic into [`FILES_DIR`], so that the front-end can offer to send it on the next start.
+//!
+//! [`FILES_DIR`]: crate::sugar::storage::FILES_DIR
+
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    fs,
+    io,
+    panic::{self, PanicHookInfo},
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex, Once},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    conn::stats::BridgeStats,
+    errors::{StorageError, SugarResult},
+    logging,
+    storage::FILES_DIR,
+};
+
+/// Directory within [`FILES_DIR`], which holds the reports.
+const CRASH_DIR: &str = "crashes";
+/// Prefix of the report's identifier.
+const PREFIX: &str = "crash";
+
+static INSTALL: Once = Once::new();
+/// Distinguishes reports of panics within the same millisecond.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Bridge, which is running right now.
+static BRIDGE: Mutex<Option<BridgeState>> = Mutex::new(None);
+
+/// Record of a single panic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// Identifier used to delete the report.
+    pub id: String,
+    /// Unix time in milliseconds.
+    pub time: u64,
+    pub thread: String,
+    pub message: String,
+    /// Source location of the panic, if known.
+    pub location: Option<String>,
+    pub backtrace: String,
+    /// State of the bridge at the moment of the panic, if one was running.
+    pub bridge: Option<String>,
+    /// Latest lines of the log before the panic.
+    pub log: Vec<String>,
+}
+
+/// Bridge as seen by the panic hook.
+pub(crate) struct BridgeState {
+    pub info: String,
+    pub machine: String,
+    pub bridge_id: u64,
+    pub stats: Arc<BridgeStats>,
+}
+
+/// Installs the panic hook. Further calls do nothing.
+///
+/// The previous hook still runs after the report is written.
+pub fn install() {
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            // The panic may come from the logger itself, so failures are only printed.
+            if let Err(err) = write(&capture(info)) {
+                eprintln!("Unable to write the crash report: {}", err);
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// Remembers the running bridge, so that its state can be included into reports.
+pub(crate) fn track_bridge(bridge: Option<BridgeState>) {
+    *BRIDGE.lock().unwrap_or_else(|err| err.into_inner()) = bridge;
+}
+
+/// Returns all pending reports, the oldest first.
+///
+/// Reports, which cannot be read, are skipped.
+pub fn reports() -> SugarResult<Vec<CrashReport>> {
+    let entries = match fs::read_dir(dir()?) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        entries => entries?,
+    };
+
+    let mut reports = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue
+        }
+
+        match read(&path) {
+            Ok(report) => reports.push(report),
+            Err(err) => log::warn!("Skipping unreadable crash report {}: {}", path.display(), err.report()),
+        }
+    }
+
+    reports.sort_by(|a, b| (a.time, &a.id).cmp(&(b.time, &b.id)));
+    Ok(reports)
+}
+
+/// Deletes the report, e.g. after it was sent.
+pub fn delete(id: &str) -> SugarResult<()> {
+    let valid = id.starts_with(PREFIX) && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid {
+        log::error!("Invalid crash report: {}", id);
+        return Err(StorageError::INVALID_KEY.into())
+    }
+
+    fs::remove_file(dir()?.join(id).with_extension("json"))?;
+    Ok(())
+}
+
+/// Deletes all pending reports. Returns the amount of deleted reports.
+pub fn delete_all() -> SugarResult<usize> {
+    let reports = reports()?;
+    for report in &reports {
+        delete(&report.id)?;
+    }
+
+    Ok(reports.len())
+}
+
+fn read(path: &Path) -> SugarResult<CrashReport> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+fn dir() -> SugarResult<PathBuf> {
+    let dir = FILES_DIR.read().unwrap_or_else(|err| err.into_inner());
+
+    if dir.as_os_str().is_empty() {
+        return Err(StorageError::NOT_INITIALIZED.into())
+    }
+
+    Ok(dir.join(CRASH_DIR))
+}
+
+/// Gathers everything about the panic. Must never panic itself, nor wait for any lock.
+fn capture(info: &PanicHookInfo) -> CrashReport {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis() as u64);
+    let thread = thread::current();
+
+    // Locks may be held by the panicking thread itself, so they are only tried.
+    let bridge = BRIDGE.try_lock().ok().and_then(|bridge| bridge.as_ref().map(|bridge| {
+        let stats = bridge.stats.try_snapshot()
+            .map_or_else(|| "Statistics are busy.\n".to_string(), |stats| stats.to_string());
+        format!("Device: {}\nMachine: {}\nBridge: {:#018x}\n{}", bridge.info, bridge.machine, bridge.bridge_id, stats)
+    }));
+
+    CrashReport {
+        id: format!("{}-{}-{}", PREFIX, time, COUNTER.fetch_add(1, Ordering::Relaxed)),
+        time,
+        thread: thread.name().unwrap_or("unnamed").to_string(),
+        message: logging::redact(message(info.payload())).into_owned(),
+        location: info.location().map(|location| location.to_string()),
+        backtrace: Backtrace::force_capture().to_string(),
+        bridge,
+        log: logging::recent_lines(),
+    }
+}
+
+/// Writes the report. Panics before the storage is initialized are not recorded.
+fn write(report: &CrashReport) -> io::Result<()> {
+    let dir = match FILES_DIR.try_read() {
+        Ok(dir) if !dir.as_os_str().is_empty() => dir.join(CRASH_DIR),
+        _ => return Ok(()),
+    };
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(&report.id).with_extension("json");
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, serde_json::to_vec_pretty(report)?)?;
+    fs::rename(&temp, &path)
+}
+
+/// Obtains the message from the panic's payload.
+pub(crate) fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.as_str()
+    } else {
+        "unknown panic"
+    }
+}
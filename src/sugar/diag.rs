@@ -0,0 +1,252 @@
+//! Diagnostic bundles attached to bug reports.
+//!
+//! A bundle is a single zip within [`EXT_FILES_DIR`], so that the front-end can share it with any
+//! application. It describes the state of the backend at the moment of the export: recent logs,
+//! the current connection and the layout of the private storage. Everything is passed through
+//! [`logging::redact`], while the contents of stored documents are never included at all.
+
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::{
+    conn::{actor::ConnectionReport, service, session::{Capabilities, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION}},
+    errors::{InternalError, StorageError, SugarError, SugarResult},
+    logging,
+    storage::{db::{self, SessionRecord}, Cache, Database, EXT_FILES_DIR, FILES_DIR},
+};
+
+/// Directory within [`EXT_FILES_DIR`], which holds the bundles.
+const BUNDLE_DIR: &str = "diagnostics";
+/// Amount of the latest sessions included into the bundle.
+const RECENT_SESSIONS: usize = 20;
+
+/// State of the backend gathered before the bundle is written.
+struct Snapshot {
+    app_version: String,
+    connection: Option<ConnectionReport>,
+    sessions: SugarResult<Vec<SessionRecord>>,
+}
+
+/// Writes a new diagnostic bundle and returns its path.
+///
+/// The application version is provided by the front-end, since the backend only knows its own one.
+pub async fn export_bundle(app_version: String) -> SugarResult<PathBuf> {
+    let snapshot = Snapshot {
+        app_version,
+        connection: service::connection_report().await,
+        sessions: Database::run(|db| db.sessions().recent(RECENT_SESSIONS)).await,
+    };
+
+    let path = tokio::task::spawn_blocking(move || write_bundle(&snapshot)).await
+        .map_err(|err| SugarError::new(InternalError::TOKIO_THREAD_ERROR).with_source(err))??;
+
+    log::info!("Diagnostic bundle is written to {}", path.display());
+    Ok(path)
+}
+
+fn write_bundle(snapshot: &Snapshot) -> SugarResult<PathBuf> {
+    let dir = EXT_FILES_DIR.read().unwrap_or_else(|err| err.into_inner()).to_path_buf();
+    if dir.as_os_str().is_empty() {
+        return Err(StorageError::NOT_INITIALIZED.into())
+    }
+    let dir = dir.join(BUNDLE_DIR);
+    fs::create_dir_all(&dir)?;
+
+    let (path, temp, file) = create_bundle(&dir)?;
+    let out = write_entries(file, snapshot).and_then(|_| Ok(fs::rename(&temp, &path)?));
+    if out.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+
+    out.map(|_| path)
+}
+
+/// Creates the temporary file of a new bundle and returns it along with both paths.
+///
+/// Several bundles can be exported within one second, so a counter is appended to the time until
+/// the name is free.
+fn create_bundle(dir: &Path) -> SugarResult<(PathBuf, PathBuf, File)> {
+    let now = db::now();
+    let mut counter = 0;
+
+    loop {
+        let path = dir.join(format!("sugar-diag-{}-{}.zip", now, counter));
+        let mut temp = path.clone().into_os_string();
+        temp.push(".part");
+        let temp = PathBuf::from(temp);
+        counter += 1;
+
+        if path.exists() {
+            continue
+        }
+        // The temporary file claims the name, even if another bundle is written right now.
+        match File::options().write(true).create_new(true).open(&temp) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            file => return Ok((path, temp, file?)),
+        }
+    }
+}
+
+fn write_entries(file: File, snapshot: &Snapshot) -> SugarResult<()> {
+    let mut zip = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let text = [
+        ("versions.txt", versions(&snapshot.app_version)),
+        ("bridge.txt", bridge(snapshot.connection.as_ref())),
+        ("sessions.txt", sessions(&snapshot.sessions)),
+        ("storage.txt", storage()),
+    ];
+    for (name, content) in text {
+        zip.start_file(name, options)?;
+        zip.write_all(logging::redact(&content).as_bytes())?;
+    }
+
+    for log in logging::log_files() {
+        // Files are rotated by the logger at any time, so a missing one is simply skipped.
+        let content = match fs::read(&log) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            content => content?,
+        };
+        let name = log.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+        zip.start_file(format!("logs/{}", name), options)?;
+        zip.write_all(logging::redact(&String::from_utf8_lossy(&content)).as_bytes())?;
+    }
+
+    zip.finish()?.sync_all()?;
+    Ok(())
+}
+
+fn versions(app_version: &str) -> String {
+    let mut out = String::new();
+    writeln!(out, "Application: {}", app_version).ok();
+    writeln!(out, "Backend: {}", env!("CARGO_PKG_VERSION")).ok();
+    writeln!(out, "Protocol version: {} (minimal {})", PROTOCOL_VERSION, MIN_PROTOCOL_VERSION).ok();
+    writeln!(out, "Capabilities: {:#010x}", Capabilities::SUPPORTED.0).ok();
+    writeln!(out, "Target: {}-{}", std::env::consts::ARCH, std::env::consts::OS).ok();
+    out
+}
+
+fn bridge(connection: Option<&ConnectionReport>) -> String {
+    let connection = match connection {
+        Some(connection) => connection,
+        None => return "Not connected.\n".to_string(),
+    };
+
+    let mut out = String::new();
+    writeln!(out, "Device: {}", connection.info).ok();
+    writeln!(out, "Machine: {}", connection.machine).ok();
+    writeln!(out, "Bridge: {:#018x}", connection.bridge_id).ok();
+    writeln!(out, "State: {:?}", connection.state).ok();
+    if let Some(session) = connection.session {
+        writeln!(out, "Session record: {}", session).ok();
+    }
+    writeln!(out, "\n{}", connection.stats).ok();
+    match &connection.descriptor {
+        Some(descriptor) => writeln!(out, "USB device descriptor:\n{}", descriptor),
+        None => writeln!(out, "No USB device descriptor."),
+    }.ok();
+    out
+}
+
+fn sessions(sessions: &SugarResult<Vec<SessionRecord>>) -> String {
+    let sessions = match sessions {
+        Ok(sessions) => sessions,
+        Err(err) => return format!("Unable to read the sessions: {}\n", err.report()),
+    };
+
+    let mut out = String::new();
+    for session in sessions {
+        writeln!(out, "{} {} bridge {:#018x} started {} ended {} error {}",
+            session.id,
+            session.machine,
+            session.bridge_id,
+            session.started_at,
+            session.ended_at.map_or("-".to_string(), |time| time.to_string()),
+            session.error.map_or("-".to_string(), |code| code.to_string()),
+        ).ok();
+    }
+    out
+}
+
+/// Lists the private storage with sizes and modification times only, never the contents.
+fn storage() -> String {
+    let root = FILES_DIR.read().unwrap_or_else(|err| err.into_inner()).to_path_buf();
+    let mut out = String::new();
+
+    if root.as_os_str().is_empty() {
+        return "Storage is not initialized.\n".to_string()
+    }
+
+    match list(&root) {
+        Ok(files) => for (name, size, modified) in files {
+            writeln!(out, "{} {} bytes modified {}", name, size, modified).ok();
+        },
+        Err(err) => { writeln!(out, "Unable to list the storage: {}", err).ok(); },
+    }
+
+    match Cache::usage() {
+        Ok(usage) => writeln!(out, "\nCache: {} internal, {} external, {} pinned, {} items, budget {}",
+            usage.internal, usage.external, usage.pinned, usage.items, usage.budget),
+        Err(err) => writeln!(out, "\nUnable to measure the cache: {}", err.report()),
+    }.ok();
+    out
+}
+
+/// Returns relative names, sizes and modification times of all files within the directory.
+fn list(root: &Path) -> io::Result<Vec<(String, u64, u64)>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+
+            if meta.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                let path = entry.path();
+                let name = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+                let modified = meta.modified().ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |time| time.as_secs());
+
+                files.push((name, meta.len(), modified));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundles_within_one_second_are_kept() {
+        let dir = std::env::temp_dir().join(format!("sugar-diag-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+
+        let (first, first_temp, _) = create_bundle(&dir).unwrap();
+        fs::rename(&first_temp, &first).unwrap();
+        // The other one is still being written.
+        let (second, second_temp, _) = create_bundle(&dir).unwrap();
+        let (third, ..) = create_bundle(&dir).unwrap();
+
+        assert_ne!(first, second);
+        assert_ne!(second, third);
+        assert!(first.exists() && second_temp.exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,81 +1,260 @@
 //! Module for defining different status errors.
+//!
+//! All failures of the backend are represented by a single [`SugarError`]. Each error belongs to
+//! one failure domain, which holds the exact kind of the error. Every kind has its own stable
+//! code, which is unique across all domains, so the front-end can always tell them apart. Codes
+//! are grouped by hundreds, one hundred per domain:
+//!
+//! - `1xx`: internal application errors;
+//! - `2xx`: storage errors;
+//! - `3xx`: authentication errors;
+//! - `4xx`: bridge errors;
+//! - `5xx`: client request errors.
+//!
+//! Code `0` is never used by any error and means success on the Java side.
 #![allow(non_camel_case_types)]
 
-use firebase_auth_sdk::Error;
-use std::fmt::Display;
+use firebase_auth_sdk::Error as FirebaseError;
+use std::{error::Error, fmt::Display, io, sync::Arc};
+
+/// Result of any operation, which may fail within the backend.
+pub type SugarResult<T> = Result<T, SugarError>;
 
 /// Internal application errors.
 ///
 /// Errors, which occur due to some internal application faults.
-#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
 pub enum InternalError {
     /// Can occur if some of tokio threads did not exit successfully or panicked for some reason.
     /// This is a inner application bug.
-    TOKIO_THREAD_ERROR = 1,
+    TOKIO_THREAD_ERROR = 101,
     /// Will occur if unable to connect to some service. This is most likely a networking issue.
-    NETWORK_ERROR = 2,
+    NETWORK_ERROR = 102,
+    /// Call from the front-end has failed on the boundary, e.g. the backend has panicked or a
+    /// Java argument could not be read. A Java exception with the details is thrown as well.
+    FFI_ERROR = 103,
 }
 
 /// Application error related to storage manipulations.
 ///
 /// Basically flags if the read or write operation was done successfully or not. It is used with
 /// both local storage and cloud alternatives.
-#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
 pub enum StorageError {
     /// if trying to read from non-existing file.
-    FILE_NOT_EXIST = 40,
-    /// Either read or write is taking too much time and caused an IO timeout. 
-    TIME_OUT = 41,
+    FILE_NOT_EXIST = 201,
+    /// Either read or write is taking too much time and caused an IO timeout.
+    TIME_OUT = 202,
     /// The data's content does not match it's destination.
-    BAD_DATA = 42,
+    BAD_DATA = 203,
     /// No data is provided for writing, or the file is empty.
-    NO_DATA = 43,
-    /// This can only occur if serde's Serialize/Deserialize macro will somehow fail. Not very likely. 
-    SERIALIZATION_ERROR = 44,
+    NO_DATA = 204,
+    /// This can only occur if serde's Serialize/Deserialize macro will somehow fail. Not very likely.
+    SERIALIZATION_ERROR = 205,
     /// Not enough memory to perform read or write. This can be handled from the software side and
     /// fixed right away.
-    OUT_OF_MEMORY = 45,
+    OUT_OF_MEMORY = 206,
     /// An IO operation was interrupted.
-    INTERRUPTED = 46,
+    INTERRUPTED = 207,
+    /// Any other IO failure. The OS error is kept as the source.
+    IO_ERROR = 208,
+    /// The application has no permission to access the file.
+    PERMISSION_DENIED = 209,
+    /// The file already exists.
+    ALREADY_EXISTS = 210,
+    /// There is no space left on the device, or the quota is exceeded.
+    STORAGE_FULL = 211,
+    /// The storage directory is not provided by the front-end yet.
+    NOT_INITIALIZED = 212,
+    /// The key of the document contains characters, which are not allowed in a file name.
+    INVALID_KEY = 213,
+    /// The document is written by a newer version of the application.
+    UNSUPPORTED_SCHEMA = 214,
+    /// The document cannot be decrypted: it was tampered with or sealed with another key.
+    DECRYPTION_FAILED = 215,
+    /// The key for the encrypted documents cannot be obtained.
+    KEY_UNAVAILABLE = 216,
+    /// The database rejected the query.
+    DATABASE_ERROR = 217,
 }
 
-/// Errors which occur during signup.
-#[repr(u8)]
-pub enum SignupError {
+/// Errors which occur during signup, login or changes of user's profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum AuthError {
     /// Email is in wrong format.
-    INVALID_EMAIL = 10,
+    INVALID_EMAIL = 301,
+    /// Wrong password.
+    INVALID_PASS = 302,
     /// Password is too short.
-    INVALID_PASS = 11,
+    WEAK_PASS = 303,
     /// User with provided email already exist.
-    EMAIL_EXISTS = 20,
+    EMAIL_EXISTS = 304,
     /// Too many attempts. This will happen, if the device was doing too many requests and
     /// firebase counter it as an unusual activity.
-    TOO_MANY_ATTEMPTS = 21,
-}
-
-/// Errors which occur during login.
-#[repr(u8)]
-pub enum LoginError {
-    /// Email is in wrong format.
-    INVALID_EMAIL = 10,
-    /// Wrong password.
-    INVALID_PASS = 11,
+    TOO_MANY_ATTEMPTS = 305,
     /// There is no user in firebase auth system with provided email.
-    INVALID_LOGIN_CREDENTIALS = 30,
+    INVALID_LOGIN_CREDENTIALS = 306,
     /// User was disabled by an administrator.
-    USER_DISABLED = 31,
+    USER_DISABLED = 307,
     /// Token is expired and user must login once more to renew it.
-    TOKEN_EXPIRED = 32,
+    TOKEN_EXPIRED = 308,
     /// Token is not valid yet.
-    TOKEN_NOT_VALID = 33,
+    TOKEN_NOT_VALID = 309,
     /// Either token's header is malformed or it is missing kid property.
-    MALFORMED_TOKEN_HEADER = 34,
+    MALFORMED_TOKEN_HEADER = 310,
     /// Unable to parse keys from token validation request.
-    KEY_PARSING_ERROR = 35,
-    /// Unable to decode the verification key. 
-    NO_MATCH_DECODING_KEY = 36,
+    KEY_PARSING_ERROR = 311,
+    /// Unable to decode the verification key.
+    NO_MATCH_DECODING_KEY = 312,
     /// Provided email is not found.
-    EMAIL_NOT_FOUND = 37,
+    EMAIL_NOT_FOUND = 313,
+    /// User's id from the session cannot be represented as a number.
+    INVALID_USER_ID = 314,
+    /// Firebase has answered with an error, which is not known to the application.
+    UNKNOWN = 399,
+}
+
+/// Errors of the bridge communication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum BridgeError {
+    /// Appears when trying to close a bridge, which is yet not initialized fully.
+    BridgeNotReady = 401,
+    /// Appears when the target device has refused a connection. Can happen when trying to connect
+    /// to a device when it is already is connected or when the bridge ID does not match.
+    ConnectionRefused = 402,
+    /// Connection to the device has timed out.
+    ConnectionTimeout = 403,
+    /// Unable to send any data to the bridge since it is closed.
+    BridgeClosed = 404,
+    /// Unable to setup a new libusb context
+    ContextError = 405,
+    /// Unable to read a usb device by a file descriptor.
+    FileDescriptorError = 406,
+    /// The target device kept sending corrupted commands even after all retransmission requests.
+    RetryLimitExceeded = 407,
+    /// Unable to transfer the data through the transport.
+    TransferError = 408,
+    /// The target device does not speak a compatible version of the protocol.
+    IncompatibleTarget = 409,
+    /// Another bridge is still running.
+    AlreadyConnected = 410,
+    /// There is no running bridge.
+    NotConnected = 411,
+    /// The device has no interface with a pair of bulk endpoints.
+    NoSuitableInterface = 412,
+    /// Unable to claim the interface of the device, e.g. since another driver holds it.
+    InterfaceClaimError = 413,
+    /// The device was disconnected from the bus.
+    DeviceLost = 414,
+    /// The data does not fit into a single command.
+    DataTooLarge = 415,
+}
+
+/// Errors which occur while waiting for an answer from the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum ClientError {
+    /// The target has refused the request.
+    Refused = 501,
+    /// The bridge is closed, so no answer will ever come.
+    BridgeClosed = 502,
+    /// The target did not answer in time.
+    Timeout = 503,
+    /// The answer does not contain the expected data.
+    BadData = 504,
+    /// The request, e.g. a long path, does not fit into a single command.
+    TooLarge = 505,
+}
+
+/// Failure domain, which the error belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Internal(InternalError),
+    Storage(StorageError),
+    Auth(AuthError),
+    Bridge(BridgeError),
+    Client(ClientError),
+}
+
+impl ErrorKind {
+    /// Stable code of the error, which is unique across all domains.
+    pub fn code(&self) -> u16 {
+        match *self {
+            Self::Internal(err) => err as u16,
+            Self::Storage(err) => err as u16,
+            Self::Auth(err) => err as u16,
+            Self::Bridge(err) => err as u16,
+            Self::Client(err) => err as u16,
+        }
+    }
+}
+
+/// Any error of the backend.
+///
+/// Along with its kind, the error may keep the lower level error which has caused it. Cloning
+/// the error shares the source.
+#[derive(Debug, Clone)]
+pub struct SugarError {
+    kind: ErrorKind,
+    source: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl SugarError {
+    /// Creates a new error without any source.
+    pub fn new(kind: impl Into<ErrorKind>) -> Self {
+        Self { kind: kind.into(), source: None }
+    }
+
+    /// Attaches the lower level error, which has caused this one.
+    pub fn with_source(mut self, source: impl Error + Send + Sync + 'static) -> Self {
+        self.source.replace(Arc::new(source));
+        self
+    }
+
+    /// Returns the kind of this error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Stable code of the error, which is passed to the front-end.
+    pub fn code(&self) -> u16 {
+        self.kind.code()
+    }
+
+    /// Formats the error along with all of its sources.
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = self.source();
+
+        while let Some(err) = source {
+            report.push_str(&format!(" Caused by: {}", err));
+            source = err.source();
+        }
+
+        report
+    }
+}
+
+impl Display for SugarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ErrorKind::Internal(err) => err.fmt(f),
+            ErrorKind::Storage(err) => err.fmt(f),
+            ErrorKind::Auth(err) => err.fmt(f),
+            ErrorKind::Bridge(err) => err.fmt(f),
+            ErrorKind::Client(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for SugarError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|err| err as &(dyn Error + 'static))
+    }
 }
 
 impl Display for InternalError {
@@ -83,6 +262,7 @@ impl Display for InternalError {
         match self {
             Self::TOKIO_THREAD_ERROR => write!(f, "Tokio Thread did not exit successfully"),
             Self::NETWORK_ERROR => write!(f, "Network error. No internet connection."),
+            Self::FFI_ERROR => write!(f, "Call from the front-end has failed."),
         }
     }
 }
@@ -97,26 +277,28 @@ impl Display for StorageError {
             Self::TIME_OUT => write!(f, "IO operation failed: Operation timed out."),
             Self::INTERRUPTED => write!(f, "IO operation failed: Interrupted, retrying..."),
             Self::OUT_OF_MEMORY => write!(f, "IO opertation failed: Out of memory, retrying..."),
+            Self::IO_ERROR => write!(f, "IO operation failed."),
+            Self::PERMISSION_DENIED => write!(f, "IO operation failed: Permission denied."),
+            Self::ALREADY_EXISTS => write!(f, "IO operation failed: File already exists."),
+            Self::STORAGE_FULL => write!(f, "IO operation failed: No space left on the device."),
+            Self::NOT_INITIALIZED => write!(f, "IO operation failed: Storage directory is not initialized."),
+            Self::INVALID_KEY => write!(f, "IO operation failed: Invalid storage key."),
+            Self::UNSUPPORTED_SCHEMA => write!(f, "IO operation failed: Unsupported version of the document."),
+            Self::DECRYPTION_FAILED => write!(f, "IO operation failed: Unable to decrypt the document."),
+            Self::KEY_UNAVAILABLE => write!(f, "IO operation failed: Encryption key is not available."),
+            Self::DATABASE_ERROR => write!(f, "IO operation failed: Database error."),
         }
     }
 }
 
-impl Display for SignupError {
+impl Display for AuthError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::INVALID_EMAIL => write!(f, "Invalid email regex was provided."),
-            Self::INVALID_PASS => write!(f, "Provided password is too weak."),
+            Self::INVALID_PASS => write!(f, "Provided password is wrong."),
+            Self::WEAK_PASS => write!(f, "Provided password is too weak."),
             Self::EMAIL_EXISTS => write!(f, "Email exists, login required."),
             Self::TOO_MANY_ATTEMPTS => write!(f, "Too many attepts. Waiting on CD."),
-        }
-    }
-}
-
-impl Display for LoginError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::INVALID_EMAIL => write!(f, "Invalid email regex was provided."),
-            Self::INVALID_PASS => write!(f, "Provided password is too weak."),
             Self::INVALID_LOGIN_CREDENTIALS => write!(f, "Either password or email is wrong. Retry is needed."),
             Self::USER_DISABLED => write!(f, "User is disabled by application administrator."),
             Self::TOKEN_EXPIRED => write!(f, "Token is expired."),
@@ -125,91 +307,191 @@ impl Display for LoginError {
             Self::KEY_PARSING_ERROR => write!(f, "Unable to parse keys from token validation requerst."),
             Self::NO_MATCH_DECODING_KEY => write!(f, "Unable to decode the verification key."),
             Self::EMAIL_NOT_FOUND => write!(f, "Email not found."),
+            Self::INVALID_USER_ID => write!(f, "User's id is not a number."),
+            Self::UNKNOWN => write!(f, "Unknown authentication error."),
         }
     }
 }
 
-impl Into<SignupError> for Error {
-    fn into(self) -> SignupError {
+impl Display for BridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::SignUp(s) => {
-                if s.contains("INVALID_EMAIL") {
-                    return SignupError::INVALID_EMAIL
-                }
-                if s.contains("WEAK_PASSWORD") {
-                    return SignupError::INVALID_PASS
-                }
-                if s.contains("EMAIL_EXISTS") {
-                    return SignupError::EMAIL_EXISTS
-                }
-                if s.contains("TOO_MANY_ATTEMPTS_TRY_LATER") {
-                    return SignupError::TOO_MANY_ATTEMPTS
-                }
-
-                log::error!("Fatal error: {}", s);
-                panic!();            
-            }
-            _ => unreachable!(),
+            Self::BridgeNotReady => write!(f, "Bridge error: bridge is not connected yet."),
+            Self::ConnectionRefused => write!(f, "Bridge error: target has refused the connection."),
+            Self::ConnectionTimeout => write!(f, "Bridge error: connection has timed out."),
+            Self::BridgeClosed => write!(f, "Bridge error: bridge is closed."),
+            Self::ContextError => write!(f, "Bridge error: unable to setup the libusb context."),
+            Self::FileDescriptorError => write!(f, "Bridge error: unable to open the device by its file descriptor."),
+            Self::RetryLimitExceeded => write!(f, "Bridge error: target keeps sending corrupted commands."),
+            Self::TransferError => write!(f, "Bridge error: unable to transfer the data."),
+            Self::IncompatibleTarget => write!(f, "Bridge error: target speaks an incompatible protocol version."),
+            Self::AlreadyConnected => write!(f, "Bridge error: another bridge is still running."),
+            Self::NotConnected => write!(f, "Bridge error: not connected."),
+            Self::NoSuitableInterface => write!(f, "Bridge error: device has no interface with bulk endpoints."),
+            Self::InterfaceClaimError => write!(f, "Bridge error: unable to claim the interface of the device."),
+            Self::DeviceLost => write!(f, "Bridge error: device is disconnected."),
+            Self::DataTooLarge => write!(f, "Bridge error: data does not fit into one command."),
         }
     }
 }
 
-impl Into<LoginError> for Error {
-    fn into(self) -> LoginError {
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::SignIn(s) => {
-                if s.contains("EMAIL_NOT_FOUND") {
-                    return LoginError::EMAIL_NOT_FOUND
-                }
-                if s.contains("INVALID_EMAIL") {
-                    return LoginError::INVALID_EMAIL
-                }
-                if s.contains("INVALID_PASSWORD") {
-                    return LoginError::INVALID_PASS
-                }
-                if s.contains("INVALID_LOGIN_CREDENTIALS") {
-                    return LoginError::INVALID_LOGIN_CREDENTIALS
-                }
-                if s.contains("USER_DISABLED") {
-                    return LoginError::USER_DISABLED
-
-                }
-            
-                log::error!("Fatal error: {}", s);
-                panic!();   
-            },
-            Self::Token(s) => {
-                if s.contains("Invalid ID token") || s.contains("Token isn't valid yet!") {
-                    return LoginError::TOKEN_NOT_VALID
-                }
-                if s.contains("Token is expired") || s.contains("TOKEN_EXPIRED") {
-                    return LoginError::TOKEN_EXPIRED
-                }
-                if s.contains("token header") {
-                    return LoginError::MALFORMED_TOKEN_HEADER
-                }
-                if s.contains("No match decoding key!") {
-                    return LoginError::NO_MATCH_DECODING_KEY
-                }
-
-                log::error!("Fatal error: {}", s);
-                panic!();  
+            Self::Refused => write!(f, "Client error: request was refused by the target."),
+            Self::BridgeClosed => write!(f, "Client error: bridge is closed."),
+            Self::Timeout => write!(f, "Client error: target did not answer in time."),
+            Self::BadData => write!(f, "Client error: answer contains unexpected data."),
+            Self::TooLarge => write!(f, "Client error: request does not fit into one command."),
+        }
+    }
+}
+
+impl Error for InternalError {}
+impl Error for StorageError {}
+impl Error for AuthError {}
+impl Error for BridgeError {}
+impl Error for ClientError {}
+
+// Each kind is placed in its own domain.
+macro_rules! impl_domain {
+    ($($kind:ident => $domain:ident),*) => {$(
+        impl From<$kind> for ErrorKind {
+            fn from(err: $kind) -> Self {
+                Self::$domain(err)
             }
-            Self::User(s) => {
-                if s.contains("WEAK_PASSWORD") {
-                    return LoginError::INVALID_PASS
-                }
-                if s.contains("INVALID_EMAIL") {
-                    return LoginError::INVALID_EMAIL
-                }
-                if s.contains("CREDENTIAL_TOO_OLD_LOGIN_AGAIN") {
-                    return LoginError::TOKEN_EXPIRED
-                }
-
-                log::error!("Fatal error: {}", s);
-                panic!(); 
+        }
+
+        impl From<$kind> for SugarError {
+            fn from(err: $kind) -> Self {
+                Self::new(err)
             }
-            _ => unreachable!(),
         }
+    )*};
+}
+
+impl_domain!(
+    InternalError => Internal,
+    StorageError => Storage,
+    AuthError => Auth,
+    BridgeError => Bridge,
+    ClientError => Client
+);
+
+impl From<rusb::Error> for SugarError {
+    fn from(err: rusb::Error) -> Self {
+        let kind = match err {
+            rusb::Error::Timeout => BridgeError::ConnectionTimeout,
+            // Just like the failed transfers, see `recovery::fatal`.
+            rusb::Error::NoDevice => BridgeError::DeviceLost,
+            _ => BridgeError::TransferError,
+        };
+
+        Self::new(kind).with_source(err)
     }
 }
+
+impl From<io::Error> for SugarError {
+    fn from(err: io::Error) -> Self {
+        let kind = match err.kind() {
+            io::ErrorKind::NotFound => StorageError::FILE_NOT_EXIST,
+            io::ErrorKind::TimedOut => StorageError::TIME_OUT,
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => StorageError::BAD_DATA,
+            io::ErrorKind::UnexpectedEof => StorageError::NO_DATA,
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => StorageError::INTERRUPTED,
+            io::ErrorKind::OutOfMemory => StorageError::OUT_OF_MEMORY,
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => StorageError::PERMISSION_DENIED,
+            io::ErrorKind::AlreadyExists => StorageError::ALREADY_EXISTS,
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => StorageError::STORAGE_FULL,
+            _ => StorageError::IO_ERROR,
+        };
+
+        Self::new(kind).with_source(err)
+    }
+}
+
+impl From<serde_json::Error> for SugarError {
+    fn from(err: serde_json::Error) -> Self {
+        // Failures of the underlying reader or writer are IO errors, not serialization ones.
+        if err.is_io() {
+            return io::Error::from(err).into()
+        }
+
+        Self::new(StorageError::SERIALIZATION_ERROR).with_source(err)
+    }
+}
+
+impl From<rusqlite::Error> for SugarError {
+    fn from(err: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+
+        let kind = match err.sqlite_error_code() {
+            Some(ErrorCode::CannotOpen) => StorageError::FILE_NOT_EXIST,
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => StorageError::TIME_OUT,
+            Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => StorageError::BAD_DATA,
+            Some(ErrorCode::OutOfMemory) => StorageError::OUT_OF_MEMORY,
+            Some(ErrorCode::OperationInterrupted) => StorageError::INTERRUPTED,
+            Some(ErrorCode::PermissionDenied | ErrorCode::ReadOnly) => StorageError::PERMISSION_DENIED,
+            Some(ErrorCode::DiskFull) => StorageError::STORAGE_FULL,
+            Some(ErrorCode::SystemIoFailure) => StorageError::IO_ERROR,
+            _ => match err {
+                rusqlite::Error::FromSqlConversionFailure(..) | rusqlite::Error::IntegralValueOutOfRange(..) => StorageError::BAD_DATA,
+                rusqlite::Error::QueryReturnedNoRows => StorageError::NO_DATA,
+                _ => StorageError::DATABASE_ERROR,
+            },
+        };
+
+        Self::new(kind).with_source(err)
+    }
+}
+
+impl From<zip::result::ZipError> for SugarError {
+    fn from(err: zip::result::ZipError) -> Self {
+        match err {
+            zip::result::ZipError::Io(err) => err.into(),
+            err => Self::new(StorageError::IO_ERROR).with_source(err),
+        }
+    }
+}
+
+impl From<FirebaseError> for SugarError {
+    fn from(err: FirebaseError) -> Self {
+        let kind = match &err {
+            FirebaseError::API(_) => ErrorKind::Internal(InternalError::NETWORK_ERROR),
+            FirebaseError::SignUp(s) | FirebaseError::SignIn(s) | FirebaseError::Token(s) | FirebaseError::User(s) => {
+                ErrorKind::Auth(auth_error(s))
+            },
+        };
+
+        Self::new(kind).with_source(err)
+    }
+}
+
+/// Recognizes the error by the message obtained from firebase.
+fn auth_error(s: &str) -> AuthError {
+    const KNOWN: [(&str, AuthError); 15] = [
+        ("EMAIL_NOT_FOUND", AuthError::EMAIL_NOT_FOUND),
+        ("INVALID_EMAIL", AuthError::INVALID_EMAIL),
+        ("INVALID_PASSWORD", AuthError::INVALID_PASS),
+        ("WEAK_PASSWORD", AuthError::WEAK_PASS),
+        ("EMAIL_EXISTS", AuthError::EMAIL_EXISTS),
+        ("TOO_MANY_ATTEMPTS_TRY_LATER", AuthError::TOO_MANY_ATTEMPTS),
+        ("INVALID_LOGIN_CREDENTIALS", AuthError::INVALID_LOGIN_CREDENTIALS),
+        ("USER_DISABLED", AuthError::USER_DISABLED),
+        ("Invalid ID token", AuthError::TOKEN_NOT_VALID),
+        ("Token isn't valid yet!", AuthError::TOKEN_NOT_VALID),
+        ("Token is expired", AuthError::TOKEN_EXPIRED),
+        ("TOKEN_EXPIRED", AuthError::TOKEN_EXPIRED),
+        ("CREDENTIAL_TOO_OLD_LOGIN_AGAIN", AuthError::TOKEN_EXPIRED),
+        ("token header", AuthError::MALFORMED_TOKEN_HEADER),
+        ("No match decoding key!", AuthError::NO_MATCH_DECODING_KEY),
+    ];
+
+    KNOWN.iter()
+        .find(|(pattern, _)| s.contains(pattern))
+        .map(|(_, kind)| *kind)
+        .unwrap_or_else(|| {
+            log::error!("Unknown authentication error: {}", s);
+            AuthError::UNKNOWN
+        })
+}
@@ -0,0 +1,113 @@
+//! Events pushed from the backend to the front-end.
+//!
+//! Any part of the backend can emit an event from any thread or task. Emitting never blocks:
+//! events are queued and delivered one by one to the registered sink from a single dispatcher
+//! thread. This way the sink only has to attach that one thread to the JVM.
+
+use std::{sync::{mpsc::{self, Sender}, Mutex}, thread, time::Duration};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use super::{
+    conn::{client::{Disk, Partition, RemoteEntry}, lifecycle::LifecycleState},
+    errors::SugarError,
+};
+
+/// Name of the thread which delivers all events.
+const DISPATCHER_NAME: &str = "sugar-events";
+
+/// Sender to the running dispatcher, if any sink is registered.
+static DISPATCHER: Mutex<Option<Sender<Event>>> = Mutex::new(None);
+
+/// Event which is pushed to the front-end.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Session with the target is negotiated.
+    Connected,
+    /// Bridge is closed, either properly or due to the error.
+    Disconnected(Option<SugarError>),
+    /// Bridge has moved to another state of its lifecycle.
+    State(LifecycleState),
+    /// Bridge has failed and is reconnected after the delay.
+    Reconnecting { attempt: u32, delay: Duration, error: SugarError },
+    /// Disks of the target.
+    Disks(Vec<Disk>),
+    /// Partitions of the disk.
+    Partitions(Disk, Vec<Partition>),
+    /// Files and directories in the root of the partition.
+    Files(Partition, Vec<RemoteEntry>),
+    /// Amount of bytes of the file transferred so far.
+    Progress { file: RemoteEntry, done: u64, finished: bool },
+    /// Error, which was not returned to any caller.
+    Error(SugarError),
+    /// Log line of the backend.
+    Log(Level, String),
+}
+
+/// Receiver of all events.
+///
+/// The sink is always called from the same dispatcher thread.
+pub trait EventSink: Send + 'static {
+    fn handle(&mut self, event: Event);
+}
+
+/// Registers the sink, which will obtain all following events.
+///
+/// The previous sink is dropped, once it has handled all events emitted before.
+pub fn set_sink<S: EventSink>(mut sink: S) {
+    let (tx, rx) = mpsc::channel::<Event>();
+
+    let spawned = thread::Builder::new()
+        .name(DISPATCHER_NAME.to_string())
+        .spawn(move || rx.into_iter().for_each(|event| sink.handle(event)));
+
+    match spawned {
+        Ok(_) => { DISPATCHER.lock().unwrap_or_else(|err| err.into_inner()).replace(tx); },
+        Err(err) => log::error!("Unable to spawn the event dispatcher: {}", err),
+    }
+}
+
+/// Removes the current sink. All following events are dropped.
+pub fn clear_sink() {
+    DISPATCHER.lock().unwrap_or_else(|err| err.into_inner()).take();
+}
+
+/// Pushes the event to the registered sink, if any.
+pub fn emit(event: Event) {
+    if let Some(tx) = DISPATCHER.lock().unwrap_or_else(|err| err.into_inner()).as_ref() {
+        // The dispatcher only stops when its sender is dropped.
+        tx.send(event).ok();
+    }
+}
+
+/// Logger which forwards all records to the inner logger and emits them as events.
+pub struct EventLogger<L: Log> {
+    inner: L,
+    /// The most verbose level emitted as events.
+    level: LevelFilter,
+}
+
+impl<L: Log> EventLogger<L> {
+    pub fn new(inner: L, level: LevelFilter) -> Self {
+        Self { inner, level }
+    }
+}
+
+impl<L: Log> Log for EventLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.log(record);
+
+        // Records from the dispatcher itself are not emitted, otherwise a failing sink would
+        // keep feeding itself with its own errors.
+        if record.level() <= self.level && thread::current().name() != Some(DISPATCHER_NAME) {
+            emit(Event::Log(record.level(), record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
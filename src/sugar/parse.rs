@@ -1,6 +1,11 @@
 //! Custom module for parsing daemon-mobile communication byte code.
 
-use super::conn::{cmd::{DaemonCommand, DaemonCommandByte}, Bridge, Buffer};
+use super::conn::{
+    cmd::{DaemonCommand, DaemonCommandByte, DecodedCommand, ProtocolError},
+    lifecycle::LifecycleState,
+    session::HandshakeError,
+    Bridge, Transport,
+};
 
 /// Struct which handles all parsing activity related to user input and data.
 ///
@@ -11,57 +16,74 @@ pub struct SugarParser;
 impl SugarParser {
     /// Parses the daemon byte communication code and based on the result, calls different
     /// functions and changes the state of the communication bridge.
-    pub async fn parse_byte_code(bridge: &mut Bridge, command: DaemonCommand) -> ParseOutput {
+    ///
+    /// Every command is validated against its checksum first. A corrupted command is never parsed,
+    /// but a retransmission request is sent to the target instead.
+    pub async fn parse_byte_code<T: Transport>(bridge: &mut Bridge<T>, command: DaemonCommand) -> ParseOutput {
         use DaemonCommandByte::*;
 
-        let res = DaemonCommand::blank();
-        let mut cmd_iter = command.byte_code().to_owned().into_iter();
-        let mut _size: u8 = 0;
-        let mut _count: u8 = 0;
-        let mut _prev_byte = None;
+        if command.byte_code().is_empty() {
+            return ParseOutput::Empty
+        }
 
-        loop {
-            match cmd_iter.next() {
-                Some(byte) => {
-                    _count = _count.wrapping_add(byte.into());
+        if !command.is_valid() {
+            bridge.retries = bridge.retries.saturating_add(1);
+            // Errors are already logged by the bridge, the retransmission will be requested once
+            // more on the next corrupted command.
+            bridge.transmit(&DaemonCommand::retry()).await.ok();
+            bridge.pending.restart();
+            return ParseOutput::Checksum
+        }
+        bridge.retries = 0;
 
-                    match byte {
-                        prefix @ (REQ | ACK | NACK) => {
-                            _prev_byte.replace(prefix);
-                        }
-                        data => {
-                            if _prev_byte.is_none() {
-                                _size = data.into();
-                                _prev_byte.replace(SIZE);
-                            }
-                            break;
-                        }
-                    }
+        let decoded = match command.decode() {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                // Refusing the command, so that the target will know it was not handled. Refusals
+                // are never refused back, otherwise both sides would bounce them forever.
+                if command.body().first() != Some(&NACK.into()) {
+                    bridge.transmit(&DaemonCommand::nack(err)).await.ok();
                 }
-                None => {
-                    let mut buffer = bridge.buf.lock().await;
-                    let device = bridge.device.lock().await;
-
-                    let (response, output) = if _count == 0 {
-                        if _prev_byte.is_some() {
-                            (res, ParseOutput::Success)
-                        } else {
-                            (DaemonCommand::retry(), ParseOutput::Empty)
-                        }
-                    } else {
-                        (DaemonCommand::retry(), ParseOutput::Empty)
-                    };
+                return ParseOutput::Protocol(err)
+            },
+        };
 
-                    while let Err(err) = buffer.write(&device, response.clone()) {
-                        log::error!("Unable to write data to the target device: {}", err);
-                    }
-
-                    return output;
+        match decoded {
+            // Shutdown is the only command which comes without a prefix. The user's shutdown must
+            // also be forwarded to the target.
+            DecodedCommand { prefix: None, command: SHUT, data } => {
+                bridge.enter(LifecycleState::Draining);
+                if data == [ACK as u8; 3] {
+                    bridge.transmit(&command).await.ok();
+                }
+                ParseOutput::Shutdown
+            },
+            DecodedCommand { prefix: Some(REQ), command: RET, .. } => {
+                log::warn!("Target device requested a retransmission.");
+                bridge.resend().await.ok();
+                ParseOutput::Success
+            },
+            // The target confirms, that the last answer is about to be sent once more.
+            DecodedCommand { prefix: Some(ACK), command: RET, .. } => {
+                bridge.pending.resume();
+                ParseOutput::Success
+            },
+            DecodedCommand { prefix: Some(ACK), command: CONN, data } => match data.split_first() {
+                Some((bid, data)) if *bid == BID as u8 => match bridge.establish(data) {
+                    Ok(_) => ParseOutput::Success,
+                    Err(err) => ParseOutput::Handshake(err),
+                },
+                _ => ParseOutput::UnparsableTokens,
+            },
+            DecodedCommand { prefix: Some(NACK), command: CONN, .. } => ParseOutput::Refused,
+            DecodedCommand { prefix: Some(ACK | NACK), .. } => {
+                if !bridge.pending.answer(&decoded) {
+                    log::warn!("Obtained an answer, which no request waits for.");
                 }
-            }
+                ParseOutput::Success
+            },
+            _ => ParseOutput::UnparsableTokens,
         }
-
-        ParseOutput::UnparsableTokens
     }
 }
 
@@ -73,6 +95,14 @@ pub enum ParseOutput {
     Checksum,
     /// The command is empty.
     Empty,
-    /// Unable to parse the command. 
+    /// Unable to parse the command.
     UnparsableTokens,
+    /// The command contains bytes which are not a part of the protocol.
+    Protocol(ProtocolError),
+    /// The connection must be closed.
+    Shutdown,
+    /// The target has refused the connection.
+    Refused,
+    /// The target's answer to the handshake is not acceptable.
+    Handshake(HandshakeError),
 }
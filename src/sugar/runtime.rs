@@ -0,0 +1,49 @@
+//! Long-lived asynchronous runtime of the backend.
+//!
+//! Calls from Java come from arbitrary threads, which are not a part of any Tokio runtime. Instead
+//! of building a new runtime on each call, one multi-threaded runtime is created on the
+//! initialization and shared by all calls. Tasks spawned on it, like a running bridge, outlive the
+//! call which has spawned them.
+
+use std::{future::Future, io, sync::OnceLock};
+use tokio::{runtime::{Builder, Runtime}, task::JoinHandle};
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+fn build() -> io::Result<Runtime> {
+    Builder::new_multi_thread()
+        .thread_name("sugar-worker")
+        .enable_all()
+        .build()
+}
+
+/// Creates the runtime, if it does not exist yet.
+pub fn init() -> io::Result<()> {
+    if RUNTIME.get().is_none() {
+        // If another thread was faster, this runtime is simply dropped.
+        RUNTIME.set(build()?).ok();
+    }
+
+    Ok(())
+}
+
+/// Returns the runtime, creating it on the first use if it was not initialized before.
+pub fn get() -> &'static Runtime {
+    RUNTIME.get_or_init(|| build().expect("Unable to create the async runtime."))
+}
+
+/// Runs the future to completion on the current thread.
+///
+/// Must never be called from within an asynchronous context.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    get().block_on(future)
+}
+
+/// Spawns the future on the runtime, without waiting for it.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    get().spawn(future)
+}
@@ -5,9 +5,28 @@
 //! However it also provide an interface to Java's front-end for communicating with cloud storage.
 
 use lazy_static::lazy_static;
-use std::{fs::File, mem::size_of, io::ErrorKind, path::{Path, PathBuf}, sync::RwLock};
-use serde::{de::DeserializeOwned, Serialize};
-use super::errors::StorageError; 
+use std::{
+    borrow::Cow, fs::{self, File}, io::{self, BufReader, BufWriter, Write}, mem::size_of, path::{Path, PathBuf}, sync::RwLock
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use super::errors::{ErrorKind, StorageError, SugarResult};
+
+/// Encrypted documents for secrets and credentials.
+pub mod crypto;
+/// Embedded database for sessions, listings and transfers.
+pub mod db;
+/// Size limited cache with the eviction of old items.
+pub mod cache;
+
+pub use crypto::{EncryptedStorage, FileKeyProvider, KeyProvider, PassphraseKeyProvider};
+pub use db::Database;
+pub use cache::{Cache, CacheLocation, CacheUsage};
+
+/// Extension of the file, which is being written right now.
+const TEMP_EXTENSION: &str = "json.tmp";
+/// Extension of the previous generation of the file.
+const BACKUP_EXTENSION: &str = "json.bak";
 
 // Will be set during initialization phase. 
 lazy_static! {
@@ -21,107 +40,394 @@ lazy_static! {
     pub static ref EXT_CACHE_DIR: RwLock<Box<PathBuf>> = RwLock::new(Box::new(PathBuf::new()));
 }
 
+/// Group of related documents. Each namespace is a separate directory within [`FILES_DIR`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    /// Responses and tokens of user's authentication.
+    Auth,
+    /// Known target machines.
+    Machines,
+    /// Sessions with target machines.
+    Sessions,
+    /// Data, which can be obtained once more if lost.
+    Cache,
+}
+
+impl Namespace {
+    fn dir(&self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::Machines => "machines",
+            Self::Sessions => "sessions",
+            Self::Cache => "cache",
+        }
+    }
+}
+
+/// Key of the document within the local storage.
+///
+/// Names can be built at runtime, e.g. from a machine or user id, but may only contain ASCII
+/// letters, digits, `-`, `_` and `.`, and must not start with a dot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StorageKey {
+    namespace: Namespace,
+    name: Cow<'static, str>,
+}
+
+impl StorageKey {
+    /// Creates a key from the name known at compile time.
+    pub const fn fixed(namespace: Namespace, name: &'static str) -> Self {
+        Self { namespace, name: Cow::Borrowed(name) }
+    }
+
+    /// Creates a key from the name built at runtime.
+    pub fn new(namespace: Namespace, name: impl Into<String>) -> Self {
+        Self { namespace, name: Cow::Owned(name.into()) }
+    }
+
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.name.is_empty() && !self.name.starts_with('.') && 
+            self.name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    }
+}
+
+/// Data, which can be stored in the local storage.
+///
+/// Each document is stored along with the version of its structure. Once the structure changes,
+/// the version must be increased and [`Document::migrate`] must upgrade the older data.
+pub trait Document: Serialize + DeserializeOwned {
+    /// Current version of the document's structure.
+    const VERSION: u32;
+
+    /// Upgrades the data stored with an older version of the structure to the current one.
+    ///
+    /// Files written before the versioning was introduced have version 0. By default the data is
+    /// expected to be compatible and is returned as is.
+    fn migrate(version: u32, data: Value) -> SugarResult<Value> {
+        log::debug!("No migration required from version {} to {}.", version, Self::VERSION);
+        Ok(data)
+    }
+}
+
+/// Versioned form in which every document is written.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope<D> {
+    schema: u32,
+    data: D,
+}
+
 /// Custom structure that provides a local interface with data written on phone's disk.
+///
+/// Writes are crash-safe: the data is written to a temporary file first, synced to the disk and
+/// only then renamed over the destination. The previous generation of the file is kept as a
+/// backup, which is read instead, if the destination is missing or corrupted.
 pub struct LocalStorage;
 
 impl LocalStorage {
-    /// Writes any data that can be represented as JSON to the local storage.
+    /// Writes any document to the local storage.
     ///
     /// If write fails, will return one of pre defined errors that match it's result. If everything
     /// will go accordingly, will return the amount of bytes written to file.
-    pub fn write<T>(data: &T, dest: &'static str) -> Result<usize, StorageError> where
-        T: Serialize
-    {
-        let dest = Path::new(
-            FILES_DIR.read().unwrap().as_ref()
-        ).join(dest.to_owned()).with_extension("json");
-
-        let out = {
-            let length = size_of::<T>();
-            log::info!("Writing {} bytes to local storage: {}", length, dest.to_string_lossy());
-
-            if length == 0 {
-                return Err(StorageError::NO_DATA)
-            };
-
-            match File::create(dest) {
-                Ok(buf_writer) => match serde_json::to_writer(buf_writer, data) {
-                    Ok(_) => Ok(length),
-                    Err(_) => Err(StorageError::SERIALIZATION_ERROR),
-                },
-                Err(err) => match err.kind() {
-                    ErrorKind::TimedOut => Err(StorageError::TIME_OUT),
-                    ErrorKind::InvalidData => Err(StorageError::BAD_DATA),
-                    ErrorKind::Interrupted => Err(StorageError::INTERRUPTED),
-                    ErrorKind::OutOfMemory => Err(StorageError::OUT_OF_MEMORY),
-                    _ => unreachable!(),
-                },
-            }
-        };
+    pub fn write<T: Document>(data: &T, key: &StorageKey) -> SugarResult<usize> {
+        let out = Self::length::<T>()
+            .and_then(|length| Self::store(&Envelope { schema: T::VERSION, data }, key).map(|_| length));
 
         if let Err(ref err) = out {
-            log::error!("Local storage WRITE error: {}", err);
+            log::error!("Local storage WRITE error: {}", err.report());
         }
 
         out
     }
 
-    /// Reads the data from internal storage.
+    /// Reads the document from internal storage.
     ///
     /// If write fails, will return one of pre defined errors that match it's result. If everything
-    /// will go accordingly, will return a deserialized version of the data. 
-    pub fn read<T: DeserializeOwned>(dest: &'static str) -> Result<T, StorageError> { 
-        let dest = Path::new(
-            FILES_DIR.read().unwrap().as_ref()
-        ).join(dest.to_owned()).with_extension("json");
-        
-        let out = {
-            log::info!("Reading data from local storage: {}", dest.to_string_lossy());
-            match File::open(dest) {
-                Ok(buf_reader) => {
-                    match serde_json::from_reader(buf_reader) {
-                        Ok(data) => Ok(data),
-                        Err(err) => {
-                            log::error!("Serde error: {}", err);
-                            Err(StorageError::SERIALIZATION_ERROR)
-                        },
-                    }
-                },
-                Err(err) => match err.kind() {
-                    ErrorKind::NotFound => Err(StorageError::FILE_NOT_EXIST),
-                    ErrorKind::TimedOut => Err(StorageError::TIME_OUT),
-                    ErrorKind::InvalidData => Err(StorageError::BAD_DATA),
-                    ErrorKind::Interrupted => Err(StorageError::INTERRUPTED),
-                    ErrorKind::OutOfMemory => Err(StorageError::OUT_OF_MEMORY),
-                    _ => unreachable!(),
-                },
+    /// will go accordingly, will return a deserialized version of the data. Documents of an older
+    /// version are migrated and written back.
+    pub fn read<T: Document>(key: &StorageKey) -> SugarResult<T> { 
+        let out = Self::fetch(key, Self::open::<T>).map(|(data, version)| {
+            if version < T::VERSION {
+                log::info!("Upgrading {:?} from version {} to {}.", key, version, T::VERSION);
+                // The data is already in memory, so failing to store it only means migrating again.
+                Self::write(&data, key).ok();
             }
-        };
+            data
+        });
 
         if let Err(ref err) = out {
-            log::error!("Loal storage READ error: {}", err);
+            log::error!("Local storage READ error: {}", err.report());
         }
 
         out
     }
 
-    /// Removes some file from the local storage.
+    /// Removes some document from the local storage along with its backup.
     ///
     /// Returns a storage error if unable to delete a file.
-    pub fn remove(dest: &'static str) -> Result<(), StorageError> {
-        let dest = Path::new(
-            FILES_DIR.read().unwrap().as_ref()
-        ).join(dest.to_owned()).with_extension("json");
-
-        if let Err(err) = std::fs::remove_file(dest) {
-            return match err.kind() {
-                ErrorKind::Interrupted => Err(StorageError::INTERRUPTED),
-                ErrorKind::NotFound => Err(StorageError::FILE_NOT_EXIST),
-                ErrorKind::TimedOut => Err(StorageError::TIME_OUT),
-                _ => unreachable!(),
+    pub fn remove(key: &StorageKey) -> SugarResult<()> {
+        let out = Self::path(key).and_then(|dest| {
+            // Otherwise the backup or the legacy file would be read instead of the removed one.
+            let stale = [
+                dest.with_extension(BACKUP_EXTENSION), 
+                dest.with_extension(TEMP_EXTENSION),
+                Self::legacy_path(key)?,
+            ];
+
+            let mut removed = false;
+            for path in stale {
+                match fs::remove_file(path) {
+                    Ok(_) => removed = true,
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                    _ => (),
+                }
             }
+
+            match fs::remove_file(dest) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound && removed => Ok(()),
+                out => Ok(out?),
+            }
+        });
+
+        if let Err(ref err) = out {
+            log::error!("Local storage REMOVE error: {}", err.report());
+        }
+
+        out
+    }
+
+    /// Atomically replaces the file of the document with the serialized value.
+    ///
+    /// The current generation of the file becomes the backup.
+    fn store<S: Serialize>(value: &S, key: &StorageKey) -> SugarResult<()> {
+        let dest = Self::path(key)?;
+        log::info!("Writing data to local storage: {}", dest.to_string_lossy());
+
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let temp = dest.with_extension(TEMP_EXTENSION);
+        let mut buf_writer = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer(&mut buf_writer, value)?;
+        buf_writer.flush()?;
+        buf_writer.get_ref().sync_all()?;
+
+        // The current generation becomes the backup. If the process dies right after that,
+        // reads will simply use the backup.
+        match fs::rename(&dest, dest.with_extension(BACKUP_EXTENSION)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
         }
+        fs::rename(&temp, &dest)?;
+        Self::sync_dir(&dest);
 
         Ok(())
     }
+
+    /// Reads the raw value of the document and decodes it.
+    ///
+    /// If the file is missing or cannot be decoded, the backup is decoded instead.
+    fn fetch<R>(key: &StorageKey, decode: impl Fn(Value) -> SugarResult<R>) -> SugarResult<R> {
+        let dest = Self::path(key)?;
+        log::info!("Reading data from local storage: {}", dest.to_string_lossy());
+        Self::adopt_legacy(key, &dest);
+
+        let load = |path: &Path| -> SugarResult<R> {
+            decode(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+        };
+
+        match load(&dest) {
+            Err(err) if matches!(
+                err.kind(), 
+                ErrorKind::Storage(StorageError::FILE_NOT_EXIST | StorageError::SERIALIZATION_ERROR | StorageError::NO_DATA)
+            ) => {
+                // Either the last write was interrupted, or the file is corrupted.
+                match load(&dest.with_extension(BACKUP_EXTENSION)) {
+                    Ok(out) => {
+                        log::warn!("Unable to read {}: {}. Using the backup.", dest.to_string_lossy(), err.report());
+                        Ok(out)
+                    },
+                    Err(_) => Err(err),
+                }
+            },
+            out => out,
+        }
+    }
+
+    /// Brings the stored value to the current version of the document.
+    ///
+    /// Returns the document along with the version it was stored with.
+    fn open<T: Document>(value: Value) -> SugarResult<(T, u32)> {
+        // Files without the envelope were written before the versioning.
+        let (version, data) = match serde_json::from_value::<Envelope<Value>>(value.clone()) {
+            Ok(envelope) => (envelope.schema, envelope.data),
+            Err(_) => (0, value),
+        };
+
+        let data = match version {
+            v if v == T::VERSION => data,
+            v if v < T::VERSION => T::migrate(v, data)?,
+            v => {
+                log::error!("Document has version {}, while only {} is supported.", v, T::VERSION);
+                return Err(StorageError::UNSUPPORTED_SCHEMA.into())
+            },
+        };
+
+        Ok((serde_json::from_value(data)?, version))
+    }
+
+    /// Returns the size of the document, which is reported by writes.
+    ///
+    /// Documents without any data cannot be written.
+    fn length<T>() -> SugarResult<usize> {
+        match size_of::<T>() {
+            0 => Err(StorageError::NO_DATA.into()),
+            length => Ok(length),
+        }
+    }
+
+    /// Removes the previous generation of the document.
+    ///
+    /// Used once the previous generation must not be readable anymore.
+    fn discard_backup(key: &StorageKey) -> SugarResult<()> {
+        match fs::remove_file(Self::path(key)?.with_extension(BACKUP_EXTENSION)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves the file written before the namespaces were introduced to its namespace.
+    ///
+    /// Such files were stored right in the files directory.
+    fn adopt_legacy(key: &StorageKey, dest: &Path) {
+        let Ok(legacy) = Self::legacy_path(key) else { return };
+
+        if legacy.exists() && !dest.exists() {
+            log::info!("Moving {} to {}.", legacy.to_string_lossy(), dest.to_string_lossy());
+
+            let moved = dest.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::rename(&legacy, dest));
+            if let Err(err) = moved {
+                log::warn!("Unable to move {}: {}", legacy.to_string_lossy(), err);
+            }
+        }
+    }
+
+    /// Syncs the directory of the file, so that the rename itself survives a power loss.
+    ///
+    /// Not every platform allows to open a directory, so failures are only logged.
+    fn sync_dir(path: &Path) {
+        if let Some(dir) = path.parent() {
+            if let Err(err) = File::open(dir).and_then(|dir| dir.sync_all()) {
+                log::debug!("Unable to sync the directory {}: {}", dir.to_string_lossy(), err);
+            }
+        }
+    }
+
+    /// Returns the path of the document within the files directory.
+    ///
+    /// Fails if the directory is not provided by the front-end yet, since a relative path would
+    /// point to some random place.
+    fn path(key: &StorageKey) -> SugarResult<PathBuf> {
+        Ok(Self::files_dir(key)?.join(key.namespace.dir()).join(Self::file_name(key)))
+    }
+
+    /// Returns the path, where the document was stored before the namespaces were introduced.
+    fn legacy_path(key: &StorageKey) -> SugarResult<PathBuf> {
+        Ok(Self::files_dir(key)?.join(Self::file_name(key)))
+    }
+
+    /// Names may contain dots, so the extension is appended instead of replacing the last one.
+    fn file_name(key: &StorageKey) -> String {
+        format!("{}.json", key.name())
+    }
+
+    fn files_dir(key: &StorageKey) -> SugarResult<PathBuf> {
+        if !key.is_valid() {
+            log::error!("Invalid storage key: {:?}", key);
+            return Err(StorageError::INVALID_KEY.into())
+        }
+
+        let dir = FILES_DIR.read().unwrap_or_else(|err| err.into_inner());
+
+        if dir.as_os_str().is_empty() {
+            return Err(StorageError::NOT_INITIALIZED.into())
+        }
+
+        Ok(dir.to_path_buf())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Once;
+    use super::*;
+
+    /// Points the files directory to a temporary one, which is shared by all tests.
+    pub(crate) fn files_dir() -> PathBuf {
+        static INIT: Once = Once::new();
+        let dir = std::env::temp_dir().join(format!("sugar-storage-{}", std::process::id()));
+
+        INIT.call_once(|| {
+            fs::remove_dir_all(&dir).ok();
+            fs::create_dir_all(&dir).unwrap();
+            FILES_DIR.write().unwrap_or_else(|e| e.into_inner()).push(&dir);
+        });
+        dir
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        text: String,
+    }
+
+    impl Document for Note {
+        const VERSION: u32 = 1;
+    }
+
+    #[test]
+    fn dotted_names_round_trip() {
+        let dir = files_dir();
+        let keys = ["user", "user.1", "a.b", "a.c"].map(|name| StorageKey::new(Namespace::Machines, name));
+
+        for key in &keys {
+            LocalStorage::write(&Note { text: key.name().to_string() }, key).unwrap();
+        }
+        for key in &keys {
+            assert_eq!(LocalStorage::read::<Note>(key).unwrap(), Note { text: key.name().to_string() });
+        }
+        assert!(dir.join("machines/user.1.json").exists());
+
+        LocalStorage::remove(&keys[2]).unwrap();
+        assert!(LocalStorage::read::<Note>(&keys[2]).is_err());
+        assert_eq!(LocalStorage::read::<Note>(&keys[3]).unwrap().text, "a.c");
+    }
+
+    #[test]
+    fn truncated_file_falls_back_to_the_backup() {
+        let dir = files_dir();
+        let key = StorageKey::new(Namespace::Machines, "backup");
+        LocalStorage::write(&Note { text: "first".into() }, &key).unwrap();
+        LocalStorage::write(&Note { text: "second".into() }, &key).unwrap();
+
+        // The last write was torn, e.g. by a power loss.
+        let primary = dir.join("machines/backup.json");
+        File::options().write(true).open(&primary).unwrap().set_len(8).unwrap();
+        assert_eq!(LocalStorage::read::<Note>(&key).unwrap(), Note { text: "first".into() });
+
+        LocalStorage::remove(&key).unwrap();
+        assert!(!primary.exists());
+        assert!(!dir.join("machines/backup.json.bak").exists());
+        assert!(LocalStorage::read::<Note>(&key).is_err());
+    }
 }
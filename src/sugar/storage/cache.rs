@@ -0,0 +1,458 @@
+//! Size limited cache within [`CACHE_DIR`] and [`EXT_CACHE_DIR`].
+//!
+//! Every file within both cache directories is an item of the cache, identified by its location
+//! and the path relative to the directory. Once the items take more space than the budget, the
+//! least recently used ones are removed. Pinned items, e.g. bookmarked ones, are never evicted.
+
+use std::{
+    collections::BTreeSet, ffi::OsStr, fs::{self, File}, io::{self, Write}, path::{Component, Path, PathBuf}, sync::Mutex, time::SystemTime
+};
+use serde::{Deserialize, Serialize};
+use crate::sugar::errors::{ErrorKind, StorageError, SugarResult};
+use super::{Document, LocalStorage, Namespace, StorageKey, CACHE_DIR, EXT_CACHE_DIR};
+
+/// Budget used until the front-end configures another one.
+pub const DEFAULT_BUDGET: u64 = 512 * 1024 * 1024;
+/// Extension of the item, which is being written right now.
+const TEMP_EXTENSION: &str = "part";
+
+const SETTINGS: StorageKey = StorageKey::fixed(Namespace::Cache, "cache_settings");
+
+/// Loaded settings. Also serializes all operations on the cache.
+static STATE: Mutex<Option<CacheSettings>> = Mutex::new(None);
+
+/// Cache directory, which holds the item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheLocation {
+    /// Private cache directory of the application.
+    Internal,
+    /// Cache directory on the external storage.
+    External,
+}
+
+impl CacheLocation {
+    const ALL: [Self; 2] = [Self::Internal, Self::External];
+
+    fn dir(&self) -> SugarResult<PathBuf> {
+        let dir = match self {
+            Self::Internal => CACHE_DIR.read(),
+            Self::External => EXT_CACHE_DIR.read(),
+        }.unwrap_or_else(|err| err.into_inner()).to_path_buf();
+
+        if dir.as_os_str().is_empty() {
+            return Err(StorageError::NOT_INITIALIZED.into())
+        }
+
+        Ok(dir)
+    }
+
+    /// Prefix of the item within the list of pinned items.
+    fn prefix(&self) -> &'static str {
+        match self {
+            Self::Internal => "internal",
+            Self::External => "external",
+        }
+    }
+}
+
+/// Space taken by the cache, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheUsage {
+    pub internal: u64,
+    pub external: u64,
+    /// Part of the usage taken by pinned items.
+    pub pinned: u64,
+    /// Amount of items within both directories.
+    pub items: usize,
+    pub budget: u64,
+}
+
+impl CacheUsage {
+    pub fn total(&self) -> u64 {
+        self.internal + self.external
+    }
+}
+
+/// Settings of the cache, which are kept between the runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheSettings {
+    budget: u64,
+    /// Pinned items as `location/relative/path`.
+    pinned: BTreeSet<String>,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self { budget: DEFAULT_BUDGET, pinned: BTreeSet::new() }
+    }
+}
+
+impl Document for CacheSettings {
+    const VERSION: u32 = 1;
+}
+
+/// File within one of the cache directories.
+struct Item {
+    id: String,
+    path: PathBuf,
+    size: u64,
+    used: SystemTime,
+}
+
+/// Manager of both cache directories.
+pub struct Cache;
+
+impl Cache {
+    /// Returns the path of the item. The item itself is not necessarily there.
+    ///
+    /// The name is a relative path within the cache directory. Names of unfinished items, which
+    /// end with `.part`, are not allowed.
+    pub fn path(location: CacheLocation, name: &str) -> SugarResult<PathBuf> {
+        let relative = Path::new(name);
+        let valid = !name.is_empty()
+            && relative.components().all(|c| matches!(c, Component::Normal(_)))
+            && relative.extension() != Some(OsStr::new(TEMP_EXTENSION));
+
+        if !valid {
+            log::error!("Invalid cache item: {}", name);
+            return Err(StorageError::INVALID_KEY.into())
+        }
+
+        Ok(location.dir()?.join(relative))
+    }
+
+    /// Writes the item to the cache and evicts old items if the budget is exceeded.
+    ///
+    /// The written item itself is never evicted here, even if it is larger than the budget.
+    pub fn put(location: CacheLocation, name: &str, data: &[u8]) -> SugarResult<PathBuf> {
+        let path = Self::path(location, name)?;
+
+        Self::locked(|settings| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            let mut temp = path.clone().into_os_string();
+            temp.push(".");
+            temp.push(TEMP_EXTENSION);
+            File::create(&temp)?.write_all(data)?;
+            fs::rename(&temp, &path)?;
+
+            Self::evict_locked(settings, Some(&Self::id(location, Path::new(name))))
+        })?;
+
+        Ok(path)
+    }
+
+    /// Returns the path of the cached item and marks it as recently used.
+    ///
+    /// Fails with [`StorageError::FILE_NOT_EXIST`] if the item is not cached.
+    pub fn get(location: CacheLocation, name: &str) -> SugarResult<PathBuf> {
+        let path = Self::path(location, name)?;
+        File::options().write(true).open(&path)?.set_modified(SystemTime::now())?;
+        Ok(path)
+    }
+
+    /// Protects the item from the eviction. The item does not have to be cached yet.
+    pub fn pin(location: CacheLocation, name: &str) -> SugarResult<()> {
+        Self::path(location, name)?;
+        Self::locked(|settings| match settings.pinned.insert(Self::id(location, Path::new(name))) {
+            true => Self::save(settings),
+            false => Ok(()),
+        })
+    }
+
+    /// Allows the item to be evicted again.
+    pub fn unpin(location: CacheLocation, name: &str) -> SugarResult<()> {
+        Self::locked(|settings| match settings.pinned.remove(&Self::id(location, Path::new(name))) {
+            true => Self::save(settings),
+            false => Ok(()),
+        })
+    }
+
+    /// Changes the budget and evicts items, which do not fit anymore.
+    ///
+    /// Returns the amount of freed bytes.
+    pub fn set_budget(budget: u64) -> SugarResult<u64> {
+        Self::locked(|settings| {
+            settings.budget = budget;
+            Self::save(settings)?;
+            Self::evict_locked(settings, None)
+        })
+    }
+
+    /// Returns the space taken by both cache directories.
+    pub fn usage() -> SugarResult<CacheUsage> {
+        Self::locked(|settings| {
+            let mut usage = CacheUsage { budget: settings.budget, ..Default::default() };
+
+            for location in CacheLocation::ALL {
+                for item in Self::scan(location)? {
+                    match location {
+                        CacheLocation::Internal => usage.internal += item.size,
+                        CacheLocation::External => usage.external += item.size,
+                    }
+                    if settings.pinned.contains(&item.id) {
+                        usage.pinned += item.size;
+                    }
+                    usage.items += 1;
+                }
+            }
+
+            Ok(usage)
+        })
+    }
+
+    /// Evicts the least recently used items until the cache fits the budget.
+    ///
+    /// Returns the amount of freed bytes.
+    pub fn evict() -> SugarResult<u64> {
+        Self::locked(|settings| Self::evict_locked(settings, None))
+    }
+
+    /// Removes all items from both cache directories.
+    ///
+    /// Returns the amount of freed bytes.
+    pub fn clear(keep_pinned: bool) -> SugarResult<u64> {
+        Self::locked(|settings| {
+            let mut freed = 0;
+
+            for location in CacheLocation::ALL {
+                for item in Self::scan(location)? {
+                    if !(keep_pinned && settings.pinned.contains(&item.id)) {
+                        freed += Self::remove(location, &item)?;
+                    }
+                }
+            }
+
+            if !keep_pinned && !settings.pinned.is_empty() {
+                settings.pinned.clear();
+                Self::save(settings)?;
+            }
+
+            log::info!("Cache is cleared, {} bytes freed.", freed);
+            Ok(freed)
+        })
+    }
+
+    fn evict_locked(settings: &CacheSettings, keep: Option<&str>) -> SugarResult<u64> {
+        let mut items = Vec::new();
+        for location in CacheLocation::ALL {
+            items.extend(Self::scan(location)?.into_iter().map(|item| (location, item)));
+        }
+
+        let mut total: u64 = items.iter().map(|(_, item)| item.size).sum();
+        if total <= settings.budget {
+            return Ok(0)
+        }
+
+        items.retain(|(_, item)| !settings.pinned.contains(&item.id) && Some(item.id.as_str()) != keep);
+        items.sort_by_key(|(_, item)| item.used);
+
+        let mut freed = 0;
+        for (location, item) in items {
+            if total <= settings.budget {
+                break
+            }
+
+            let size = Self::remove(location, &item)?;
+            total -= size;
+            freed += size;
+        }
+
+        if total > settings.budget {
+            log::warn!("Cache takes {} bytes even after the eviction, while the budget is {}.", total, settings.budget);
+        }
+
+        log::info!("Evicted {} bytes from the cache.", freed);
+        Ok(freed)
+    }
+
+    /// Lists all items of the location. A missing directory has no items.
+    ///
+    /// Leftovers of interrupted writes are removed, since no write runs while the cache is locked.
+    fn scan(location: CacheLocation) -> SugarResult<Vec<Item>> {
+        let root = location.dir()?;
+        let mut items = Vec::new();
+        let mut dirs = vec![root.clone()];
+
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                entries => entries?,
+            };
+
+            for entry in entries {
+                let entry = entry?;
+                let meta = entry.metadata()?;
+
+                if meta.is_dir() {
+                    dirs.push(entry.path());
+                } else if meta.is_file() {
+                    let path = entry.path();
+
+                    if path.extension().is_some_and(|ext| ext == TEMP_EXTENSION) {
+                        log::info!("Removing unfinished cache item: {}", path.to_string_lossy());
+                        fs::remove_file(&path).ok();
+                        continue
+                    }
+
+                    items.push(Item {
+                        id: Self::id(location, path.strip_prefix(&root).unwrap_or(&path)),
+                        size: meta.len(),
+                        used: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                        path,
+                    });
+                }
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Removes the item along with the directories it leaves empty. Returns the freed bytes.
+    fn remove(location: CacheLocation, item: &Item) -> SugarResult<u64> {
+        match fs::remove_file(&item.path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            out => out?,
+        }
+
+        let root = location.dir()?;
+        let mut dir = item.path.parent();
+        while let Some(path) = dir.filter(|path| *path != root && path.starts_with(&root)) {
+            if fs::remove_dir(path).is_err() {
+                break
+            }
+            dir = path.parent();
+        }
+
+        Ok(item.size)
+    }
+
+    /// Runs the function with the settings, while no other operation on the cache is running.
+    ///
+    /// Settings are loaded on the first use.
+    fn locked<R>(f: impl FnOnce(&mut CacheSettings) -> SugarResult<R>) -> SugarResult<R> {
+        let mut state = STATE.lock().unwrap_or_else(|err| err.into_inner());
+
+        let settings = match state.as_mut() {
+            Some(settings) => settings,
+            None => {
+                let settings = match LocalStorage::read::<CacheSettings>(&SETTINGS) {
+                    Err(err) if err.kind() == ErrorKind::Storage(StorageError::FILE_NOT_EXIST) => CacheSettings::default(),
+                    out => out?,
+                };
+                state.insert(settings)
+            },
+        };
+
+        f(settings)
+    }
+
+    fn save(settings: &CacheSettings) -> SugarResult<()> {
+        LocalStorage::write(settings, &SETTINGS).map(|_| ())
+    }
+
+    /// Builds the id from the components of the relative path, so that the same item always has
+    /// the same id, regardless of separators used in its name.
+    fn id(location: CacheLocation, relative: &Path) -> String {
+        let parts: Vec<_> = relative.components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+
+        format!("{}/{}", location.prefix(), parts.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Mutex, MutexGuard, Once}, time::Duration};
+    use super::*;
+    use crate::sugar::storage::tests::files_dir;
+
+    /// All tests share both cache directories.
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// Empties the cache, which is placed within the shared files directory.
+    fn empty_cache() -> MutexGuard<'static, ()> {
+        static INIT: Once = Once::new();
+        let serial = SERIAL.lock().unwrap_or_else(|err| err.into_inner());
+
+        INIT.call_once(|| {
+            let dir = files_dir();
+            CACHE_DIR.write().unwrap().push(dir.join("cache-internal"));
+            EXT_CACHE_DIR.write().unwrap().push(dir.join("cache-external"));
+        });
+        Cache::clear(false).unwrap();
+        Cache::set_budget(DEFAULT_BUDGET).unwrap();
+        serial
+    }
+
+    /// Marks the item as used the provided amount of seconds ago.
+    fn used_ago(location: CacheLocation, name: &str, secs: u64) {
+        let path = Cache::path(location, name).unwrap();
+        File::options().write(true).open(path).unwrap().set_modified(SystemTime::now() - Duration::from_secs(secs)).unwrap();
+    }
+
+    fn cached(location: CacheLocation, name: &str) -> bool {
+        Cache::path(location, name).unwrap().exists()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let _serial = empty_cache();
+
+        Cache::put(CacheLocation::Internal, "dir/a", &[0; 100]).unwrap();
+        Cache::put(CacheLocation::Internal, "b", &[0; 100]).unwrap();
+        Cache::put(CacheLocation::External, "c", &[0; 100]).unwrap();
+        used_ago(CacheLocation::Internal, "dir/a", 300);
+        used_ago(CacheLocation::Internal, "b", 200);
+        used_ago(CacheLocation::External, "c", 100);
+        Cache::get(CacheLocation::Internal, "dir/a").unwrap();
+
+        assert_eq!(Cache::set_budget(250).unwrap(), 100);
+        assert!(!cached(CacheLocation::Internal, "b"));
+        assert_eq!(Cache::set_budget(150).unwrap(), 100);
+        assert!(!cached(CacheLocation::External, "c"));
+        assert!(cached(CacheLocation::Internal, "dir/a"));
+    }
+
+    #[test]
+    fn pinned_items_are_kept() {
+        let _serial = empty_cache();
+
+        Cache::put(CacheLocation::Internal, "dir/pinned", &[0; 100]).unwrap();
+        Cache::put(CacheLocation::Internal, "other", &[0; 100]).unwrap();
+        used_ago(CacheLocation::Internal, "dir/pinned", 100);
+        // Pinned by another spelling of the same path.
+        Cache::pin(CacheLocation::Internal, "dir//pinned").unwrap();
+
+        assert_eq!(Cache::set_budget(100).unwrap(), 100);
+        assert!(cached(CacheLocation::Internal, "dir/pinned"));
+        assert!(!cached(CacheLocation::Internal, "other"));
+        assert_eq!(Cache::usage().unwrap().pinned, 100);
+
+        Cache::put(CacheLocation::External, "loose", &[0; 10]).unwrap();
+        assert_eq!(Cache::clear(true).unwrap(), 10);
+        assert!(cached(CacheLocation::Internal, "dir/pinned"));
+        assert_eq!(Cache::clear(false).unwrap(), 100);
+        assert_eq!(Cache::usage().unwrap(), CacheUsage { budget: 100, ..Default::default() });
+    }
+
+    #[test]
+    fn unfinished_items_are_removed() {
+        let _serial = empty_cache();
+
+        let leftover = CacheLocation::Internal.dir().unwrap().join("item.part");
+        fs::create_dir_all(leftover.parent().unwrap()).unwrap();
+        fs::write(&leftover, [0; 100]).unwrap();
+        Cache::put(CacheLocation::Internal, "item", &[0; 10]).unwrap();
+
+        assert_eq!(Cache::usage().unwrap().internal, 10);
+        assert!(!leftover.exists());
+        assert!(Cache::path(CacheLocation::Internal, "item.part").is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! Encrypted documents within the local storage.
+//!
+//! Documents are sealed with XChaCha20-Poly1305, so that both reading and tampering with them
+//! require the key. The key itself is obtained from the [`KeyProvider`], which allows to keep it
+//! somewhere else than the documents, e.g. in the platform keystore.
+
+use lazy_static::lazy_static;
+use std::{
+    fs::{self, File}, io::{self, Read, Write}, mem::size_of, path::{Path, PathBuf}, sync::{Arc, Mutex, RwLock}
+};
+use argon2::Argon2;
+use rand::RngCore;
+use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::sugar::{errors::{ErrorKind, StorageError, SugarError, SugarResult}, logging::Secret};
+use super::{Document, Envelope, LocalStorage, StorageKey, FILES_DIR};
+
+/// Name of the cipher written along with every sealed document.
+const CIPHER: &str = "xchacha20poly1305";
+/// Length of the encryption key in bytes.
+pub const KEY_LEN: usize = 32;
+/// Length of the salt used for the key derivation.
+const SALT_LEN: usize = 16;
+/// Marker within the files directory, which is created once plaintext documents are encrypted.
+const MIGRATED_MARKER: &str = "keys/plaintext.migrated";
+
+lazy_static! {
+    /// Provider used by the encrypted storage. Defaults to [`FileKeyProvider`] within the files
+    /// directory.
+    static ref KEY_PROVIDER: RwLock<Option<Arc<dyn KeyProvider>>> = RwLock::new(None);
+}
+
+/// Source of the key for the encrypted documents.
+///
+/// The key must stay the same between the runs of the application, otherwise previously stored
+/// documents cannot be decrypted anymore.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the key, creating it on the first use.
+    fn key(&self) -> SugarResult<Key>;
+}
+
+/// Provides a random key stored in the file.
+///
+/// The file lives within the private directory of the application, so this only keeps secrets
+/// out of the documents themselves. Used until the platform keystore is supported.
+pub struct FileKeyProvider {
+    path: PathBuf,
+    key: Mutex<Option<Key>>,
+}
+
+impl FileKeyProvider {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), key: Mutex::new(None) }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn key(&self) -> SugarResult<Key> {
+        let mut cached = self.key.lock().unwrap_or_else(|err| err.into_inner());
+
+        if let Some(key) = *cached {
+            return Ok(key)
+        }
+
+        let key = Key::from(load_or_create::<KEY_LEN>(&self.path)?);
+        *cached = Some(key);
+        Ok(key)
+    }
+}
+
+/// Derives the key from the passphrase with Argon2.
+///
+/// The random salt is stored in the file, so the same passphrase gives the same key on every run.
+pub struct PassphraseKeyProvider {
+    passphrase: Secret<String>,
+    salt: PathBuf,
+    key: Mutex<Option<Key>>,
+}
+
+impl PassphraseKeyProvider {
+    pub fn new(passphrase: impl Into<String>, salt: impl Into<PathBuf>) -> Self {
+        Self { passphrase: Secret::new(passphrase.into()), salt: salt.into(), key: Mutex::new(None) }
+    }
+}
+
+impl KeyProvider for PassphraseKeyProvider {
+    fn key(&self) -> SugarResult<Key> {
+        let mut cached = self.key.lock().unwrap_or_else(|err| err.into_inner());
+
+        if let Some(key) = *cached {
+            return Ok(key)
+        }
+
+        let salt = load_or_create::<SALT_LEN>(&self.salt)?;
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(self.passphrase.expose().as_bytes(), &salt, &mut key)
+            .map_err(|err| {
+                log::error!("Unable to derive the key from the passphrase: {}", err);
+                SugarError::from(StorageError::KEY_UNAVAILABLE)
+            })?;
+
+        *cached = Some(key);
+        Ok(key)
+    }
+}
+
+/// Sealed form in which every encrypted document is written.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Sealed {
+    cipher: String,
+    nonce: String,
+    data: String,
+}
+
+/// Local storage for the documents, which must not be stored in plaintext.
+///
+/// Shares the file layout and crash safety of [`LocalStorage`]. Documents, which were written
+/// in plaintext before, are encrypted by [`EncryptedStorage::seal_plaintext`] until the migration
+/// is finished. Plaintext documents are refused otherwise, so that a sealed document cannot be
+/// replaced by a forged one.
+pub struct EncryptedStorage;
+
+impl EncryptedStorage {
+    /// Replaces the provider of the encryption key.
+    ///
+    /// Must be called before any encrypted document is accessed, since documents sealed with
+    /// another key cannot be read anymore.
+    pub fn set_key_provider(provider: impl KeyProvider + 'static) {
+        *KEY_PROVIDER.write().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(provider));
+    }
+
+    /// Encrypts and writes the document to the local storage.
+    ///
+    /// Returns the same errors as [`LocalStorage::write`], along with the errors of the key
+    /// provider.
+    pub fn write<T: Document>(data: &T, key: &StorageKey) -> SugarResult<usize> {
+        let out = LocalStorage::length::<T>().and_then(|length| {
+            serde_json::to_vec(&Envelope { schema: T::VERSION, data })
+                .map_err(SugarError::from)
+                .and_then(|plaintext| Self::seal(&plaintext, key))
+                .and_then(|sealed| LocalStorage::store(&sealed, key))
+                .map(|_| length)
+        });
+
+        if let Err(ref err) = out {
+            log::error!("Encrypted storage WRITE error: {}", err.report());
+        }
+
+        out
+    }
+
+    /// Reads and decrypts the document from the local storage.
+    ///
+    /// Plaintext documents are refused with [`StorageError::DECRYPTION_FAILED`].
+    pub fn read<T: Document>(key: &StorageKey) -> SugarResult<T> {
+        let out = Self::load(key, false);
+
+        if let Err(ref err) = out {
+            log::error!("Encrypted storage READ error: {}", err.report());
+        }
+
+        out
+    }
+
+    /// Encrypts the document, if it is still stored in plaintext, while its plaintext backup is
+    /// removed.
+    ///
+    /// Does nothing once the migration is finished. Missing documents are not an error.
+    pub fn seal_plaintext<T: Document>(key: &StorageKey) -> SugarResult<()> {
+        if Self::migrated_marker()?.exists() {
+            return Ok(())
+        }
+
+        match Self::load::<T>(key, true) {
+            Err(err) if err.kind() == ErrorKind::Storage(StorageError::FILE_NOT_EXIST) => Ok(()),
+            out => out.map(|_| ()),
+        }
+    }
+
+    /// Finishes the migration of plaintext documents, which are refused from now on.
+    ///
+    /// Must be called once all documents are passed to [`EncryptedStorage::seal_plaintext`].
+    pub fn finish_migration() -> SugarResult<()> {
+        let marker = Self::migrated_marker()?;
+        if marker.exists() {
+            return Ok(())
+        }
+
+        log::info!("Plaintext documents are encrypted, refusing them from now on.");
+        create(&marker, &[])
+    }
+
+    /// Removes the encrypted document from the local storage along with its backup.
+    pub fn remove(key: &StorageKey) -> SugarResult<()> {
+        LocalStorage::remove(key)
+    }
+
+    fn load<T: Document>(key: &StorageKey, plaintext: bool) -> SugarResult<T> {
+        LocalStorage::fetch(key, |value| {
+            match serde_json::from_value::<Sealed>(value.clone()) {
+                Ok(sealed) => LocalStorage::open::<T>(Self::unseal(&sealed, key)?).map(|(data, version)| (data, version, true)),
+                Err(_) if plaintext => LocalStorage::open::<T>(value).map(|(data, version)| (data, version, false)),
+                Err(_) => {
+                    log::error!("Document {:?} is not encrypted.", key);
+                    Err(StorageError::DECRYPTION_FAILED.into())
+                },
+            }
+        }).map(|(data, version, sealed)| {
+            if !sealed {
+                log::info!("Encrypting plaintext document {:?}.", key);
+                if Self::write(&data, key).is_ok() {
+                    LocalStorage::discard_backup(key).ok();
+                }
+            } else if version < T::VERSION {
+                log::info!("Upgrading {:?} from version {} to {}.", key, version, T::VERSION);
+                Self::write(&data, key).ok();
+            }
+            data
+        })
+    }
+
+    fn seal(plaintext: &[u8], key: &StorageKey) -> SugarResult<Sealed> {
+        let nonce = XNonce::from(rand::random::<[u8; 24]>());
+        let aad = Self::associated_data(key);
+
+        let data = Self::cipher()?
+            .encrypt(&nonce, Payload { msg: plaintext, aad: aad.as_bytes() })
+            .map_err(|_| SugarError::from(StorageError::BAD_DATA))?;
+
+        Ok(Sealed { cipher: CIPHER.into(), nonce: to_hex(&nonce), data: to_hex(&data) })
+    }
+
+    fn unseal(sealed: &Sealed, key: &StorageKey) -> SugarResult<Value> {
+        if sealed.cipher != CIPHER {
+            log::error!("Document {:?} is sealed with unknown cipher: {}", key, sealed.cipher);
+            return Err(StorageError::UNSUPPORTED_SCHEMA.into())
+        }
+
+        let nonce = from_hex(&sealed.nonce)
+            .filter(|nonce| nonce.len() == size_of::<XNonce>())
+            .ok_or(StorageError::BAD_DATA)?;
+        let data = from_hex(&sealed.data).ok_or(StorageError::BAD_DATA)?;
+        let aad = Self::associated_data(key);
+
+        let plaintext = Self::cipher()?
+            .decrypt(XNonce::from_slice(&nonce), Payload { msg: &data, aad: aad.as_bytes() })
+            .map_err(|_| SugarError::from(StorageError::DECRYPTION_FAILED))?;
+
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    fn migrated_marker() -> SugarResult<PathBuf> {
+        Ok(files_dir()?.join(MIGRATED_MARKER))
+    }
+
+    /// Binds the ciphertext to its key, so that sealed documents cannot be swapped.
+    fn associated_data(key: &StorageKey) -> String {
+        format!("{}/{}", key.namespace.dir(), key.name())
+    }
+
+    fn cipher() -> SugarResult<XChaCha20Poly1305> {
+        let provider = KEY_PROVIDER.read().unwrap_or_else(|err| err.into_inner()).clone();
+
+        let provider = match provider {
+            Some(provider) => provider,
+            None => {
+                let provider: Arc<dyn KeyProvider> = Arc::new(FileKeyProvider::new(files_dir()?.join("keys").join("storage.key")));
+                KEY_PROVIDER.write().unwrap_or_else(|err| err.into_inner()).get_or_insert(provider).clone()
+            },
+        };
+
+        Ok(XChaCha20Poly1305::new(&provider.key()?))
+    }
+}
+
+/// Returns the files directory, which must be provided by the front-end first.
+fn files_dir() -> SugarResult<PathBuf> {
+    let dir = FILES_DIR.read().unwrap_or_else(|err| err.into_inner()).to_path_buf();
+    if dir.as_os_str().is_empty() {
+        return Err(StorageError::NOT_INITIALIZED.into())
+    }
+
+    Ok(dir)
+}
+
+/// Reads the secret of the fixed length from the file, or creates a random one.
+///
+/// The secret is written to a temporary file and linked into place, so that concurrent callers
+/// never observe a partially written one.
+fn load_or_create<const N: usize>(path: &Path) -> SugarResult<[u8; N]> {
+    let read = |path: &Path| -> SugarResult<[u8; N]> {
+        let mut secret = [0; N];
+        let mut file = File::open(path)?;
+        file.read_exact(&mut secret)?;
+
+        match file.read(&mut [0])? {
+            0 => Ok(secret),
+            _ => Err(StorageError::BAD_DATA.into()),
+        }
+    };
+
+    let out = match read(path) {
+        Err(err) if err.kind() == ErrorKind::Storage(StorageError::FILE_NOT_EXIST) => {
+            log::info!("Creating a new secret: {}", path.to_string_lossy());
+            let mut secret = [0; N];
+            rand::thread_rng().fill_bytes(&mut secret);
+            create(path, &secret).and_then(|_| read(path))
+        },
+        out => out,
+    };
+
+    out.map_err(|err| {
+        log::error!("Unable to obtain the secret {}: {}", path.to_string_lossy(), err.report());
+        SugarError::from(StorageError::KEY_UNAVAILABLE).with_source(err)
+    })
+}
+
+fn create(path: &Path, secret: &[u8]) -> SugarResult<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let temp = path.with_extension(format!("{}.tmp", rand::random::<u32>()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let out = options.open(&temp)
+        .and_then(|mut file| file.write_all(secret).and_then(|_| file.sync_all()))
+        .and_then(|_| match fs::hard_link(&temp, path) {
+            // Somebody else was faster, so their secret is used.
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+            out => out,
+        });
+    fs::remove_file(&temp).ok();
+
+    Ok(out?)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None
+    }
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => u8::from_str_radix(&format!("{}{}", *hi as char, *lo as char), 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sugar::storage::{tests::files_dir, Namespace};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Token {
+        token: String,
+    }
+
+    impl Document for Token {
+        const VERSION: u32 = 1;
+    }
+
+    #[test]
+    fn plaintext_is_only_sealed_once() {
+        let dir = files_dir();
+        let legacy = StorageKey::fixed(Namespace::Auth, "legacy_token");
+        let forged = StorageKey::fixed(Namespace::Auth, "forged_token");
+
+        LocalStorage::write(&Token { token: "old".into() }, &legacy).unwrap();
+        assert!(EncryptedStorage::read::<Token>(&legacy).unwrap_err().kind() == ErrorKind::Storage(StorageError::DECRYPTION_FAILED));
+
+        EncryptedStorage::seal_plaintext::<Token>(&legacy).unwrap();
+        assert!(!fs::read_to_string(dir.join("auth/legacy_token.json")).unwrap().contains("old"));
+        assert_eq!(EncryptedStorage::read::<Token>(&legacy).unwrap().token, "old");
+
+        // Once migrated, plaintext documents are never accepted.
+        EncryptedStorage::finish_migration().unwrap();
+        LocalStorage::write(&Token { token: "forged".into() }, &forged).unwrap();
+        EncryptedStorage::seal_plaintext::<Token>(&forged).unwrap();
+        assert!(EncryptedStorage::read::<Token>(&forged).unwrap_err().kind() == ErrorKind::Storage(StorageError::DECRYPTION_FAILED));
+        assert_eq!(EncryptedStorage::read::<Token>(&legacy).unwrap().token, "old");
+    }
+}
@@ -0,0 +1,417 @@
+//! Embedded database for the data, which grows with every connection.
+//!
+//! Sessions, seen remote files and transfers are kept in a single SQLite database within
+//! [`FILES_DIR`], while small documents stay in the [`LocalStorage`](super::LocalStorage). The
+//! database is only accessed through typed repositories, so no SQL leaks out of this module.
+
+use std::{
+    path::Path, sync::{Arc, Mutex, MutexGuard}, time::{SystemTime, UNIX_EPOCH}
+};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use crate::sugar::{
+    conn::client::{EntryKind, Partition, RemoteEntry},
+    errors::{InternalError, StorageError, SugarError, SugarResult},
+};
+use super::FILES_DIR;
+
+/// Name of the database file within the files directory.
+const DATABASE_FILE: &str = "sugar.db";
+
+/// Schema upgrades. The version of the schema is the amount of applied upgrades.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE sessions (
+        id INTEGER PRIMARY KEY,
+        machine TEXT NOT NULL,
+        bridge_id INTEGER NOT NULL,
+        started_at INTEGER NOT NULL,
+        ended_at INTEGER,
+        error INTEGER
+    );
+    CREATE INDEX sessions_by_machine ON sessions (machine, started_at);
+
+    CREATE TABLE entries (
+        machine TEXT NOT NULL,
+        disk TEXT NOT NULL,
+        partition TEXT NOT NULL,
+        path TEXT NOT NULL,
+        kind INTEGER NOT NULL,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        PRIMARY KEY (machine, disk, partition, path)
+    );
+
+    CREATE TABLE transfers (
+        id INTEGER PRIMARY KEY,
+        machine TEXT NOT NULL,
+        disk TEXT NOT NULL,
+        partition TEXT NOT NULL,
+        path TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        bytes INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL,
+        error INTEGER
+    );
+    CREATE INDEX transfers_by_machine ON transfers (machine, finished_at);",
+];
+
+static DATABASE: Mutex<Option<Arc<Database>>> = Mutex::new(None);
+
+/// Returns the current time as seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+/// Connection with a target machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord {
+    pub id: i64,
+    /// Identifier of the machine, stable between connections.
+    pub machine: String,
+    pub bridge_id: u64,
+    /// Unix time in seconds.
+    pub started_at: u64,
+    /// Unix time in seconds. Not set while the session is running, or if the application died.
+    pub ended_at: Option<u64>,
+    /// Code of the error, which closed the session.
+    pub error: Option<u16>,
+}
+
+/// Remote file or directory, which was seen in some listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeenEntry {
+    pub machine: String,
+    pub entry: RemoteEntry,
+    /// Unix time in seconds.
+    pub first_seen: u64,
+    /// Unix time in seconds.
+    pub last_seen: u64,
+}
+
+/// Copy of a remote file to the local storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferRecord {
+    pub machine: String,
+    pub file: RemoteEntry,
+    /// Local path of the copy.
+    pub destination: String,
+    /// Amount of transferred bytes.
+    pub bytes: u64,
+    /// Unix time in seconds.
+    pub finished_at: u64,
+    /// Code of the error, which interrupted the transfer.
+    pub error: Option<u16>,
+}
+
+/// Embedded database with all repositories.
+pub struct Database {
+    conn: Mutex<Connection>,
+}
+
+impl Database {
+    /// Opens the database file, creating it if missing, and upgrades its schema.
+    pub fn open(path: &Path) -> SugarResult<Self> {
+        log::info!("Opening the database: {}", path.to_string_lossy());
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Opens a database, which only lives in memory.
+    pub fn open_in_memory() -> SugarResult<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    /// Returns the database within the files directory, opening it on the first call.
+    pub fn global() -> SugarResult<Arc<Self>> {
+        let mut global = DATABASE.lock().unwrap_or_else(|err| err.into_inner());
+
+        if let Some(db) = global.as_ref() {
+            return Ok(db.clone())
+        }
+
+        let dir = FILES_DIR.read().unwrap_or_else(|err| err.into_inner()).to_path_buf();
+        if dir.as_os_str().is_empty() {
+            return Err(StorageError::NOT_INITIALIZED.into())
+        }
+
+        let db = Arc::new(Self::open(&dir.join(DATABASE_FILE))?);
+        global.replace(db.clone());
+        Ok(db)
+    }
+
+    /// Runs the function on the global database without blocking the async runtime.
+    pub async fn run<R, F>(f: F) -> SugarResult<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Database) -> SugarResult<R> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(move || f(&*Self::global()?)).await
+            .map_err(|err| SugarError::new(InternalError::TOKIO_THREAD_ERROR).with_source(err))?
+    }
+
+    pub fn sessions(&self) -> Sessions<'_> {
+        Sessions(self)
+    }
+
+    pub fn listings(&self) -> Listings<'_> {
+        Listings(self)
+    }
+
+    pub fn transfers(&self) -> Transfers<'_> {
+        Transfers(self)
+    }
+
+    fn init(mut conn: Connection) -> SugarResult<Self> {
+        // The default rollback journal fsyncs way more than needed for this kind of data.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            log::error!("Database has schema version {}, while only {} is supported.", version, MIGRATIONS.len());
+            return Err(StorageError::UNSUPPORTED_SCHEMA.into())
+        }
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            log::info!("Upgrading the database schema to version {}.", i + 1);
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Repository of sessions with target machines.
+pub struct Sessions<'db>(&'db Database);
+
+impl Sessions<'_> {
+    /// Records the start of a new session and returns its ID.
+    pub fn start(&self, machine: &str, bridge_id: u64) -> SugarResult<i64> {
+        let conn = self.0.conn();
+        conn.execute(
+            "INSERT INTO sessions (machine, bridge_id, started_at) VALUES (?1, ?2, ?3)",
+            params![machine, bridge_id as i64, now() as i64],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Records the end of the session along with the error, which closed it.
+    pub fn finish(&self, id: i64, error: Option<&SugarError>) -> SugarResult<()> {
+        let updated = self.0.conn().execute(
+            "UPDATE sessions SET ended_at = ?2, error = ?3 WHERE id = ?1",
+            params![id, now() as i64, error.map(SugarError::code)],
+        )?;
+
+        match updated {
+            0 => Err(StorageError::NO_DATA.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the session by its ID.
+    pub fn get(&self, id: i64) -> SugarResult<Option<SessionRecord>> {
+        Ok(self.0.conn()
+            .query_row("SELECT * FROM sessions WHERE id = ?1", [id], Self::from_row)
+            .optional()?)
+    }
+
+    /// Returns all sessions with the machine, the latest first.
+    pub fn for_machine(&self, machine: &str) -> SugarResult<Vec<SessionRecord>> {
+        let conn = self.0.conn();
+        let mut stmt = conn.prepare_cached("SELECT * FROM sessions WHERE machine = ?1 ORDER BY started_at DESC, id DESC")?;
+        let rows = stmt.query_map([machine], Self::from_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns the latest sessions with any machine, the latest first.
+    pub fn recent(&self, limit: usize) -> SugarResult<Vec<SessionRecord>> {
+        let conn = self.0.conn();
+        let mut stmt = conn.prepare_cached("SELECT * FROM sessions ORDER BY started_at DESC, id DESC LIMIT ?1")?;
+        let rows = stmt.query_map([limit as i64], Self::from_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<SessionRecord> {
+        Ok(SessionRecord {
+            id: row.get("id")?,
+            machine: row.get("machine")?,
+            bridge_id: row.get::<_, i64>("bridge_id")? as u64,
+            started_at: row.get("started_at")?,
+            ended_at: row.get("ended_at")?,
+            error: row.get("error")?,
+        })
+    }
+}
+
+/// Repository of remote files, which were seen in listings.
+pub struct Listings<'db>(&'db Database);
+
+impl Listings<'_> {
+    /// Records all entries of the listing. Already known entries are only marked as seen again.
+    ///
+    /// Returns the amount of entries, which were never seen before.
+    pub fn record(&self, machine: &str, entries: &[RemoteEntry]) -> SugarResult<usize> {
+        let mut conn = self.0.conn();
+        let tx = conn.transaction()?;
+        let now = now() as i64;
+        let mut new = 0;
+
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO entries (machine, disk, partition, path, kind, first_seen, last_seen)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6) ON CONFLICT DO NOTHING",
+            )?;
+            let mut touch = tx.prepare_cached(
+                "UPDATE entries SET kind = ?5, last_seen = ?6
+                WHERE machine = ?1 AND disk = ?2 AND partition = ?3 AND path = ?4",
+            )?;
+
+            for entry in entries {
+                let params = params![machine, entry.partition.disk, entry.partition.name, entry.path, kind_code(entry.kind), now];
+                match insert.execute(params)? {
+                    0 => { touch.execute(params)?; },
+                    _ => new += 1,
+                }
+            }
+        }
+
+        tx.commit()?;
+        Ok(new)
+    }
+
+    /// Returns all entries seen on the partition of the machine, sorted by path.
+    pub fn seen_on(&self, machine: &str, partition: &Partition) -> SugarResult<Vec<SeenEntry>> {
+        let conn = self.0.conn();
+        let mut stmt = conn.prepare_cached(
+            "SELECT * FROM entries WHERE machine = ?1 AND disk = ?2 AND partition = ?3 ORDER BY path",
+        )?;
+        let rows = stmt.query_map(params![machine, partition.disk, partition.name], |row| {
+            Ok(SeenEntry {
+                machine: row.get("machine")?,
+                entry: entry_from_row(row)?,
+                first_seen: row.get("first_seen")?,
+                last_seen: row.get("last_seen")?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+/// Repository of finished and failed transfers.
+pub struct Transfers<'db>(&'db Database);
+
+impl Transfers<'_> {
+    /// Records the transfer and returns its ID.
+    pub fn record(&self, transfer: &TransferRecord) -> SugarResult<i64> {
+        let conn = self.0.conn();
+        conn.execute(
+            "INSERT INTO transfers (machine, disk, partition, path, destination, bytes, finished_at, error)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                transfer.machine, transfer.file.partition.disk, transfer.file.partition.name, transfer.file.path,
+                transfer.destination, transfer.bytes as i64, transfer.finished_at as i64, transfer.error,
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Returns up to `limit` latest transfers from the machine, the latest first.
+    pub fn for_machine(&self, machine: &str, limit: usize) -> SugarResult<Vec<TransferRecord>> {
+        let conn = self.0.conn();
+        let mut stmt = conn.prepare_cached(
+            "SELECT * FROM transfers WHERE machine = ?1 ORDER BY finished_at DESC, id DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![machine, limit as i64], |row| {
+            Ok(TransferRecord {
+                machine: row.get("machine")?,
+                file: entry_from_row(row)?,
+                destination: row.get("destination")?,
+                bytes: row.get::<_, i64>("bytes")? as u64,
+                finished_at: row.get("finished_at")?,
+                error: row.get("error")?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+fn kind_code(kind: EntryKind) -> u8 {
+    match kind {
+        EntryKind::File => 0,
+        EntryKind::Directory => 1,
+    }
+}
+
+/// Reads the remote entry from the row. Rows without the kind are files.
+fn entry_from_row(row: &Row) -> rusqlite::Result<RemoteEntry> {
+    let kind = match row.get::<_, u8>("kind") {
+        Ok(1) => EntryKind::Directory,
+        _ => EntryKind::File,
+    };
+
+    Ok(RemoteEntry {
+        partition: Partition { disk: row.get("disk")?, name: row.get("partition")? },
+        path: row.get("path")?,
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sugar::errors::ErrorKind;
+
+    fn entry(path: &str, kind: EntryKind) -> RemoteEntry {
+        RemoteEntry { partition: Partition { disk: "sda".into(), name: "sda1".into() }, path: path.into(), kind }
+    }
+
+    #[test]
+    fn sessions_for_machine_latest_first() {
+        let db = Database::open_in_memory().unwrap();
+        let sessions = db.sessions();
+
+        let first = sessions.start("m1", 1).unwrap();
+        let second = sessions.start("m1", 2).unwrap();
+        let third = sessions.start("m1", 3).unwrap();
+        sessions.start("m2", 4).unwrap();
+        // The start time is ordered first, the ID only breaks ties within one second.
+        db.conn().execute("UPDATE sessions SET started_at = started_at + 10 WHERE id = ?1", [first]).unwrap();
+
+        let ids: Vec<i64> = sessions.for_machine("m1").unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![first, third, second]);
+        assert!(sessions.for_machine("m3").unwrap().is_empty());
+
+        sessions.finish(second, Some(&SugarError::new(StorageError::NO_DATA))).unwrap();
+        let record = sessions.get(second).unwrap().unwrap();
+        assert_eq!(record.error, Some(StorageError::NO_DATA as u16));
+        assert!(record.ended_at.is_some());
+    }
+
+    #[test]
+    fn listings_count_new_entries() {
+        let db = Database::open_in_memory().unwrap();
+        let listings = db.listings();
+        let partition = Partition { disk: "sda".into(), name: "sda1".into() };
+
+        assert_eq!(listings.record("m1", &[entry("a", EntryKind::File), entry("b", EntryKind::File)]).unwrap(), 2);
+        // Re-seen entries are not counted, but their kind is updated.
+        assert_eq!(listings.record("m1", &[entry("b", EntryKind::Directory), entry("c", EntryKind::File)]).unwrap(), 1);
+        assert_eq!(listings.record("m2", &[entry("a", EntryKind::File)]).unwrap(), 1);
+
+        let seen: Vec<RemoteEntry> = listings.seen_on("m1", &partition).unwrap().into_iter().map(|s| s.entry).collect();
+        assert_eq!(seen, vec![entry("a", EntryKind::File), entry("b", EntryKind::Directory), entry("c", EntryKind::File)]);
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+
+        let err = Database::init(conn).err().unwrap();
+        assert!(err.kind() == ErrorKind::Storage(StorageError::UNSUPPORTED_SCHEMA));
+    }
+}